@@ -1,5 +1,6 @@
 use super::math::*;
 
+#[derive(Debug, Clone)]
 pub struct Camera {
     pub translation: Vec3,
     pub rotation: Vec3,
@@ -13,3 +14,102 @@ impl Camera {
             .mat_mul(&self.translation.mul(-1.0).translation())
     }
 }
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum CameraMode {
+    /// Circle around a target point at a fixed distance.
+    Orbit,
+    /// Move freely in the direction the camera is facing.
+    Fly,
+}
+
+/// Drives a `Camera` from user input, either orbiting a target or flying around freely.
+#[derive(Debug, Clone)]
+pub struct CameraController {
+    pub mode: CameraMode,
+    pub target: Vec3,
+    pub distance: f32,
+    pub position: Vec3,
+    pub yaw: f32,
+    pub pitch: f32,
+}
+
+impl CameraController {
+    pub fn orbit(target: Vec3, distance: f32, yaw: f32, pitch: f32) -> Self {
+        let mut controller = CameraController {
+            mode: CameraMode::Orbit,
+            target,
+            distance,
+            position: target,
+            yaw,
+            pitch,
+        };
+        controller.position = controller.orbit_position();
+        controller
+    }
+
+    /// The direction the camera looks in, in world space.
+    pub fn forward(&self) -> Vec3 {
+        Vec3::new(
+            -self.yaw.sin() * self.pitch.cos(),
+            -self.pitch.sin(),
+            self.yaw.cos() * self.pitch.cos(),
+        )
+    }
+
+    /// The direction to the camera's right, parallel to the ground.
+    pub fn right(&self) -> Vec3 {
+        Vec3::new(self.yaw.cos(), 0.0, self.yaw.sin())
+    }
+
+    pub fn rotate(&mut self, yaw: f32, pitch: f32) {
+        let limit = std::f32::consts::FRAC_PI_2 - 0.01;
+        self.yaw += yaw;
+        self.pitch = clamp((-limit, limit), self.pitch + pitch);
+        self.update();
+    }
+
+    /// Moves the camera relative to where it's facing. In orbit mode, moving
+    /// forward and backward changes the distance to the target.
+    pub fn travel(&mut self, forward: f32, right: f32) {
+        match self.mode {
+            CameraMode::Orbit => {
+                self.distance = (self.distance - forward).max(0.1);
+                self.target = self.target.add(&self.right().mul(right));
+            }
+            CameraMode::Fly => {
+                self.position = self
+                    .position
+                    .add(&self.forward().mul(forward))
+                    .add(&self.right().mul(right));
+            }
+        }
+        self.update();
+    }
+
+    pub fn toggle_mode(&mut self) {
+        self.mode = match self.mode {
+            CameraMode::Orbit => CameraMode::Fly,
+            CameraMode::Fly => {
+                // Keep looking at the same spot, just orbit around it from now on.
+                self.target = self.position.add(&self.forward().mul(self.distance));
+                CameraMode::Orbit
+            }
+        };
+    }
+
+    pub fn apply(&self, camera: &mut Camera) {
+        camera.translation = self.position;
+        camera.rotation = Vec3::new(self.pitch, self.yaw, 0.0);
+    }
+
+    fn orbit_position(&self) -> Vec3 {
+        self.target.sub(&self.forward().mul(self.distance))
+    }
+
+    fn update(&mut self) {
+        if self.mode == CameraMode::Orbit {
+            self.position = self.orbit_position();
+        }
+    }
+}
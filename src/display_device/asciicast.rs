@@ -0,0 +1,245 @@
+use super::*;
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+
+/// Wraps another display device and records every frame, as `ConsoleDisplay` would draw it,
+/// to an asciinema v2 `.cast` file.
+pub struct AsciicastRecorder<D: DisplayDevice, W: Write> {
+    inner: D,
+    encoder: ConsoleDisplay<std::io::Sink>,
+    out: W,
+    start: Instant,
+    started: bool,
+    // Only the first viewport is recorded; a recording can only show a single terminal.
+    recorded_viewport: Option<usize>,
+    frame: Vec<u8>,
+    event: Vec<u8>,
+}
+
+impl<D: DisplayDevice, W: Write> AsciicastRecorder<D, W> {
+    pub fn new(inner: D, out: W, rgb: bool) -> Self {
+        AsciicastRecorder {
+            inner,
+            encoder: ConsoleDisplay::new(std::io::sink(), rgb),
+            out,
+            start: Instant::now(),
+            started: false,
+            recorded_viewport: None,
+            frame: vec![],
+            event: vec![],
+        }
+    }
+
+    pub fn into_inner(self) -> (D, W) {
+        (self.inner, self.out)
+    }
+
+    fn record(&mut self, rt: &RenderTarget) -> std::io::Result<()> {
+        if !std::mem::replace(&mut self.started, true) {
+            // The header has to state the terminal size, which we only know once we see a frame.
+            // Every pixel is two characters wide.
+            let timestamp = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|time| time.as_secs())
+                .unwrap_or(0);
+            writeln!(
+                self.event,
+                "{{\"version\": 2, \"width\": {}, \"height\": {}, \"timestamp\": {}, \"env\": {{\"TERM\": \"xterm-256color\"}}}}",
+                rt.width * 2,
+                rt.height,
+                timestamp
+            )?;
+
+            self.frame.extend_from_slice(terminal::ENTER_SCREEN);
+        }
+
+        self.frame.extend_from_slice(b"\x1b[H");
+        self.encoder.encode_frame(&mut self.frame, rt);
+
+        let frame = std::mem::take(&mut self.frame);
+        let result = self.write_event(&frame);
+        self.frame = frame;
+        self.frame.clear();
+        result
+    }
+
+    /// Writes an event showing the given output, along with the header if it's still pending.
+    fn write_event(&mut self, output: &[u8]) -> std::io::Result<()> {
+        write!(
+            self.event,
+            "[{:.6}, \"o\", ",
+            self.start.elapsed().as_secs_f64()
+        )?;
+        // Frames are built from ASCII escape sequences and block characters, so they're always UTF-8.
+        write_terminal_output(&mut self.event, std::str::from_utf8(output).unwrap());
+        self.event.extend_from_slice(b"]\n");
+
+        let result = self
+            .out
+            .write_all(&self.event)
+            .and_then(|_| self.out.flush());
+        self.event.clear();
+        result
+    }
+}
+
+/// Writes output destined for the terminal as a JSON string.
+fn write_terminal_output(buffer: &mut Vec<u8>, string: &str) {
+    buffer.push(b'"');
+    for c in string.chars() {
+        match c {
+            '"' => buffer.extend_from_slice(b"\\\""),
+            '\\' => buffer.extend_from_slice(b"\\\\"),
+            // The terminal driver turns line feeds into CRLF on the way out, so the recording must too.
+            '\n' => buffer.extend_from_slice(b"\\r\\n"),
+            '\r' => buffer.extend_from_slice(b"\\r"),
+            c if (c as u32) < 0x20 => write!(buffer, "\\u{:04x}", c as u32).unwrap(),
+            c => {
+                let mut utf8 = [0; 4];
+                buffer.extend_from_slice(c.encode_utf8(&mut utf8).as_bytes());
+            }
+        }
+    }
+    buffer.push(b'"');
+}
+
+impl<D: DisplayDevice, W: Write> DisplayDevice for AsciicastRecorder<D, W> {
+    fn capabilities(&self) -> Capabilities {
+        self.inner.capabilities()
+    }
+
+    fn begin(&mut self) -> Result<(), Error> {
+        self.inner.begin()
+    }
+
+    fn viewports(&mut self) -> Vec<Viewport> {
+        let viewports = self.inner.viewports();
+        self.recorded_viewport = viewports.first().map(|viewport| viewport.id);
+        viewports
+    }
+
+    fn present(&mut self, viewport: usize, rt: &RenderTarget) -> Result<(), Error> {
+        self.inner.present(viewport, rt)?;
+
+        if self.recorded_viewport == Some(viewport) {
+            self.record(rt)?;
+        }
+
+        Ok(())
+    }
+
+    fn end(&mut self) -> Result<(), Error> {
+        self.inner.end()?;
+
+        // Leave the recording the way the terminal would be left, rather than stuck on the last frame.
+        if std::mem::replace(&mut self.started, false) {
+            self.write_event(terminal::LEAVE_SCREEN)?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::Value;
+
+    /// A device with two viewports that doesn't show anything.
+    struct Viewports;
+
+    impl DisplayDevice for Viewports {
+        fn capabilities(&self) -> Capabilities {
+            Capabilities {
+                color_depth: ColorDepth::Monochrome,
+                pixels_per_cell: (0.5, 1.0),
+                input: false,
+            }
+        }
+
+        fn viewports(&mut self) -> Vec<Viewport> {
+            [3, 4]
+                .iter()
+                .map(|&id| Viewport {
+                    id,
+                    dimensions: (2, 2),
+                    pixel_aspect: 1.0,
+                })
+                .collect()
+        }
+
+        fn present(&mut self, _viewport: usize, _rt: &RenderTarget) -> Result<(), Error> {
+            Ok(())
+        }
+    }
+
+    fn output(event: &Value) -> &str {
+        assert_eq!(event[1], "o");
+        event[2].as_str().unwrap()
+    }
+
+    #[test]
+    fn terminal_output_is_escaped_for_json() {
+        let output = "\x1b[H\"quoted\" \\ █\x07\r\n";
+        let mut buffer = vec![];
+        write_terminal_output(&mut buffer, output);
+
+        let json = std::str::from_utf8(&buffer).unwrap();
+        assert_eq!(json, "\"\\u001b[H\\\"quoted\\\" \\\\ █\\u0007\\r\\r\\n\"");
+        let parsed: String = serde_json::from_str(json).unwrap();
+        assert_eq!(parsed, output.replace('\n', "\r\n"));
+    }
+
+    #[test]
+    fn records_the_first_viewport_until_the_end() {
+        let mut rt = RenderTarget::new((2, 2));
+        rt.clear(&Vec4::new(0.0, 0.0, 0.0, 1.0));
+        rt.set_pixel(0, 0, &Vec4::new(1.0, 1.0, 1.0, 1.0));
+        rt.set_pixel(1, 1, &Vec4::new(1.0, 1.0, 1.0, 1.0));
+
+        let mut recorder = AsciicastRecorder::new(Viewports, vec![], false);
+        recorder.begin().unwrap();
+        for _ in 0..2 {
+            for viewport in recorder.viewports() {
+                recorder.present(viewport.id, &rt).unwrap();
+            }
+        }
+        recorder.end().unwrap();
+
+        let (_, out) = recorder.into_inner();
+        let lines: Vec<Value> = std::str::from_utf8(&out)
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(lines.len(), 4);
+
+        let header = &lines[0];
+        assert_eq!(header["version"], 2);
+        assert_eq!(header["width"], 4);
+        assert_eq!(header["height"], 2);
+        assert!(header["timestamp"].as_u64().unwrap() > 0);
+        assert_eq!(header["env"]["TERM"], "xterm-256color");
+
+        let events = &lines[1..];
+        let times: Vec<f64> = events
+            .iter()
+            .map(|event| event[0].as_f64().unwrap())
+            .collect();
+        assert!(times[0] >= 0.0);
+        assert!(times.windows(2).all(|pair| pair[0] <= pair[1]));
+
+        let frame = "\x1b[H██  \r\n  ██";
+        let enter = std::str::from_utf8(terminal::ENTER_SCREEN).unwrap();
+        assert_eq!(output(&events[0]), format!("{}{}", enter, frame));
+        assert_eq!(output(&events[1]), frame);
+        assert_eq!(output(&events[2]).as_bytes(), terminal::LEAVE_SCREEN);
+    }
+
+    #[test]
+    fn ending_before_any_frame_records_nothing() {
+        let mut recorder = AsciicastRecorder::new(Viewports, vec![], false);
+        recorder.begin().unwrap();
+        recorder.end().unwrap();
+        assert!(recorder.into_inner().1.is_empty());
+    }
+}
@@ -0,0 +1,182 @@
+use super::*;
+
+/// The kitty protocol limits each escape sequence to this much base64 payload.
+const CHUNK_SIZE: usize = 4096;
+
+/// Sends frames as RGB images using the kitty terminal graphics protocol.
+pub struct KittyDisplay<W: Write> {
+    /// How many terminal pixels each render target pixel is blown up to.
+    pub scale: usize,
+    /// Size of a character cell in terminal pixels, used to work out the resolution
+    /// when the terminal doesn't report it.
+    pub cell_size: (usize, usize),
+    output: TerminalOutput<W>,
+    frame: Vec<u8>,
+}
+
+impl KittyDisplay<std::io::Stdout> {
+    pub fn stdout() -> Self {
+        KittyDisplay::with_output(TerminalOutput::new(std::io::stdout(), true))
+    }
+}
+
+impl<W: Write> KittyDisplay<W> {
+    pub fn new(out: W) -> Self {
+        KittyDisplay::with_output(TerminalOutput::new(out, false))
+    }
+
+    fn with_output(output: TerminalOutput<W>) -> Self {
+        KittyDisplay {
+            scale: 2,
+            cell_size: (8, 16),
+            output,
+            frame: vec![],
+        }
+    }
+
+    pub fn get_ref(&self) -> &W {
+        &self.output.out
+    }
+
+    fn cell_size(&self) -> (usize, usize) {
+        self.output
+            .window_size
+            .and_then(|size| size.cell_size())
+            .unwrap_or(self.cell_size)
+    }
+
+    /// Appends the graphics protocol escape sequences that transmit and display `rt` to `buffer`.
+    pub fn encode_frame(&self, buffer: &mut Vec<u8>, rt: &RenderTarget) {
+        let scale = self.scale.max(1);
+        let width = rt.width * scale;
+        let height = rt.height * scale;
+
+        let mut pixels = Vec::with_capacity(width * height * 3);
+        for y in 0..height {
+            for x in 0..width {
+                let (r, g, b) = to_rgb8(&rt.get_pixel(x / scale, y / scale));
+                pixels.extend_from_slice(&[r, g, b]);
+            }
+        }
+
+        let payload = base64::encode(&pixels);
+        let chunks: Vec<&[u8]> = payload.as_bytes().chunks(CHUNK_SIZE).collect();
+
+        for (i, chunk) in chunks.iter().enumerate() {
+            let more = if i + 1 < chunks.len() { 1 } else { 0 };
+
+            if i == 0 {
+                // Reusing the same image ID replaces the previous frame instead of stacking
+                // images; q=2 keeps the terminal from answering on stdin, C=1 from moving the cursor.
+                write!(
+                    buffer,
+                    "\x1b_Ga=T,i=1,f=24,s={},v={},q=2,C=1,m={};",
+                    width, height, more
+                )
+                .unwrap();
+            } else {
+                write!(buffer, "\x1b_Gm={};", more).unwrap();
+            }
+
+            buffer.extend_from_slice(chunk);
+            buffer.extend_from_slice(b"\x1b\\");
+        }
+    }
+}
+
+impl<W: Write> DisplayDevice for KittyDisplay<W> {
+    fn capabilities(&self) -> Capabilities {
+        let scale = self.scale.max(1) as f32;
+        let (cell_width, cell_height) = self.cell_size();
+
+        Capabilities {
+            color_depth: ColorDepth::TrueColor,
+            pixels_per_cell: (cell_width as f32 / scale, cell_height as f32 / scale),
+            input: self.output.is_terminal,
+        }
+    }
+
+    fn begin(&mut self) -> Result<(), Error> {
+        Ok(self.output.begin()?)
+    }
+
+    fn viewports(&mut self) -> Vec<Viewport> {
+        let scale = self.scale.max(1);
+        let (columns, rows) = self
+            .output
+            .window_size()
+            .map(|size| (size.columns, size.rows))
+            .unwrap_or(DEFAULT_WINDOW_SIZE);
+        let (cell_width, cell_height) = self.cell_size();
+
+        vec![Viewport {
+            id: 0,
+            dimensions: (
+                columns * cell_width / scale,
+                rows.saturating_sub(1) * cell_height / scale,
+            ),
+            pixel_aspect: 1.0,
+        }]
+    }
+
+    fn present(&mut self, _viewport: usize, rt: &RenderTarget) -> Result<(), Error> {
+        let mut frame = std::mem::take(&mut self.frame);
+        frame.extend_from_slice(b"\x1b[H");
+        self.encode_frame(&mut frame, rt);
+
+        let result = self.output.write_frame(&frame);
+        frame.clear();
+        self.frame = frame;
+        Ok(result?)
+    }
+
+    fn end(&mut self) -> Result<(), Error> {
+        Ok(self.output.end()?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn encode(rt: &RenderTarget) -> String {
+        let mut display = KittyDisplay::new(vec![]);
+        display.scale = 1;
+        let mut buffer = vec![];
+        display.encode_frame(&mut buffer, rt);
+        String::from_utf8(buffer).unwrap()
+    }
+
+    #[test]
+    fn encodes_a_single_chunk() {
+        let mut rt = RenderTarget::new((1, 1));
+        rt.clear(&Vec4::new(1.0, 0.0, 0.0, 1.0));
+
+        assert_eq!(
+            encode(&rt),
+            "\x1b_Ga=T,i=1,f=24,s=1,v=1,q=2,C=1,m=0;/wAA\x1b\\"
+        );
+    }
+
+    #[test]
+    fn splits_large_frames_into_chunks() {
+        // Every pixel takes four base64 characters, so this is just over one chunk.
+        let mut rt = RenderTarget::new((1025, 1));
+        rt.clear(&Vec4::new(0.0, 1.0, 0.0, 1.0));
+        let output = encode(&rt);
+
+        let sequences: Vec<&str> = output
+            .split_terminator("\x1b\\")
+            .map(|sequence| sequence.strip_prefix("\x1b_G").unwrap())
+            .collect();
+        assert_eq!(sequences.len(), 2);
+
+        let (control, first) = sequences[0].split_at(sequences[0].find(';').unwrap() + 1);
+        assert_eq!(control, "a=T,i=1,f=24,s=1025,v=1,q=2,C=1,m=1;");
+        assert_eq!(first.len(), CHUNK_SIZE);
+        let last = sequences[1].strip_prefix("m=0;").unwrap();
+
+        let pixels = base64::decode([first, last].concat()).unwrap();
+        assert_eq!(pixels, [0, 255, 0].repeat(1025));
+    }
+}
@@ -0,0 +1,350 @@
+use super::math::*;
+use super::render_target::RenderTarget;
+use super::terminal;
+use failure::Error;
+use std::io::Write;
+
+mod asciicast;
+mod kitty;
+mod sixel;
+mod telnet;
+
+pub use asciicast::AsciicastRecorder;
+pub use kitty::KittyDisplay;
+pub use sixel::SixelDisplay;
+pub use telnet::TelnetServer;
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum ColorDepth {
+    /// Pixels are either on or off.
+    Monochrome,
+    /// A palette with this many colors.
+    Indexed(usize),
+    /// 24-bit RGB.
+    TrueColor,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Capabilities {
+    pub color_depth: ColorDepth,
+    /// How many render target pixels fit in one character cell, horizontally and vertically.
+    pub pixels_per_cell: (f32, f32),
+    /// Whether key presses and mouse events can be read from the terminal the device draws on.
+    pub input: bool,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Viewport {
+    /// Identifies the viewport when presenting a frame for it.
+    pub id: usize,
+    pub dimensions: (usize, usize),
+    /// Width divided by height of a single pixel as it appears on the device,
+    /// which the projection has to correct for to keep objects in proportion.
+    pub pixel_aspect: f32,
+}
+
+pub trait DisplayDevice {
+    fn capabilities(&self) -> Capabilities;
+
+    /// Gets the device ready to show frames. Called once, before anything else.
+    fn begin(&mut self) -> Result<(), Error> {
+        Ok(())
+    }
+
+    /// The frames the device wants for the next update. Most devices have a single viewport,
+    /// but e.g. a server has one for every client.
+    fn viewports(&mut self) -> Vec<Viewport>;
+
+    /// Shows a frame rendered for the viewport with the given ID.
+    fn present(&mut self, viewport: usize, rt: &RenderTarget) -> Result<(), Error>;
+
+    /// Puts the device back the way `begin` found it.
+    fn end(&mut self) -> Result<(), Error> {
+        Ok(())
+    }
+}
+
+impl<D: DisplayDevice + ?Sized> DisplayDevice for Box<D> {
+    fn capabilities(&self) -> Capabilities {
+        (**self).capabilities()
+    }
+
+    fn begin(&mut self) -> Result<(), Error> {
+        (**self).begin()
+    }
+
+    fn viewports(&mut self) -> Vec<Viewport> {
+        (**self).viewports()
+    }
+
+    fn present(&mut self, viewport: usize, rt: &RenderTarget) -> Result<(), Error> {
+        (**self).present(viewport, rt)
+    }
+
+    fn end(&mut self) -> Result<(), Error> {
+        (**self).end()
+    }
+}
+
+/// Output going to a terminal, or something that records what a terminal would get.
+/// Shared by the devices that draw using escape sequences.
+struct TerminalOutput<W: Write> {
+    out: W,
+    // Whether `out` is the process' own terminal, which the panic hook has to clean up.
+    is_terminal: bool,
+    active: bool,
+    window_size: Option<terminal::WindowSize>,
+}
+
+impl<W: Write> TerminalOutput<W> {
+    fn new(out: W, is_terminal: bool) -> Self {
+        TerminalOutput {
+            out,
+            is_terminal,
+            active: false,
+            window_size: None,
+        }
+    }
+
+    fn begin(&mut self) -> std::io::Result<()> {
+        self.write_frame(terminal::ENTER_SCREEN)?;
+        self.active = true;
+
+        if self.is_terminal {
+            terminal::set_screen_active(true);
+            terminal::watch_resize();
+        }
+
+        Ok(())
+    }
+
+    fn end(&mut self) -> std::io::Result<()> {
+        if !std::mem::replace(&mut self.active, false) {
+            return Ok(());
+        }
+
+        if self.is_terminal {
+            // The panic hook may already have restored the terminal, and leaving
+            // the alternate screen twice would clear the panic message.
+            terminal::restore();
+            Ok(())
+        } else {
+            self.write_frame(terminal::LEAVE_SCREEN)
+        }
+    }
+
+    /// The whole frame goes out in a single write, which keeps the terminal from
+    /// showing half-drawn frames and avoids locking stdout for every pixel.
+    fn write_frame(&mut self, frame: &[u8]) -> std::io::Result<()> {
+        self.out.write_all(frame)?;
+        self.out.flush()
+    }
+
+    /// The size of the terminal, only asked for again after it's been resized.
+    fn window_size(&mut self) -> Option<terminal::WindowSize> {
+        if self.window_size.is_none() || terminal::take_resized() {
+            self.window_size = terminal::window_size();
+        }
+        self.window_size
+    }
+}
+
+impl<W: Write> Drop for TerminalOutput<W> {
+    fn drop(&mut self) {
+        // Errors are ignored since this may run while unwinding, where a second panic would abort.
+        let _ = self.end();
+    }
+}
+
+/// Fallback for terminals that don't report their pixel size; most fonts are about twice as tall as wide.
+const DEFAULT_CELL_ASPECT: f32 = 0.5;
+
+/// Terminal size assumed when it can't be asked for, e.g. when output is redirected to a file.
+const DEFAULT_WINDOW_SIZE: (usize, usize) = (40, 10);
+
+pub struct ConsoleDisplay<W: Write> {
+    pub rgb: bool,
+    /// Width divided by height of a character cell. If not set, it's asked from the terminal.
+    pub cell_aspect: Option<f32>,
+    output: TerminalOutput<W>,
+    // Reused between frames so that we don't have to allocate a new buffer every time.
+    frame: Vec<u8>,
+}
+
+fn set_terminal_rgb(buffer: &mut Vec<u8>, (r, g, b): (u8, u8, u8)) {
+    // Writing to a Vec can't fail.
+    write!(buffer, "\x1b[48;2;{};{};{}m", r, g, b).unwrap();
+}
+
+fn to_rgb8(pixel: &Vec4) -> (u8, u8, u8) {
+    (
+        (pixel.x * 255.0).round() as u8,
+        (pixel.y * 255.0).round() as u8,
+        (pixel.z * 255.0).round() as u8,
+    )
+}
+
+impl ConsoleDisplay<std::io::Stdout> {
+    pub fn stdout(rgb: bool) -> Self {
+        ConsoleDisplay {
+            rgb,
+            cell_aspect: None,
+            output: TerminalOutput::new(std::io::stdout(), true),
+            frame: vec![],
+        }
+    }
+}
+
+impl<W: Write> ConsoleDisplay<W> {
+    pub fn new(out: W, rgb: bool) -> Self {
+        ConsoleDisplay {
+            rgb,
+            cell_aspect: None,
+            output: TerminalOutput::new(out, false),
+            frame: vec![],
+        }
+    }
+
+    pub fn get_ref(&self) -> &W {
+        &self.output.out
+    }
+
+    /// Appends the escape sequences and characters that draw `rt` to `buffer`.
+    pub fn encode_frame(&self, buffer: &mut Vec<u8>, rt: &RenderTarget) {
+        let threshold = Vec4::new(0.5, 0.5, 0.5, 1.0).length();
+
+        let mut rgb: (u8, u8, u8) = (0, 0, 0);
+
+        if self.rgb {
+            set_terminal_rgb(buffer, rgb);
+        }
+
+        for y in 0..rt.height {
+            for x in 0..rt.width {
+                let pixel = rt.get_pixel(x, y);
+                if self.rgb {
+                    let new_rgb = to_rgb8(&pixel);
+
+                    if new_rgb != rgb {
+                        rgb = new_rgb;
+                        set_terminal_rgb(buffer, rgb);
+                    }
+
+                    buffer.extend_from_slice(b"  ");
+                } else if pixel.length() > threshold {
+                    buffer.extend_from_slice("██".as_bytes());
+                } else {
+                    buffer.extend_from_slice(b"  ");
+                }
+            }
+
+            if y != rt.height - 1 {
+                buffer.push(b'\n');
+            } else if self.rgb {
+                buffer.extend_from_slice(b"\x1b[0m");
+            }
+        }
+    }
+}
+
+impl<W: Write> DisplayDevice for ConsoleDisplay<W> {
+    fn capabilities(&self) -> Capabilities {
+        Capabilities {
+            color_depth: if self.rgb {
+                ColorDepth::TrueColor
+            } else {
+                ColorDepth::Monochrome
+            },
+            // Every pixel is two characters wide.
+            pixels_per_cell: (0.5, 1.0),
+            input: self.output.is_terminal,
+        }
+    }
+
+    fn begin(&mut self) -> Result<(), Error> {
+        Ok(self.output.begin()?)
+    }
+
+    fn viewports(&mut self) -> Vec<Viewport> {
+        let size = self.output.window_size();
+        let (columns, rows) = size
+            .map(|size| (size.columns, size.rows))
+            .unwrap_or(DEFAULT_WINDOW_SIZE);
+        let cell_aspect = self
+            .cell_aspect
+            .or_else(|| size.and_then(|size| size.cell_aspect()))
+            .unwrap_or(DEFAULT_CELL_ASPECT);
+
+        vec![Viewport {
+            id: 0,
+            dimensions: (columns / 2, rows),
+            pixel_aspect: 2.0 * cell_aspect,
+        }]
+    }
+
+    fn present(&mut self, _viewport: usize, rt: &RenderTarget) -> Result<(), Error> {
+        let mut frame = std::mem::take(&mut self.frame);
+        frame.extend_from_slice(b"\x1b[H");
+        self.encode_frame(&mut frame, rt);
+
+        let result = self.output.write_frame(&frame);
+        frame.clear();
+        self.frame = frame;
+        Ok(result?)
+    }
+
+    fn end(&mut self) -> Result<(), Error> {
+        Ok(self.output.end()?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn checkerboard() -> RenderTarget {
+        let mut rt = RenderTarget::new((2, 2));
+        rt.clear(&Vec4::new(0.0, 0.0, 0.0, 1.0));
+        rt.set_pixel(0, 0, &Vec4::new(1.0, 1.0, 1.0, 1.0));
+        rt.set_pixel(1, 1, &Vec4::new(1.0, 0.0, 0.0, 1.0));
+        rt
+    }
+
+    fn capture(rgb: bool) -> Vec<u8> {
+        let mut display = ConsoleDisplay::new(vec![], rgb);
+        display.begin().unwrap();
+        display.present(0, &checkerboard()).unwrap();
+        display.end().unwrap();
+        display.get_ref().clone()
+    }
+
+    #[test]
+    fn monochrome_output() {
+        let expected = [
+            terminal::ENTER_SCREEN,
+            "\x1b[H██  \n  ██".as_bytes(),
+            terminal::LEAVE_SCREEN,
+        ]
+        .concat();
+        assert_eq!(capture(false), expected);
+    }
+
+    #[test]
+    fn rgb_output_only_changes_color_when_needed() {
+        let expected = [
+            terminal::ENTER_SCREEN,
+            b"\x1b[H\x1b[48;2;0;0;0m\x1b[48;2;255;255;255m  \x1b[48;2;0;0;0m  \n  ",
+            b"\x1b[48;2;255;0;0m  \x1b[0m",
+            terminal::LEAVE_SCREEN,
+        ]
+        .concat();
+        assert_eq!(capture(true), expected);
+    }
+
+    #[test]
+    fn end_without_begin_writes_nothing() {
+        let mut display = ConsoleDisplay::new(vec![], false);
+        display.end().unwrap();
+        assert!(display.get_ref().is_empty());
+    }
+}
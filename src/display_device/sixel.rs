@@ -0,0 +1,253 @@
+use super::*;
+
+/// Number of levels per channel in the fixed color cube that pixels are quantized to.
+const LEVELS: usize = 6;
+const PALETTE_SIZE: usize = LEVELS * LEVELS * LEVELS;
+
+/// Sends frames as DEC Sixel images, giving one terminal pixel (times `scale`)
+/// per render target pixel on terminals that support it.
+pub struct SixelDisplay<W: Write> {
+    /// How many terminal pixels each render target pixel is blown up to.
+    pub scale: usize,
+    /// Size of a character cell in terminal pixels, used to work out the resolution
+    /// when the terminal doesn't report it.
+    pub cell_size: (usize, usize),
+    output: TerminalOutput<W>,
+    frame: Vec<u8>,
+}
+
+impl SixelDisplay<std::io::Stdout> {
+    pub fn stdout() -> Self {
+        SixelDisplay::with_output(TerminalOutput::new(std::io::stdout(), true))
+    }
+}
+
+impl<W: Write> SixelDisplay<W> {
+    pub fn new(out: W) -> Self {
+        SixelDisplay::with_output(TerminalOutput::new(out, false))
+    }
+
+    fn with_output(output: TerminalOutput<W>) -> Self {
+        SixelDisplay {
+            scale: 2,
+            cell_size: (8, 16),
+            output,
+            frame: vec![],
+        }
+    }
+
+    pub fn get_ref(&self) -> &W {
+        &self.output.out
+    }
+
+    fn cell_size(&self) -> (usize, usize) {
+        self.output
+            .window_size
+            .and_then(|size| size.cell_size())
+            .unwrap_or(self.cell_size)
+    }
+
+    /// Appends the sixel data stream for `rt` to `buffer`.
+    pub fn encode_frame(&self, buffer: &mut Vec<u8>, rt: &RenderTarget) {
+        let scale = self.scale.max(1);
+        let width = rt.width * scale;
+        let height = rt.height * scale;
+
+        // Quantize the whole image up front; every band needs to look at the
+        // color indices several times.
+        let mut indices = Vec::with_capacity(width * height);
+        let mut used = [false; PALETTE_SIZE];
+        for y in 0..height {
+            for x in 0..width {
+                let index = palette_index(&rt.get_pixel(x / scale, y / scale));
+                used[index] = true;
+                indices.push(index);
+            }
+        }
+
+        // DCS introducer, where P1 = 9 selects a 1:1 pixel aspect ratio (0 would mean 2:1),
+        // followed by raster attributes that say 1:1 again for terminals that go by those.
+        write!(buffer, "\x1bP9;1;0q\"1;1;{};{}", width, height).unwrap();
+
+        for (index, _) in used.iter().enumerate().filter(|(_, &used)| used) {
+            let (r, g, b) = palette_color(index);
+            write!(buffer, "#{};2;{};{};{}", index, r, g, b).unwrap();
+        }
+
+        for band in (0..height).step_by(6) {
+            let rows = (height - band).min(6);
+
+            let mut in_band = [false; PALETTE_SIZE];
+            for &index in &indices[band * width..(band + rows) * width] {
+                in_band[index] = true;
+            }
+
+            let mut first = true;
+            for (color, _) in in_band.iter().enumerate().filter(|(_, &used)| used) {
+                if !first {
+                    // Graphics carriage return; overlay the next color on the same band.
+                    buffer.push(b'$');
+                }
+                first = false;
+
+                write!(buffer, "#{}", color).unwrap();
+
+                let mut run: Option<(u8, usize)> = None;
+                for x in 0..width {
+                    let mut bits = 0u8;
+                    for row in 0..rows {
+                        if indices[(band + row) * width + x] == color {
+                            bits |= 1 << row;
+                        }
+                    }
+
+                    let sixel = 63 + bits;
+                    run = match run {
+                        Some((previous, count)) if previous == sixel => Some((previous, count + 1)),
+                        Some((previous, count)) => {
+                            write_run(buffer, previous, count);
+                            Some((sixel, 1))
+                        }
+                        None => Some((sixel, 1)),
+                    };
+                }
+
+                if let Some((sixel, count)) = run {
+                    write_run(buffer, sixel, count);
+                }
+            }
+
+            // Graphics new line; move down to the next band.
+            buffer.push(b'-');
+        }
+
+        buffer.extend_from_slice(b"\x1b\\");
+    }
+}
+
+fn palette_index(pixel: &Vec4) -> usize {
+    let level = |c: f32| (clamp((0.0, 1.0), c) * (LEVELS - 1) as f32).round() as usize;
+    (level(pixel.x) * LEVELS + level(pixel.y)) * LEVELS + level(pixel.z)
+}
+
+/// Returns the color of a palette entry in percent, which is what sixel color registers use.
+fn palette_color(index: usize) -> (usize, usize, usize) {
+    let percent = |level: usize| level * 100 / (LEVELS - 1);
+    (
+        percent(index / (LEVELS * LEVELS)),
+        percent(index / LEVELS % LEVELS),
+        percent(index % LEVELS),
+    )
+}
+
+fn write_run(buffer: &mut Vec<u8>, sixel: u8, count: usize) {
+    if count > 3 {
+        write!(buffer, "!{}", count).unwrap();
+        buffer.push(sixel);
+    } else {
+        buffer.extend(std::iter::repeat_n(sixel, count));
+    }
+}
+
+impl<W: Write> DisplayDevice for SixelDisplay<W> {
+    fn capabilities(&self) -> Capabilities {
+        let scale = self.scale.max(1) as f32;
+        let (cell_width, cell_height) = self.cell_size();
+
+        Capabilities {
+            color_depth: ColorDepth::Indexed(PALETTE_SIZE),
+            pixels_per_cell: (cell_width as f32 / scale, cell_height as f32 / scale),
+            input: self.output.is_terminal,
+        }
+    }
+
+    fn begin(&mut self) -> Result<(), Error> {
+        Ok(self.output.begin()?)
+    }
+
+    fn viewports(&mut self) -> Vec<Viewport> {
+        let scale = self.scale.max(1);
+        let (columns, rows) = self
+            .output
+            .window_size()
+            .map(|size| (size.columns, size.rows))
+            .unwrap_or(DEFAULT_WINDOW_SIZE);
+        let (cell_width, cell_height) = self.cell_size();
+
+        vec![Viewport {
+            id: 0,
+            // Leave the last line empty, otherwise the terminal scrolls when the image reaches it.
+            dimensions: (
+                columns * cell_width / scale,
+                rows.saturating_sub(1) * cell_height / scale,
+            ),
+            pixel_aspect: 1.0,
+        }]
+    }
+
+    fn present(&mut self, _viewport: usize, rt: &RenderTarget) -> Result<(), Error> {
+        let mut frame = std::mem::take(&mut self.frame);
+        frame.extend_from_slice(b"\x1b[H");
+        self.encode_frame(&mut frame, rt);
+
+        let result = self.output.write_frame(&frame);
+        frame.clear();
+        self.frame = frame;
+        Ok(result?)
+    }
+
+    fn end(&mut self) -> Result<(), Error> {
+        Ok(self.output.end()?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn encode(rt: &RenderTarget) -> String {
+        let mut display = SixelDisplay::new(vec![]);
+        display.scale = 1;
+        let mut buffer = vec![];
+        display.encode_frame(&mut buffer, rt);
+        String::from_utf8(buffer).unwrap()
+    }
+
+    #[test]
+    fn encodes_colors_as_separate_passes_over_a_band() {
+        let mut rt = RenderTarget::new((2, 1));
+        rt.set_pixel(0, 0, &Vec4::new(0.0, 0.0, 0.0, 1.0));
+        rt.set_pixel(1, 0, &Vec4::new(1.0, 1.0, 1.0, 1.0));
+
+        assert_eq!(
+            encode(&rt),
+            "\x1bP9;1;0q\"1;1;2;1#0;2;0;0;0#215;2;100;100;100#0@?$#215?@-\x1b\\"
+        );
+    }
+
+    #[test]
+    fn compresses_runs_and_splits_bands_every_six_rows() {
+        let mut rt = RenderTarget::new((5, 7));
+        rt.clear(&Vec4::new(1.0, 0.0, 0.0, 1.0));
+
+        assert_eq!(
+            encode(&rt),
+            "\x1bP9;1;0q\"1;1;5;7#180;2;100;0;0#180!5~-#180!5@-\x1b\\"
+        );
+    }
+
+    #[test]
+    fn scales_pixels_up() {
+        let mut display = SixelDisplay::new(vec![]);
+        display.scale = 2;
+        let mut rt = RenderTarget::new((1, 1));
+        rt.clear(&Vec4::new(0.0, 0.0, 1.0, 1.0));
+        let mut buffer = vec![];
+        display.encode_frame(&mut buffer, &rt);
+
+        assert_eq!(
+            String::from_utf8(buffer).unwrap(),
+            "\x1bP9;1;0q\"1;1;2;2#5;2;0;0;100#5BB-\x1b\\"
+        );
+    }
+}
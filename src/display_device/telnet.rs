@@ -0,0 +1,363 @@
+use super::*;
+use std::io::Read;
+use std::net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
+use std::sync::{Arc, Mutex};
+
+const IAC: u8 = 255;
+const DONT: u8 = 254;
+const DO: u8 = 253;
+const WONT: u8 = 252;
+const WILL: u8 = 251;
+const SB: u8 = 250;
+const SE: u8 = 240;
+
+const ECHO: u8 = 1;
+const SUPPRESS_GO_AHEAD: u8 = 3;
+const NAWS: u8 = 31;
+
+/// Terminal size assumed for clients that don't negotiate one, like netcat.
+const DEFAULT_SIZE: (usize, usize) = (80, 24);
+
+/// Accepts telnet (or plain TCP) connections and streams ANSI-rendered frames to them.
+/// Each client reports its own terminal size, so frames are rendered separately per client.
+pub struct TelnetServer {
+    // Non-blocking, and polled for new clients along with the viewports, so that the port is
+    // freed as soon as the server is dropped.
+    listener: TcpListener,
+    local_addr: SocketAddr,
+    clients: Vec<Client>,
+    next_id: usize,
+    encoder: ConsoleDisplay<std::io::Sink>,
+    frame: Vec<u8>,
+}
+
+struct Client {
+    id: usize,
+    stream: TcpStream,
+    state: Arc<Mutex<ClientState>>,
+}
+
+struct ClientState {
+    /// Terminal size in character cells.
+    size: (usize, usize),
+    open: bool,
+}
+
+impl TelnetServer {
+    pub fn bind<A: ToSocketAddrs>(address: A, rgb: bool) -> std::io::Result<TelnetServer> {
+        let listener = TcpListener::bind(address)?;
+        listener.set_nonblocking(true)?;
+        let local_addr = listener.local_addr()?;
+
+        Ok(TelnetServer {
+            listener,
+            local_addr,
+            clients: vec![],
+            next_id: 0,
+            encoder: ConsoleDisplay::new(std::io::sink(), rgb),
+            frame: vec![],
+        })
+    }
+
+    pub fn local_addr(&self) -> SocketAddr {
+        self.local_addr
+    }
+
+    /// Connects everyone waiting to be accepted.
+    fn accept_clients(&mut self) {
+        // Stops at `WouldBlock` once nobody is left, but also at any other error, which would
+        // most likely just happen again.
+        while let Ok((stream, _)) = self.listener.accept() {
+            if let Ok(client) = Client::connect(self.next_id, stream) {
+                self.clients.push(client);
+            }
+            self.next_id += 1;
+        }
+    }
+
+    fn close_all(&mut self) {
+        for client in self.clients.drain(..) {
+            let _ = (&client.stream).write_all(terminal::LEAVE_SCREEN);
+            let _ = client.stream.shutdown(std::net::Shutdown::Both);
+        }
+    }
+}
+
+impl DisplayDevice for TelnetServer {
+    fn capabilities(&self) -> Capabilities {
+        Capabilities {
+            color_depth: if self.encoder.rgb {
+                ColorDepth::TrueColor
+            } else {
+                ColorDepth::Monochrome
+            },
+            // Every pixel is two characters wide.
+            pixels_per_cell: (0.5, 1.0),
+            // Key presses from clients aren't passed on; anyone could be connected.
+            input: false,
+        }
+    }
+
+    /// One viewport per connected client, with the client ID as the viewport ID.
+    fn viewports(&mut self) -> Vec<Viewport> {
+        self.accept_clients();
+        self.clients
+            .retain(|client| client.state.lock().unwrap().open);
+
+        self.clients
+            .iter()
+            .map(|client| {
+                let (columns, rows) = client.state.lock().unwrap().size;
+                Viewport {
+                    id: client.id,
+                    dimensions: (columns / 2, rows),
+                    // There's no way to ask a remote terminal about its font.
+                    pixel_aspect: 1.0,
+                }
+            })
+            .collect()
+    }
+
+    /// Sends a frame to a client. Clients that can't keep up are dropped rather than failing.
+    fn present(&mut self, viewport: usize, rt: &RenderTarget) -> Result<(), Error> {
+        self.frame.clear();
+        self.frame.extend_from_slice(b"\x1b[H");
+        self.encoder.encode_frame(&mut self.frame, rt);
+
+        // Telnet wants CRLF line endings; there's no terminal driver on the other end to add them.
+        let mut output = Vec::with_capacity(self.frame.len() + rt.height);
+        for &byte in self.frame.iter() {
+            if byte == b'\n' {
+                output.push(b'\r');
+            }
+            output.push(byte);
+        }
+
+        if let Some(client) = self.clients.iter().find(|client| client.id == viewport) {
+            if (&client.stream).write_all(&output).is_err() {
+                client.state.lock().unwrap().open = false;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn end(&mut self) -> Result<(), Error> {
+        self.close_all();
+        Ok(())
+    }
+}
+
+impl Drop for TelnetServer {
+    fn drop(&mut self) {
+        self.close_all();
+    }
+}
+
+impl Client {
+    fn connect(id: usize, stream: TcpStream) -> std::io::Result<Client> {
+        // Not every platform leaves accepted streams blocking when the listener isn't.
+        stream.set_nonblocking(false)?;
+        stream.set_nodelay(true)?;
+        // A client that stops reading would otherwise block the render loop.
+        stream.set_write_timeout(Some(std::time::Duration::from_secs(1)))?;
+
+        // Only ask for the window size for now; the rest of the negotiation waits until the
+        // client shows it speaks telnet. Plain TCP clients print the request as it is, but on
+        // the alternate screen, where the first frame draws over it.
+        (&stream).write_all(terminal::ENTER_SCREEN)?;
+        (&stream).write_all(&[IAC, DO, NAWS])?;
+
+        let state = Arc::new(Mutex::new(ClientState {
+            size: DEFAULT_SIZE,
+            open: true,
+        }));
+
+        let reader = stream.try_clone()?;
+        let reader_state = state.clone();
+        std::thread::spawn(move || read_client(reader, reader_state));
+
+        Ok(Client { id, stream, state })
+    }
+}
+
+enum TelnetState {
+    Data,
+    Command,
+    Negotiation,
+    Subnegotiation(Vec<u8>),
+    SubnegotiationCommand(Vec<u8>),
+}
+
+fn read_client(mut stream: TcpStream, state: Arc<Mutex<ClientState>>) {
+    let mut telnet = TelnetState::Data;
+    let mut negotiated = false;
+    let mut buffer = [0u8; 256];
+
+    loop {
+        let n = match stream.read(&mut buffer) {
+            Ok(0) | Err(_) => break,
+            Ok(n) => n,
+        };
+
+        for &byte in &buffer[..n] {
+            telnet = match (telnet, byte) {
+                (TelnetState::Data, IAC) => {
+                    if !negotiated {
+                        // Take over echoing so typed characters don't mess up the picture.
+                        negotiated = true;
+                        let _ =
+                            (&stream).write_all(&[IAC, WILL, ECHO, IAC, WILL, SUPPRESS_GO_AHEAD]);
+                    }
+                    TelnetState::Command
+                }
+                (TelnetState::Data, b'q') => {
+                    state.lock().unwrap().open = false;
+                    let _ = stream.shutdown(std::net::Shutdown::Both);
+                    return;
+                }
+                (TelnetState::Data, _) => TelnetState::Data,
+                // We don't care about the client's answers, only the window size.
+                (TelnetState::Command, WILL)
+                | (TelnetState::Command, WONT)
+                | (TelnetState::Command, DO)
+                | (TelnetState::Command, DONT) => TelnetState::Negotiation,
+                (TelnetState::Command, SB) => TelnetState::Subnegotiation(vec![]),
+                (TelnetState::Command, _) => TelnetState::Data,
+                (TelnetState::Negotiation, _) => TelnetState::Data,
+                (TelnetState::Subnegotiation(data), IAC) => {
+                    TelnetState::SubnegotiationCommand(data)
+                }
+                (TelnetState::Subnegotiation(mut data), _) => {
+                    data.push(byte);
+                    TelnetState::Subnegotiation(data)
+                }
+                // A doubled IAC is a literal 255 within the subnegotiation.
+                (TelnetState::SubnegotiationCommand(mut data), IAC) => {
+                    data.push(IAC);
+                    TelnetState::Subnegotiation(data)
+                }
+                (TelnetState::SubnegotiationCommand(data), SE) => {
+                    if let [NAWS, w0, w1, h0, h1] = data[..] {
+                        let columns = u16::from_be_bytes([w0, w1]) as usize;
+                        let rows = u16::from_be_bytes([h0, h1]) as usize;
+                        if columns > 0 && rows > 0 {
+                            state.lock().unwrap().size = (columns, rows);
+                        }
+                    }
+                    TelnetState::Data
+                }
+                (TelnetState::SubnegotiationCommand(_), _) => TelnetState::Data,
+            };
+        }
+    }
+
+    state.lock().unwrap().open = false;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::{Duration, Instant};
+
+    fn connect(server: &TelnetServer) -> TcpStream {
+        let stream = TcpStream::connect(server.local_addr()).unwrap();
+        stream
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        stream
+    }
+
+    fn read_exactly(stream: &mut TcpStream, length: usize) -> Vec<u8> {
+        let mut buffer = vec![0; length];
+        stream.read_exact(&mut buffer).unwrap();
+        buffer
+    }
+
+    /// Polls the server until its viewports are what `done` is waiting for.
+    fn wait_for_viewports(
+        server: &mut TelnetServer,
+        done: impl Fn(&[Viewport]) -> bool,
+    ) -> Vec<Viewport> {
+        let start = Instant::now();
+        loop {
+            let viewports = server.viewports();
+            if done(&viewports) {
+                return viewports;
+            }
+            assert!(start.elapsed() < Duration::from_secs(5), "{:?}", viewports);
+            std::thread::sleep(Duration::from_millis(5));
+        }
+    }
+
+    fn frame() -> RenderTarget {
+        let mut rt = RenderTarget::new((1, 2));
+        rt.clear(&Vec4::new(1.0, 1.0, 1.0, 1.0));
+        rt
+    }
+
+    #[test]
+    fn telnet_client_reports_its_size_and_receives_frames() {
+        let mut server = TelnetServer::bind("127.0.0.1:0", false).unwrap();
+        let mut client = connect(&server);
+        wait_for_viewports(&mut server, |viewports| viewports.len() == 1);
+
+        let greeting = [terminal::ENTER_SCREEN, &[IAC, DO, NAWS]].concat();
+        assert_eq!(read_exactly(&mut client, greeting.len()), greeting);
+
+        client
+            .write_all(&[IAC, WILL, NAWS, IAC, SB, NAWS, 0, 100, 0, 40, IAC, SE])
+            .unwrap();
+        assert_eq!(
+            read_exactly(&mut client, 6),
+            [IAC, WILL, ECHO, IAC, WILL, SUPPRESS_GO_AHEAD]
+        );
+
+        let viewports = wait_for_viewports(&mut server, |viewports| {
+            viewports.len() == 1 && viewports[0].dimensions != (40, 24)
+        });
+        assert_eq!(viewports[0].dimensions, (50, 40));
+
+        server.present(viewports[0].id, &frame()).unwrap();
+        let expected = "\x1b[H██\r\n██".as_bytes();
+        assert_eq!(read_exactly(&mut client, expected.len()), expected);
+
+        client.write_all(b"q").unwrap();
+        wait_for_viewports(&mut server, |viewports| viewports.is_empty());
+    }
+
+    #[test]
+    fn plain_tcp_client_only_gets_asked_for_its_size() {
+        let mut server = TelnetServer::bind("127.0.0.1:0", false).unwrap();
+        let mut client = connect(&server);
+
+        let viewports = wait_for_viewports(&mut server, |viewports| viewports.len() == 1);
+        assert_eq!(viewports[0].dimensions, (40, 24));
+
+        server.present(viewports[0].id, &frame()).unwrap();
+        server.end().unwrap();
+
+        let mut output = vec![];
+        client.read_to_end(&mut output).unwrap();
+        let expected = [
+            terminal::ENTER_SCREEN,
+            &[IAC, DO, NAWS],
+            "\x1b[H██\r\n██".as_bytes(),
+            terminal::LEAVE_SCREEN,
+        ]
+        .concat();
+        assert_eq!(output, expected);
+    }
+
+    #[test]
+    fn dropping_the_server_frees_its_port() {
+        let mut server = TelnetServer::bind("127.0.0.1:0", false).unwrap();
+        let address = server.local_addr();
+        let _client = connect(&server);
+        wait_for_viewports(&mut server, |viewports| viewports.len() == 1);
+
+        drop(server);
+        assert!(TcpStream::connect(address).is_err());
+        TelnetServer::bind(address, false).unwrap();
+    }
+}
@@ -0,0 +1,175 @@
+use super::*;
+
+/// A box around something, with its sides along the axes.
+#[derive(Debug, Copy, Clone)]
+pub struct Aabb {
+    pub min: Vec3,
+    pub max: Vec3,
+}
+
+impl Aabb {
+    /// The smallest box around the given points, or an empty one at the origin if there are none.
+    pub fn around<'a>(points: impl IntoIterator<Item = &'a Vec3>) -> Aabb {
+        let mut points = points.into_iter();
+        let first = match points.next() {
+            Some(first) => *first,
+            None => {
+                let origin = Vec3::new(0.0, 0.0, 0.0);
+                return Aabb {
+                    min: origin,
+                    max: origin,
+                };
+            }
+        };
+
+        points.fold(
+            Aabb {
+                min: first,
+                max: first,
+            },
+            |Aabb { min, max }, p| Aabb {
+                min: Vec3::new(min.x.min(p.x), min.y.min(p.y), min.z.min(p.z)),
+                max: Vec3::new(max.x.max(p.x), max.y.max(p.y), max.z.max(p.z)),
+            },
+        )
+    }
+
+    pub fn center(&self) -> Vec3 {
+        self.min.add(&self.max).mul(0.5)
+    }
+
+    pub fn size(&self) -> Vec3 {
+        self.max.sub(&self.min)
+    }
+}
+
+/// A sphere around something, for quickly telling whether it can be seen at all.
+#[derive(Debug, Copy, Clone)]
+pub struct BoundingSphere {
+    pub center: Vec3,
+    pub radius: f32,
+}
+
+impl BoundingSphere {
+    /// A sphere around the given points, centered on their bounding box. It's not the
+    /// smallest one there is, but it's close for most shapes.
+    pub fn around(aabb: &Aabb, points: &[Vec3]) -> BoundingSphere {
+        let center = aabb.center();
+        let radius = points
+            .iter()
+            .map(|p| p.sub(&center).length())
+            .fold(0.0, f32::max);
+        BoundingSphere { center, radius }
+    }
+
+    /// A sphere around this one after it's been transformed, which may scale it unevenly.
+    pub fn transform(&self, matrix: &Mat4x4) -> BoundingSphere {
+        let m = &matrix.0;
+        // The longest axis after the transform decides how much the radius grows.
+        let scale = (0..3)
+            .map(|column| Vec3::new(m[0][column], m[1][column], m[2][column]).length())
+            .fold(0.0, f32::max);
+
+        BoundingSphere {
+            center: matrix.mul(&Vec4::from_vec3(self.center, 1.0)).xyz(),
+            radius: self.radius * scale,
+        }
+    }
+}
+
+impl Mesh {
+    /// Works out the bounding box and sphere of the vertices again. Only needed after
+    /// changing the positions of `vertices` directly.
+    pub fn update_bounds(&mut self) {
+        let positions: Vec<Vec3> = self.vertices.iter().map(|vertex| vertex.position).collect();
+        self.aabb = Aabb::around(&positions);
+        self.bounding_sphere = BoundingSphere::around(&self.aabb, &positions);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_close(a: &Vec3, (x, y, z): (f32, f32, f32)) {
+        assert!(a.sub(&Vec3::new(x, y, z)).length() < 1e-5, "{:?}", a);
+    }
+
+    #[test]
+    fn box_around_points() {
+        let points = [
+            Vec3::new(1.0, 2.0, 3.0),
+            Vec3::new(-1.0, 5.0, 0.0),
+            Vec3::new(0.0, 0.0, -2.0),
+        ];
+        let aabb = Aabb::around(&points);
+        assert_close(&aabb.min, (-1.0, 0.0, -2.0));
+        assert_close(&aabb.max, (1.0, 5.0, 3.0));
+        assert_close(&aabb.center(), (0.0, 2.5, 0.5));
+        assert_close(&aabb.size(), (2.0, 5.0, 5.0));
+
+        let empty = Aabb::around(&[]);
+        assert_close(&empty.min, (0.0, 0.0, 0.0));
+        assert_close(&empty.max, (0.0, 0.0, 0.0));
+    }
+
+    #[test]
+    fn sphere_around_a_box_reaches_its_corners() {
+        let corners: Vec<Vec3> = (0..8)
+            .map(|i| {
+                let side = |bit: usize| if i & bit == 0 { -1.0 } else { 1.0 };
+                Vec3::new(3.0 + side(1), side(2), side(4))
+            })
+            .collect();
+        let sphere = BoundingSphere::around(&Aabb::around(&corners), &corners);
+        assert_close(&sphere.center, (3.0, 0.0, 0.0));
+        assert!((sphere.radius - 3f32.sqrt()).abs() < 1e-5);
+    }
+
+    #[test]
+    fn transformed_spheres_grow_with_the_longest_axis() {
+        let sphere = BoundingSphere {
+            center: Vec3::new(1.0, 0.0, 0.0),
+            radius: 2.0,
+        };
+
+        let moved = sphere.transform(
+            &Vec3::new(0.0, 5.0, 0.0)
+                .translation()
+                .mat_mul(&Vec3::new(2.0, 3.0, 1.0).scaling()),
+        );
+        assert_close(&moved.center, (2.0, 5.0, 0.0));
+        assert!((moved.radius - 6.0).abs() < 1e-5);
+
+        let turned = sphere.transform(&Vec3::new(0.3, 1.2, -0.7).rotation());
+        assert!((turned.center.length() - 1.0).abs() < 1e-5);
+        assert!((turned.radius - 2.0).abs() < 1e-5);
+    }
+
+    #[test]
+    fn meshes_keep_their_bounds_up_to_date() {
+        let vertex = |x: f32, y: f32, z: f32| Vertex {
+            position: Vec3::new(x, y, z),
+            uv: Vec2::new(0.0, 0.0),
+            normal: Vec3::new(0.0, 0.0, 1.0),
+            color: Vec4::new(1.0, 1.0, 1.0, 1.0),
+        };
+        let mut mesh = Mesh::new(
+            vec![
+                vertex(0.0, 0.0, 0.0),
+                vertex(4.0, 0.0, 0.0),
+                vertex(0.0, 3.0, 0.0),
+            ],
+            vec![[0, 1, 2]],
+            None,
+        );
+        assert_close(&mesh.aabb.max, (4.0, 3.0, 0.0));
+        assert_close(&mesh.bounding_sphere.center, (2.0, 1.5, 0.0));
+        assert!((mesh.bounding_sphere.radius - 2.5).abs() < 1e-5);
+
+        mesh.vertices[1].position = Vec3::new(-4.0, 0.0, 0.0);
+        mesh.update_bounds();
+        assert_close(&mesh.aabb.min, (-4.0, 0.0, 0.0));
+        assert_close(&mesh.bounding_sphere.center, (-2.0, 1.5, 0.0));
+    }
+}
@@ -0,0 +1,68 @@
+use super::*;
+use crate::texture::Texture;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+/// Index of a material in `Model::materials`.
+pub type MaterialId = usize;
+
+/// Surface properties, as described by an MTL file.
+#[derive(Debug, Clone)]
+pub struct Material {
+    pub name: String,
+    /// Color under ambient light (`Ka`).
+    pub ambient: Vec3,
+    /// Color under direct light (`Kd`).
+    pub diffuse: Vec3,
+    /// Color of highlights (`Ks`).
+    pub specular: Vec3,
+    /// Specular exponent (`Ns`); the higher, the smaller and sharper the highlights.
+    pub shininess: f32,
+    /// Light given off by the surface itself (`Ke`).
+    pub emissive: Vec3,
+    /// Index of refraction (`Ni`).
+    pub optical_density: f32,
+    /// 1 for fully opaque, 0 for fully transparent (`d`, or `1 - Tr`).
+    pub opacity: f32,
+    /// Illumination model (`illum`); 0 and 1 have no highlights.
+    pub illumination: u32,
+    /// Texture for the diffuse color (`map_Kd`).
+    pub diffuse_map: Option<PathBuf>,
+    /// The diffuse map, once loaded.
+    pub diffuse_texture: Option<Arc<Texture>>,
+    /// Bump map (`map_Bump` or `bump`).
+    pub bump_map: Option<PathBuf>,
+    /// Texture for the opacity (`map_d`).
+    pub opacity_map: Option<PathBuf>,
+}
+
+impl Material {
+    pub fn new(name: &str) -> Self {
+        Material {
+            name: name.to_owned(),
+            ambient: Vec3::new(1.0, 1.0, 1.0),
+            diffuse: Vec3::new(0.8, 0.8, 0.8),
+            specular: Vec3::new(0.0, 0.0, 0.0),
+            shininess: 0.0,
+            emissive: Vec3::new(0.0, 0.0, 0.0),
+            optical_density: 1.0,
+            opacity: 1.0,
+            illumination: 1,
+            diffuse_map: None,
+            diffuse_texture: None,
+            bump_map: None,
+            opacity_map: None,
+        }
+    }
+
+    /// Whether the illumination model asks for highlights.
+    pub fn has_highlights(&self) -> bool {
+        self.illumination >= 2 && self.shininess > 0.0
+    }
+}
+
+impl Default for Material {
+    fn default() -> Self {
+        Material::new("default")
+    }
+}
@@ -1,4 +1,15 @@
 use super::math::*;
+use std::collections::HashMap;
+use std::path::PathBuf;
+
+mod bounds;
+mod material;
+mod normals;
+mod primitives;
+
+pub use bounds::*;
+pub use material::*;
+pub use normals::*;
 
 #[derive(Debug, Copy, Clone)]
 pub struct Vertex {
@@ -10,12 +21,149 @@ pub struct Vertex {
 
 pub type Triangle = [Vertex; 3];
 
+/// Triangles made of corners that index into a list of vertices, so that a vertex shared
+/// between triangles is only stored, and transformed, once.
 #[derive(Debug)]
 pub struct Mesh {
-    pub triangles: Vec<Triangle>,
+    pub vertices: Vec<Vertex>,
+    /// The vertices of every triangle, as indices into `vertices`.
+    pub indices: Vec<[u32; 3]>,
+    /// The material of the whole mesh, in the model it belongs to.
+    pub material: Option<MaterialId>,
+    /// Bounds of the vertices, worked out when the mesh is made.
+    pub aabb: Aabb,
+    pub bounding_sphere: BoundingSphere,
+}
+
+/// Loose points without any faces, such as scanned point clouds. They're drawn as single pixels.
+#[derive(Debug)]
+pub struct PointCloud {
+    pub points: Vec<Vertex>,
+}
+
+/// A named piece of a model with a single material, such as an OBJ object or group.
+#[derive(Debug)]
+pub struct Part {
+    pub name: String,
+    pub mesh: Mesh,
+}
+
+/// A model made up of parts that can be shown, colored and moved separately.
+#[derive(Debug)]
+pub struct Model {
+    pub parts: Vec<Part>,
+    pub materials: Vec<Material>,
+    /// Files the model was read from, such as an OBJ file and its material libraries.
+    pub sources: Vec<PathBuf>,
+}
+
+impl Model {
+    pub fn part(&self, name: &str) -> Option<&Part> {
+        self.parts.iter().find(|part| part.name == name)
+    }
+
+    pub fn part_mut(&mut self, name: &str) -> Option<&mut Part> {
+        self.parts.iter_mut().find(|part| part.name == name)
+    }
+
+    pub fn material(&self, id: Option<MaterialId>) -> Option<&Material> {
+        id.and_then(|id| self.materials.get(id))
+    }
+
+    /// Joins all parts into a single mesh, without a material.
+    pub fn merge(&self) -> Mesh {
+        Mesh::from_triangles(
+            self.parts.iter().flat_map(|part| part.mesh.triangles()),
+            None,
+        )
+    }
 }
 
 impl Mesh {
+    pub fn new(
+        vertices: Vec<Vertex>,
+        indices: Vec<[u32; 3]>,
+        material: Option<MaterialId>,
+    ) -> Mesh {
+        let origin = Vec3::new(0.0, 0.0, 0.0);
+        let mut mesh = Mesh {
+            vertices,
+            indices,
+            material,
+            aabb: Aabb {
+                min: origin,
+                max: origin,
+            },
+            bounding_sphere: BoundingSphere {
+                center: origin,
+                radius: 0.0,
+            },
+        };
+        mesh.update_bounds();
+        mesh
+    }
+
+    /// Builds a mesh out of separate triangles, welding together vertices that are exactly
+    /// the same, down to their normal, UV and color.
+    pub fn from_triangles(
+        triangles: impl IntoIterator<Item = Triangle>,
+        material: Option<MaterialId>,
+    ) -> Mesh {
+        let mut vertices = vec![];
+        let mut indices = vec![];
+        let mut welded: HashMap<[u32; 12], u32> = HashMap::new();
+
+        for triangle in triangles {
+            let mut corners = [0; 3];
+            for (index, vertex) in corners.iter_mut().zip(&triangle) {
+                *index = *welded.entry(vertex_key(vertex)).or_insert_with(|| {
+                    vertices.push(*vertex);
+                    (vertices.len() - 1) as u32
+                });
+            }
+            indices.push(corners);
+        }
+
+        Mesh::new(vertices, indices, material)
+    }
+
+    pub fn triangle(&self, index: usize) -> Triangle {
+        let [a, b, c] = self.indices[index];
+        [
+            self.vertices[a as usize],
+            self.vertices[b as usize],
+            self.vertices[c as usize],
+        ]
+    }
+
+    pub fn triangles(&self) -> impl Iterator<Item = Triangle> + '_ {
+        (0..self.indices.len()).map(move |index| self.triangle(index))
+    }
+
+    /// Changes the corners of the triangles one by one, given the index of the triangle and
+    /// of the corner. Corners that shared a vertex may not be the same anymore afterwards,
+    /// so the vertices are welded again.
+    pub fn map_corners(&mut self, mut f: impl FnMut(usize, usize, &mut Vertex)) {
+        let triangles: Vec<Triangle> = self
+            .triangles()
+            .enumerate()
+            .map(|(t, mut triangle)| {
+                for (k, vertex) in triangle.iter_mut().enumerate() {
+                    f(t, k, vertex);
+                }
+                triangle
+            })
+            .collect();
+        *self = Mesh::from_triangles(triangles, self.material);
+    }
+
+    /// Replaces the color of every vertex.
+    pub fn set_color(&mut self, color: Vec4) {
+        for vertex in &mut self.vertices {
+            vertex.color = color;
+        }
+    }
+
     pub fn from_faces(faces: Vec<[Vec3; 3]>) -> Mesh {
         let mut triangles: Vec<Triangle> = vec![];
 
@@ -25,23 +173,223 @@ impl Mesh {
                     position: a,
                     uv: Vec2::new(a.x, a.y),
                     color: Vec4::new(1.0, 1.0, 1.0, 1.0),
-                    normal: Vec3::new(0.0, 1.0, 0.0),
+                    normal: Vec3::new(0.0, 0.0, 0.0),
                 },
                 Vertex {
                     position: b,
                     uv: Vec2::new(b.x, b.y),
                     color: Vec4::new(1.0, 1.0, 1.0, 1.0),
-                    normal: Vec3::new(0.0, 1.0, 0.0),
+                    normal: Vec3::new(0.0, 0.0, 0.0),
                 },
                 Vertex {
                     position: c,
                     uv: Vec2::new(c.x, c.y),
                     color: Vec4::new(1.0, 1.0, 1.0, 1.0),
-                    normal: Vec3::new(0.0, 1.0, 0.0),
+                    normal: Vec3::new(0.0, 0.0, 0.0),
                 },
             ]);
         }
 
-        Mesh { triangles }
+        let mut mesh = Mesh::from_triangles(triangles, None);
+        let flat = NormalOptions {
+            smooth: false,
+            ..NormalOptions::default()
+        };
+        mesh.generate_normals(&flat, None);
+        mesh
+    }
+}
+
+/// Splits a polygon into triangles, returned as indices into `polygon`. Convex polygons
+/// are split into a fan; concave ones are split by ear clipping.
+pub fn triangulate(polygon: &[Vec3]) -> Vec<[usize; 3]> {
+    if polygon.len() < 3 {
+        return vec![];
+    }
+
+    // Work in the plane of the polygon. Newell's method gives a normal that's robust for
+    // slightly non-planar polygons and points along the winding order, so corners that
+    // turn counterclockwise around it are convex.
+    let mut normal = Vec3::new(0.0, 0.0, 0.0);
+    for (i, a) in polygon.iter().enumerate() {
+        let b = &polygon[(i + 1) % polygon.len()];
+        normal.x += (a.y - b.y) * (a.z + b.z);
+        normal.y += (a.z - b.z) * (a.x + b.x);
+        normal.z += (a.x - b.x) * (a.y + b.y);
+    }
+    let normal = normal.normalize();
+    let axis = if normal.x.abs() > 0.9 {
+        Vec3::new(0.0, 1.0, 0.0)
+    } else {
+        Vec3::new(1.0, 0.0, 0.0)
+    };
+    let u = axis.cross(&normal).normalize();
+    let v = normal.cross(&u);
+    let points: Vec<Vec2> = polygon
+        .iter()
+        .map(|p| Vec2::new(p.dot(&u), p.dot(&v)))
+        .collect();
+
+    let turn = |a: usize, b: usize, c: usize| {
+        cross_2d(&points[b].sub(&points[a]), &points[c].sub(&points[b]))
+    };
+
+    let mut remaining: Vec<usize> = (0..polygon.len()).collect();
+    let convex = (0..remaining.len()).all(|i| {
+        let n = remaining.len();
+        turn(
+            remaining[(i + n - 1) % n],
+            remaining[i],
+            remaining[(i + 1) % n],
+        ) >= 0.0
+    });
+    if convex {
+        return (1..polygon.len() - 1).map(|i| [0, i, i + 1]).collect();
+    }
+
+    let mut triangles = Vec::with_capacity(polygon.len() - 2);
+    while remaining.len() > 3 {
+        let n = remaining.len();
+        let corner = |i: usize| {
+            (
+                remaining[(i + n - 1) % n],
+                remaining[i],
+                remaining[(i + 1) % n],
+            )
+        };
+
+        let ear = (0..n).find(|&i| {
+            let (a, b, c) = corner(i);
+            turn(a, b, c) > 0.0
+                && remaining
+                    .iter()
+                    .filter(|&&p| p != a && p != b && p != c)
+                    .all(|&p| !in_triangle(&points[p], &points[a], &points[b], &points[c]))
+        });
+
+        // Degenerate polygons (e.g. self-intersecting ones) may have no ears at all;
+        // cut off any corner so we still end up with something.
+        let i = ear.unwrap_or(0);
+        let (a, b, c) = corner(i);
+        triangles.push([a, b, c]);
+        remaining.remove(i);
+    }
+    triangles.push([remaining[0], remaining[1], remaining[2]]);
+
+    triangles
+}
+
+/// Identifies vertices that are the same in every way.
+fn vertex_key(vertex: &Vertex) -> [u32; 12] {
+    // Adding 0.0 turns -0.0 into 0.0, so both end up with the same bits.
+    let bits = |x: f32| (x + 0.0).to_bits();
+    let Vertex {
+        position,
+        uv,
+        normal,
+        color,
+    } = vertex;
+    [
+        bits(position.x),
+        bits(position.y),
+        bits(position.z),
+        bits(uv.x),
+        bits(uv.y),
+        bits(normal.x),
+        bits(normal.y),
+        bits(normal.z),
+        bits(color.x),
+        bits(color.y),
+        bits(color.z),
+        bits(color.w),
+    ]
+}
+
+fn cross_2d(a: &Vec2, b: &Vec2) -> f32 {
+    a.x * b.y - a.y * b.x
+}
+
+fn in_triangle(p: &Vec2, a: &Vec2, b: &Vec2, c: &Vec2) -> bool {
+    cross_2d(&b.sub(a), &p.sub(a)) >= 0.0
+        && cross_2d(&c.sub(b), &p.sub(b)) >= 0.0
+        && cross_2d(&a.sub(c), &p.sub(c)) >= 0.0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn points(coordinates: &[(f32, f32)]) -> Vec<Vec3> {
+        coordinates
+            .iter()
+            .map(|&(x, y)| Vec3::new(x, y, 0.0))
+            .collect()
+    }
+
+    /// The area of a triangle on the XY plane, positive if it's counterclockwise.
+    fn area(polygon: &[Vec3], [a, b, c]: [usize; 3]) -> f32 {
+        let (a, b, c) = (polygon[a], polygon[b], polygon[c]);
+        cross_2d(&b.sub(&a).xy(), &c.sub(&a).xy()) / 2.0
+    }
+
+    #[test]
+    fn too_few_points_make_no_triangles() {
+        assert!(triangulate(&[]).is_empty());
+        assert!(triangulate(&points(&[(0.0, 0.0), (1.0, 0.0)])).is_empty());
+        assert_eq!(
+            triangulate(&points(&[(0.0, 0.0), (1.0, 0.0), (0.0, 1.0)])),
+            vec![[0, 1, 2]]
+        );
+    }
+
+    #[test]
+    fn convex_polygons_become_fans() {
+        let square = points(&[(0.0, 0.0), (1.0, 0.0), (1.0, 1.0), (0.0, 1.0)]);
+        assert_eq!(triangulate(&square), vec![[0, 1, 2], [0, 2, 3]]);
+
+        // Clockwise polygons are convex too, seen from the other side.
+        let reversed: Vec<Vec3> = square.iter().rev().cloned().collect();
+        assert_eq!(triangulate(&reversed), vec![[0, 1, 2], [0, 2, 3]]);
+    }
+
+    #[test]
+    fn concave_polygons_are_covered_exactly() {
+        // An L shape, where a fan from the first corner would fold over the notch.
+        let shape = points(&[
+            (2.0, 1.0),
+            (1.0, 1.0),
+            (1.0, 2.0),
+            (0.0, 2.0),
+            (0.0, 0.0),
+            (2.0, 0.0),
+        ]);
+        let triangles = triangulate(&shape);
+        assert_eq!(triangles.len(), shape.len() - 2);
+        for &triangle in &triangles {
+            assert!(area(&shape, triangle) > 0.0, "{:?} is flipped", triangle);
+        }
+        let total: f32 = triangles.iter().map(|&t| area(&shape, t)).sum();
+        assert_eq!(total, 3.0);
+    }
+
+    #[test]
+    fn polygons_can_face_any_way() {
+        // The same L shape, standing up on the YZ plane.
+        let shape: Vec<Vec3> = points(&[
+            (2.0, 1.0),
+            (1.0, 1.0),
+            (1.0, 2.0),
+            (0.0, 2.0),
+            (0.0, 0.0),
+            (2.0, 0.0),
+        ])
+        .iter()
+        .map(|p| Vec3::new(0.0, p.x, p.y))
+        .collect();
+        let flat: Vec<Vec3> = shape.iter().map(|p| Vec3::new(p.y, p.z, 0.0)).collect();
+        let triangles = triangulate(&shape);
+        assert_eq!(triangles.len(), 4);
+        let total: f32 = triangles.iter().map(|&t| area(&flat, t)).sum();
+        assert_eq!(total, 3.0);
     }
 }
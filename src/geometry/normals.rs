@@ -0,0 +1,283 @@
+use super::*;
+use std::collections::HashMap;
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum NormalWeighting {
+    /// Larger faces pull harder on the normals of their corners.
+    Area,
+    /// Faces pull by the angle they make at the corner, so the result doesn't depend on
+    /// how a surface happens to be split into triangles.
+    Angle,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct NormalOptions {
+    /// Whether to average normals across faces sharing a vertex, or give every face its own.
+    pub smooth: bool,
+    pub weighting: NormalWeighting,
+    /// Faces meeting at a steeper angle than this (in radians) get a hard edge, even when smooth.
+    pub crease_angle: Option<f32>,
+}
+
+impl Default for NormalOptions {
+    fn default() -> Self {
+        NormalOptions {
+            smooth: true,
+            weighting: NormalWeighting::Angle,
+            crease_angle: Some(std::f32::consts::FRAC_PI_3),
+        }
+    }
+}
+
+impl Mesh {
+    /// Replaces the normals of every vertex with ones worked out from the faces.
+    ///
+    /// `smoothing_groups` optionally gives a group per triangle, like OBJ's `s` directive:
+    /// normals are only averaged between faces in the same group, and faces in group 0 are flat.
+    pub fn generate_normals(&mut self, options: &NormalOptions, smoothing_groups: Option<&[u32]>) {
+        let normals = self.compute_normals(options, smoothing_groups);
+        self.map_corners(|t, k, vertex| vertex.normal = normals[t][k]);
+    }
+
+    /// Works out normals like `generate_normals` does, without changing the mesh.
+    pub fn compute_normals(
+        &self,
+        options: &NormalOptions,
+        smoothing_groups: Option<&[u32]>,
+    ) -> Vec<[Vec3; 3]> {
+        let face_normals: Vec<Vec3> = self
+            .triangles()
+            .map(|[a, b, c]| {
+                b.position
+                    .sub(&a.position)
+                    .cross(&c.position.sub(&a.position))
+                    .normalize()
+            })
+            .collect();
+
+        let group = |triangle: usize| smoothing_groups.map_or(1, |groups| groups[triangle]);
+
+        let mut normals: Vec<[Vec3; 3]> = face_normals.iter().map(|&n| [n, n, n]).collect();
+        if !options.smooth {
+            return normals;
+        }
+
+        // Find the corners of all triangles that touch each position.
+        let mut corners: HashMap<[u32; 3], Vec<(usize, usize)>> = HashMap::new();
+        for (t, triangle) in self.triangles().enumerate() {
+            if group(t) == 0 {
+                continue;
+            }
+            for (k, vertex) in triangle.iter().enumerate() {
+                corners
+                    .entry(position_key(&vertex.position))
+                    .or_default()
+                    .push((t, k));
+            }
+        }
+
+        let min_cos = options.crease_angle.map_or(-1.0, f32::cos);
+        let weighted: Vec<[Vec3; 3]> = self
+            .triangles()
+            .map(|triangle| corner_weights(&triangle, options.weighting))
+            .collect();
+
+        for shared in corners.values() {
+            for &(t, k) in shared {
+                let mut sum = Vec3::new(0.0, 0.0, 0.0);
+                for &(other_t, other_k) in shared {
+                    if group(other_t) == group(t)
+                        && face_normals[other_t].dot(&face_normals[t]) >= min_cos
+                    {
+                        sum = sum.add(&weighted[other_t][other_k]);
+                    }
+                }
+
+                // Faces that cancel out (or degenerate ones) keep their own normal.
+                if sum.length() > 0.0 {
+                    normals[t][k] = sum.normalize();
+                }
+            }
+        }
+
+        normals
+    }
+}
+
+/// Identifies vertices at the same position, even if they have different normals or UVs.
+fn position_key(position: &Vec3) -> [u32; 3] {
+    // Adding 0.0 turns -0.0 into 0.0, so both end up with the same bits.
+    [
+        (position.x + 0.0).to_bits(),
+        (position.y + 0.0).to_bits(),
+        (position.z + 0.0).to_bits(),
+    ]
+}
+
+/// Returns the face normal of a triangle scaled by how much it counts towards each corner.
+fn corner_weights([a, b, c]: &Triangle, weighting: NormalWeighting) -> [Vec3; 3] {
+    let ab = b.position.sub(&a.position);
+    let ac = c.position.sub(&a.position);
+    // The cross product is as long as twice the area of the triangle.
+    let cross = ab.cross(&ac);
+
+    match weighting {
+        NormalWeighting::Area => [cross, cross, cross],
+        NormalWeighting::Angle => {
+            let normal = cross.normalize();
+            let angle = |corner: &Vec3, p: &Vec3, q: &Vec3| {
+                let u = p.sub(corner).normalize();
+                let v = q.sub(corner).normalize();
+                clamp((-1.0, 1.0), u.dot(&v)).acos()
+            };
+            [
+                normal.mul(angle(&a.position, &b.position, &c.position)),
+                normal.mul(angle(&b.position, &c.position, &a.position)),
+                normal.mul(angle(&c.position, &a.position, &b.position)),
+            ]
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The faces of a cube from -1 to 1, two triangles to a side, facing out.
+    fn cube() -> Vec<[Vec3; 3]> {
+        let x = Vec3::new(1.0, 0.0, 0.0);
+        let y = Vec3::new(0.0, 1.0, 0.0);
+        let z = Vec3::new(0.0, 0.0, 1.0);
+        // Each side as its normal and two directions along it, with u × v = normal.
+        let sides = [
+            (x, y, z),
+            (x.mul(-1.0), z, y),
+            (y, z, x),
+            (y.mul(-1.0), x, z),
+            (z, x, y),
+            (z.mul(-1.0), y, x),
+        ];
+
+        let mut faces = vec![];
+        for (normal, u, v) in &sides {
+            let corner = |s: f32, t: f32| normal.add(&u.mul(s)).add(&v.mul(t));
+            let corners = [
+                corner(-1.0, -1.0),
+                corner(1.0, -1.0),
+                corner(1.0, 1.0),
+                corner(-1.0, 1.0),
+            ];
+            faces.push([corners[0], corners[1], corners[2]]);
+            faces.push([corners[0], corners[2], corners[3]]);
+        }
+        faces
+    }
+
+    fn normals(
+        faces: &[[Vec3; 3]],
+        options: &NormalOptions,
+        groups: Option<&[u32]>,
+    ) -> Vec<[Vec3; 3]> {
+        Mesh::from_faces(faces.to_vec()).compute_normals(options, groups)
+    }
+
+    fn assert_close(a: &Vec3, b: &Vec3) {
+        assert!(a.sub(b).length() < 1e-5, "{:?} isn't {:?}", a, b);
+    }
+
+    fn face_normal([a, b, c]: &[Vec3; 3]) -> Vec3 {
+        b.sub(a).cross(&c.sub(a)).normalize()
+    }
+
+    /// Checks that every corner has the normal of its own face.
+    fn assert_flat(faces: &[[Vec3; 3]], normals: &[[Vec3; 3]]) {
+        for (face, normals) in faces.iter().zip(normals) {
+            for normal in normals {
+                assert_close(normal, &face_normal(face));
+            }
+        }
+    }
+
+    /// Checks that the corners of the cube point straight out from its center.
+    fn assert_rounded(faces: &[[Vec3; 3]], normals: &[[Vec3; 3]]) {
+        for (face, normals) in faces.iter().zip(normals) {
+            for (position, normal) in face.iter().zip(normals) {
+                assert_close(normal, &position.normalize());
+            }
+        }
+    }
+
+    fn smooth(crease_angle: Option<f32>) -> NormalOptions {
+        NormalOptions {
+            crease_angle,
+            ..NormalOptions::default()
+        }
+    }
+
+    #[test]
+    fn cube_edges_are_hard_or_smooth() {
+        let faces = cube();
+        let flat = NormalOptions {
+            smooth: false,
+            ..NormalOptions::default()
+        };
+        assert_flat(&faces, &normals(&faces, &flat, None));
+        assert_rounded(&faces, &normals(&faces, &smooth(None), None));
+    }
+
+    #[test]
+    fn faces_steeper_than_the_crease_angle_get_hard_edges() {
+        let faces = cube();
+        // The sides of a cube meet at 90 degrees.
+        assert_flat(&faces, &normals(&faces, &NormalOptions::default(), None));
+        assert_flat(&faces, &normals(&faces, &smooth(Some(1.5)), None));
+        assert_rounded(&faces, &normals(&faces, &smooth(Some(1.6)), None));
+
+        // A strip bent up by 30 degrees along the X axis is smooth by default.
+        let (a, b) = (Vec3::new(0.0, 0.0, 0.0), Vec3::new(1.0, 0.0, 0.0));
+        let bend = Vec3::new(0.0, 3f32.sqrt() / 2.0, 0.5);
+        let faces = [[a, Vec3::new(1.0, -1.0, 0.0), b], [a, b, bend]];
+        let bent = normals(&faces, &NormalOptions::default(), None);
+        assert_close(&bent[0][2], &bent[1][1]);
+        assert!(bent[0][2].sub(&face_normal(&faces[0])).length() > 0.1);
+        assert!(bent[0][2].sub(&face_normal(&faces[1])).length() > 0.1);
+        assert_flat(&faces, &normals(&faces, &smooth(Some(0.4)), None));
+    }
+
+    #[test]
+    fn smoothing_groups_only_smooth_within_a_group() {
+        let faces = cube();
+        let options = smooth(None);
+        let same: Vec<u32> = vec![1; faces.len()];
+        assert_rounded(&faces, &normals(&faces, &options, Some(&same)));
+
+        // Group 0 is flat, and so are sides that each have a group of their own.
+        let none: Vec<u32> = vec![0; faces.len()];
+        assert_flat(&faces, &normals(&faces, &options, Some(&none)));
+        let sides: Vec<u32> = (0..faces.len() as u32).map(|t| t / 2 + 1).collect();
+        assert_flat(&faces, &normals(&faces, &options, Some(&sides)));
+    }
+
+    #[test]
+    fn weighting_by_area_favours_sides_with_more_triangles_at_a_corner() {
+        let faces = cube();
+        let area = NormalOptions {
+            weighting: NormalWeighting::Area,
+            ..smooth(None)
+        };
+        let by_area = normals(&faces, &area, None);
+        let by_angle = normals(&faces, &smooth(None), None);
+
+        // The corner at (1, 1, -1) has two triangles of the -Z side and one of each other.
+        let corner = Vec3::new(1.0, 1.0, -1.0);
+        let find = |normals: &[[Vec3; 3]]| {
+            let (t, k) = (0..faces.len())
+                .flat_map(|t| (0..3).map(move |k| (t, k)))
+                .find(|&(t, k)| faces[t][k].sub(&corner).length() < 1e-6)
+                .unwrap();
+            normals[t][k]
+        };
+        assert_close(&find(&by_area), &Vec3::new(1.0, 1.0, -2.0).normalize());
+        assert_close(&find(&by_angle), &corner.normalize());
+    }
+}
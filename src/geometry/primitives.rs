@@ -0,0 +1,631 @@
+use super::*;
+use std::f32::consts::PI;
+
+/// Meshes of basic shapes, all white, centered on the origin and upright along +Y. Curved
+/// surfaces have smooth normals, and seams in their UVs get their own vertices.
+impl Mesh {
+    /// A flat grid of `columns` by `rows` squares on the XZ plane, facing up. The texture is
+    /// upright when looking down on it with -Z at the top.
+    pub fn plane(width: f32, depth: f32, columns: usize, rows: usize) -> Mesh {
+        let mut mesh = Mesh::new(vec![], vec![], None);
+        add_grid(
+            &mut mesh,
+            Vec3::new(-width / 2.0, 0.0, depth / 2.0),
+            Vec3::new(width, 0.0, 0.0),
+            Vec3::new(0.0, 0.0, -depth),
+            (columns, rows),
+        );
+        mesh.update_bounds();
+        mesh
+    }
+
+    /// A cube with sides of the given length, each split into `subdivisions` by `subdivisions`
+    /// squares. Every side has the whole texture on it, upright for the sides around Y.
+    pub fn cube(size: f32, subdivisions: usize) -> Mesh {
+        let x = Vec3::new(1.0, 0.0, 0.0);
+        let y = Vec3::new(0.0, 1.0, 0.0);
+        let z = Vec3::new(0.0, 0.0, 1.0);
+        // The direction of U on each side; V goes up along the side, given by normal × U.
+        let sides = [
+            (x, z.mul(-1.0)),
+            (x.mul(-1.0), z),
+            (z, x),
+            (z.mul(-1.0), x.mul(-1.0)),
+            (y, x),
+            (y.mul(-1.0), x),
+        ];
+
+        let mut mesh = Mesh::new(vec![], vec![], None);
+        for (normal, u) in &sides {
+            let v = normal.cross(u);
+            let origin = normal.sub(u).sub(&v).mul(size / 2.0);
+            add_grid(
+                &mut mesh,
+                origin,
+                u.mul(size),
+                v.mul(size),
+                (subdivisions, subdivisions),
+            );
+        }
+        mesh.update_bounds();
+        mesh
+    }
+
+    /// A sphere made of `segments` slices around Y and `rings` stacked along it. U goes
+    /// around the sphere and V from the bottom to the top.
+    pub fn uv_sphere(radius: f32, segments: usize, rings: usize) -> Mesh {
+        let rings = rings.max(2);
+        let profile: Vec<ProfilePoint> = (0..=rings)
+            .map(|j| {
+                let angle = PI * j as f32 / rings as f32;
+                let (sin, cos) = pole_sin_cos(angle, j, rings);
+                ProfilePoint {
+                    radius: radius * sin,
+                    height: radius * cos,
+                    normal: Vec2::new(sin, cos),
+                    v: 1.0 - j as f32 / rings as f32,
+                }
+            })
+            .collect();
+
+        let mut mesh = Mesh::new(vec![], vec![], None);
+        revolve(&mut mesh, &profile, segments);
+        mesh.update_bounds();
+        mesh
+    }
+
+    /// A sphere made by splitting the faces of an icosahedron into four, `subdivisions`
+    /// times. Its triangles are all about the same size, unlike those of a UV sphere, which
+    /// bunch up at the poles. The UVs wrap around it like those of a UV sphere, with the
+    /// triangles across the seam cut in two along it.
+    pub fn icosphere(radius: f32, subdivisions: usize) -> Mesh {
+        let t = (1.0 + 5f32.sqrt()) / 2.0;
+        let mut positions: Vec<Vec3> = [
+            (-1.0, t, 0.0),
+            (1.0, t, 0.0),
+            (-1.0, -t, 0.0),
+            (1.0, -t, 0.0),
+            (0.0, -1.0, t),
+            (0.0, 1.0, t),
+            (0.0, -1.0, -t),
+            (0.0, 1.0, -t),
+            (t, 0.0, -1.0),
+            (t, 0.0, 1.0),
+            (-t, 0.0, -1.0),
+            (-t, 0.0, 1.0),
+        ]
+        .iter()
+        .map(|&(x, y, z)| Vec3::new(x, y, z).normalize())
+        .collect();
+        let mut faces: Vec<[usize; 3]> = vec![
+            [0, 11, 5],
+            [0, 5, 1],
+            [0, 1, 7],
+            [0, 7, 10],
+            [0, 10, 11],
+            [1, 5, 9],
+            [5, 11, 4],
+            [11, 10, 2],
+            [10, 7, 6],
+            [7, 1, 8],
+            [3, 9, 4],
+            [3, 4, 2],
+            [3, 2, 6],
+            [3, 6, 8],
+            [3, 8, 9],
+            [4, 9, 5],
+            [2, 4, 11],
+            [6, 2, 10],
+            [8, 6, 7],
+            [9, 8, 1],
+        ];
+
+        for _ in 0..subdivisions {
+            // Neighbouring faces share the points on their edges.
+            let mut midpoints: HashMap<(usize, usize), usize> = HashMap::new();
+            let mut midpoint = |a: usize, b: usize| {
+                *midpoints.entry((a.min(b), a.max(b))).or_insert_with(|| {
+                    let point = positions[a].add(&positions[b]).normalize();
+                    positions.push(point);
+                    positions.len() - 1
+                })
+            };
+            faces = faces
+                .iter()
+                .flat_map(|&[a, b, c]| {
+                    let (ab, bc, ca) = (midpoint(a, b), midpoint(b, c), midpoint(c, a));
+                    vec![[a, ab, ca], [b, bc, ab], [c, ca, bc], [ab, bc, ca]]
+                })
+                .collect();
+        }
+
+        let triangles = faces.iter().flat_map(|face| {
+            split_at_seam([positions[face[0]], positions[face[1]], positions[face[2]]])
+        });
+        let triangles = triangles.map(|corners| {
+            let uvs = sphere_uvs(&corners);
+            let mut triangle =
+                [0, 1, 2].map(|k| vertex(corners[k].mul(radius), corners[k], uvs[k]));
+            if !faces_outward(&triangle) {
+                triangle.swap(1, 2);
+            }
+            triangle
+        });
+        Mesh::from_triangles(triangles, None)
+    }
+
+    /// A cylinder with caps, made of `segments` slices around Y and `rings` stacked along it.
+    /// The side has U going around it and V from the bottom to the top, and each cap has a
+    /// disk cut out of the texture.
+    pub fn cylinder(radius: f32, height: f32, segments: usize, rings: usize) -> Mesh {
+        let rings = rings.max(1);
+        let profile: Vec<ProfilePoint> = (0..=rings)
+            .map(|j| {
+                let v = 1.0 - j as f32 / rings as f32;
+                ProfilePoint {
+                    radius,
+                    height: height * (v - 0.5),
+                    normal: Vec2::new(1.0, 0.0),
+                    v,
+                }
+            })
+            .collect();
+
+        let mut mesh = Mesh::new(vec![], vec![], None);
+        revolve(&mut mesh, &profile, segments);
+        add_disk(&mut mesh, radius, height / 2.0, segments);
+        add_disk(&mut mesh, radius, -height / 2.0, segments);
+        mesh.update_bounds();
+        mesh
+    }
+
+    /// A cone standing on its base, made of `segments` slices around Y and `rings` stacked
+    /// along it. The UVs are like a cylinder's.
+    pub fn cone(radius: f32, height: f32, segments: usize, rings: usize) -> Mesh {
+        let rings = rings.max(1);
+        // The side leans in, so its normals lean up.
+        let normal = Vec2::new(height, radius).mul(1.0 / height.hypot(radius));
+        let profile: Vec<ProfilePoint> = (0..=rings)
+            .map(|j| {
+                let v = 1.0 - j as f32 / rings as f32;
+                ProfilePoint {
+                    radius: radius * (1.0 - v),
+                    height: height * (v - 0.5),
+                    normal,
+                    v,
+                }
+            })
+            .collect();
+
+        let mut mesh = Mesh::new(vec![], vec![], None);
+        revolve(&mut mesh, &profile, segments);
+        add_disk(&mut mesh, radius, -height / 2.0, segments);
+        mesh.update_bounds();
+        mesh
+    }
+
+    /// A ring around Y, made of `segments` slices around Y, with `sides` around the tube.
+    /// The major radius is that of the circle through the middle of the tube, and the minor
+    /// one that of the tube. U goes around Y and V around the tube.
+    pub fn torus(major_radius: f32, minor_radius: f32, segments: usize, sides: usize) -> Mesh {
+        let sides = sides.max(3);
+        let profile: Vec<ProfilePoint> = (0..=sides)
+            .map(|k| {
+                // The first and last points meet, so make them the same exactly.
+                let angle = 2.0 * PI * (k % sides) as f32 / sides as f32;
+                let (sin, cos) = angle.sin_cos();
+                ProfilePoint {
+                    radius: major_radius + minor_radius * cos,
+                    height: minor_radius * sin,
+                    normal: Vec2::new(cos, sin),
+                    v: k as f32 / sides as f32,
+                }
+            })
+            .collect();
+
+        let mut mesh = Mesh::new(vec![], vec![], None);
+        revolve(&mut mesh, &profile, segments);
+        mesh.update_bounds();
+        mesh
+    }
+
+    /// A cylinder with half spheres for caps, made of `segments` slices around Y, with `rings`
+    /// rings in each half sphere. The height is that of the straight part in between, so the
+    /// capsule is `height + 2 * radius` tall. V goes from the bottom to the top, evenly
+    /// along the surface.
+    pub fn capsule(radius: f32, height: f32, segments: usize, rings: usize) -> Mesh {
+        let rings = rings.max(1);
+        let length = height + PI * radius;
+        let mut profile = vec![];
+        for (top, offset) in &[(true, height / 2.0), (false, -height / 2.0)] {
+            for j in 0..=rings {
+                let step = if *top { j } else { rings + j };
+                let angle = PI / 2.0 * step as f32 / rings as f32;
+                let (sin, cos) = pole_sin_cos(angle, step, 2 * rings);
+                // Distance along the surface from the top.
+                let distance = if *top {
+                    radius * angle
+                } else {
+                    height + radius * angle
+                };
+                profile.push(ProfilePoint {
+                    radius: radius * sin,
+                    height: offset + radius * cos,
+                    normal: Vec2::new(sin, cos),
+                    v: 1.0 - distance / length,
+                });
+            }
+        }
+
+        let mut mesh = Mesh::new(vec![], vec![], None);
+        revolve(&mut mesh, &profile, segments);
+        mesh.update_bounds();
+        mesh
+    }
+}
+
+fn vertex(position: Vec3, normal: Vec3, uv: Vec2) -> Vertex {
+    Vertex {
+        position,
+        uv,
+        normal,
+        color: Vec4::new(1.0, 1.0, 1.0, 1.0),
+    }
+}
+
+fn add_vertex(mesh: &mut Mesh, vertex: Vertex) -> u32 {
+    let index = mesh.vertices.len();
+    assert!(
+        index <= u32::MAX as usize,
+        "a mesh can't have more vertices than u32 indices reach"
+    );
+    mesh.vertices.push(vertex);
+    index as u32
+}
+
+/// Whether a triangle is wound so that its front is on the side its normals point to.
+fn faces_outward([a, b, c]: &Triangle) -> bool {
+    let face = b
+        .position
+        .sub(&a.position)
+        .cross(&c.position.sub(&a.position));
+    face.dot(&a.normal.add(&b.normal).add(&c.normal)) >= 0.0
+}
+
+/// Adds a triangle, wound to face the way its vertices' normals do.
+fn add_triangle(mesh: &mut Mesh, [a, b, c]: [u32; 3]) {
+    let triangle = [
+        mesh.vertices[a as usize],
+        mesh.vertices[b as usize],
+        mesh.vertices[c as usize],
+    ];
+    mesh.indices.push(if faces_outward(&triangle) {
+        [a, b, c]
+    } else {
+        [a, c, b]
+    });
+}
+
+/// Adds a flat grid of `columns` by `rows` squares spanning `u` and `v` from `origin`,
+/// facing along `u` × `v`, with UVs going from 0 to 1 along each.
+fn add_grid(mesh: &mut Mesh, origin: Vec3, u: Vec3, v: Vec3, (columns, rows): (usize, usize)) {
+    let (columns, rows) = (columns.max(1), rows.max(1));
+    let normal = u.cross(&v).normalize();
+    let first = mesh.vertices.len() as u32;
+    for j in 0..=rows {
+        for i in 0..=columns {
+            let s = i as f32 / columns as f32;
+            let t = j as f32 / rows as f32;
+            let position = origin.add(&u.mul(s)).add(&v.mul(t));
+            add_vertex(mesh, vertex(position, normal, Vec2::new(s, t)));
+        }
+    }
+
+    let index = |i: usize, j: usize| first + (j * (columns + 1) + i) as u32;
+    for j in 0..rows {
+        for i in 0..columns {
+            let (a, b) = (index(i, j), index(i + 1, j));
+            let (c, d) = (index(i, j + 1), index(i + 1, j + 1));
+            add_triangle(mesh, [a, b, d]);
+            add_triangle(mesh, [a, d, c]);
+        }
+    }
+}
+
+/// A point on the outline of a surface of revolution.
+struct ProfilePoint {
+    /// Distance from the Y axis.
+    radius: f32,
+    height: f32,
+    /// The normal, as its parts away from the Y axis and along it.
+    normal: Vec2,
+    v: f32,
+}
+
+/// Adds the surface swept out by turning a profile, from top to bottom, around the Y axis in
+/// `segments` steps. Points of the profile on the axis become poles.
+fn revolve(mesh: &mut Mesh, profile: &[ProfilePoint], segments: usize) {
+    let segments = segments.max(3);
+    let first = mesh.vertices.len() as u32;
+    for point in profile {
+        // One more than there are segments, since the UVs need a seam where they wrap around.
+        for i in 0..=segments {
+            let angle = 2.0 * PI * (i % segments) as f32 / segments as f32;
+            let (sin, cos) = angle.sin_cos();
+            // Triangles meet at a pole with just one corner each, in the middle of their slice.
+            let u = if point.radius == 0.0 {
+                (i as f32 + 0.5) / segments as f32
+            } else {
+                i as f32 / segments as f32
+            };
+            add_vertex(
+                mesh,
+                vertex(
+                    Vec3::new(point.radius * cos, point.height, point.radius * sin),
+                    Vec3::new(point.normal.x * cos, point.normal.y, point.normal.x * sin),
+                    Vec2::new(u, point.v),
+                ),
+            );
+        }
+    }
+
+    let index = |k: usize, i: usize| first + (k * (segments + 1) + i) as u32;
+    for (k, pair) in profile.windows(2).enumerate() {
+        for i in 0..segments {
+            let (a, b) = (index(k, i), index(k, i + 1));
+            let (c, d) = (index(k + 1, i), index(k + 1, i + 1));
+            match (pair[0].radius == 0.0, pair[1].radius == 0.0) {
+                (true, true) => {}
+                (true, false) => add_triangle(mesh, [a, d, c]),
+                (false, true) => add_triangle(mesh, [a, b, c]),
+                (false, false) => {
+                    add_triangle(mesh, [a, b, d]);
+                    add_triangle(mesh, [a, d, c]);
+                }
+            }
+        }
+    }
+}
+
+/// Adds a disk facing away from the origin along Y, as seen from above, with a circle cut
+/// out of the texture.
+fn add_disk(mesh: &mut Mesh, radius: f32, height: f32, segments: usize) {
+    let segments = segments.max(3);
+    let up = height.signum();
+    let normal = Vec3::new(0.0, up, 0.0);
+    let center = add_vertex(
+        mesh,
+        vertex(Vec3::new(0.0, height, 0.0), normal, Vec2::new(0.5, 0.5)),
+    );
+    for i in 0..segments {
+        let angle = 2.0 * PI * i as f32 / segments as f32;
+        let (sin, cos) = angle.sin_cos();
+        // The bottom is mirrored, so that its texture isn't when seen from below.
+        let uv = Vec2::new(0.5 + cos / 2.0, 0.5 - up * sin / 2.0);
+        add_vertex(
+            mesh,
+            vertex(Vec3::new(radius * cos, height, radius * sin), normal, uv),
+        );
+    }
+    for i in 0..segments {
+        let ring = |i: usize| center + 1 + (i % segments) as u32;
+        add_triangle(mesh, [center, ring(i), ring(i + 1)]);
+    }
+}
+
+/// The sine and cosine of an angle down from the top of a sphere, split into `steps` steps,
+/// exact at the poles so that the points there end up on the axis.
+fn pole_sin_cos(angle: f32, step: usize, steps: usize) -> (f32, f32) {
+    if step == 0 {
+        (0.0, 1.0)
+    } else if step == steps {
+        (0.0, -1.0)
+    } else {
+        angle.sin_cos()
+    }
+}
+
+fn on_axis(p: &Vec3) -> bool {
+    p.x.abs() < 1e-6 && p.z.abs() < 1e-6
+}
+
+/// How far around Y a point is, from 0 to 1, starting from +X. The seam is at +X, on the
+/// Z = 0 plane.
+fn longitude(p: &Vec3) -> f32 {
+    let u = p.z.atan2(p.x) / (2.0 * PI);
+    if u < 0.0 {
+        u + 1.0
+    } else {
+        u
+    }
+}
+
+/// Whether a triangle on a sphere has corners on both sides of the seam, so that its U would
+/// go most of the way around the texture the wrong way.
+fn crosses_seam(corners: &[Vec3; 3]) -> bool {
+    let (min, max) = corners
+        .iter()
+        .filter(|p| !on_axis(p))
+        .map(longitude)
+        .fold((1.0f32, 0.0f32), |(min, max), u| (min.min(u), max.max(u)));
+    max - min > 0.5
+}
+
+/// Cuts a triangle on a unit sphere that crosses the seam into pieces on either side of it,
+/// with the new corners on the seam moved out to the sphere.
+fn split_at_seam(corners: [Vec3; 3]) -> Vec<[Vec3; 3]> {
+    if !crosses_seam(&corners) {
+        return vec![corners];
+    }
+
+    let mut pieces = vec![];
+    for side in [-1.0f32, 1.0] {
+        let mut polygon = vec![];
+        for k in 0..3 {
+            let (a, b) = (corners[k], corners[(k + 1) % 3]);
+            if side * a.z >= 0.0 {
+                polygon.push(a);
+            }
+            if a.z * b.z < 0.0 {
+                // Go from the same end either way, so that neighbours cut their shared edge
+                // at exactly the same point.
+                let (from, to) = if a.z < 0.0 { (a, b) } else { (b, a) };
+                let mut cut = from.add(&to.sub(&from).mul(from.z / (from.z - to.z)));
+                cut.z = 0.0;
+                polygon.push(cut.normalize());
+            }
+        }
+        for i in 1..polygon.len().saturating_sub(1) {
+            pieces.push([polygon[0], polygon[i], polygon[i + 1]]);
+        }
+    }
+    pieces
+}
+
+/// UVs for the corners of a triangle on a unit sphere, wrapped around it like a UV sphere's.
+/// The triangle mustn't cross the seam, but it can have corners on it.
+fn sphere_uvs(corners: &[Vec3; 3]) -> [Vec2; 3] {
+    let mut u = corners.map(|p| longitude(&p));
+
+    // Corners on the seam are at 0, but belong at 1 when the rest of the triangle is before it.
+    let around: Vec<usize> = (0..3).filter(|&k| !on_axis(&corners[k])).collect();
+    if crosses_seam(corners) {
+        for &k in &around {
+            if u[k] < 0.5 {
+                u[k] += 1.0;
+            }
+        }
+    }
+    // The U of a pole could be anything, so take the middle of the other corners.
+    for k in 0..3 {
+        if on_axis(&corners[k]) {
+            u[k] = around.iter().map(|&k| u[k]).sum::<f32>() / around.len() as f32;
+        }
+    }
+
+    [0, 1, 2].map(|k| Vec2::new(u[k], 0.5 + corners[k].y.clamp(-1.0, 1.0).asin() / PI))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Checks what every generated mesh should have: unit normals that point away from the
+    /// inside, given by `outward` for a position, triangles facing the same way as their
+    /// normals, and UVs within the texture.
+    fn check(name: &str, mesh: &Mesh, outward: impl Fn(&Vec3) -> Vec3) {
+        assert!(!mesh.indices.is_empty(), "{} has no triangles", name);
+        for vertex in mesh.triangles().flatten() {
+            let (position, normal, uv) = (vertex.position, vertex.normal, vertex.uv);
+            assert!(
+                (normal.length() - 1.0).abs() < 1e-5,
+                "{}: normal of length {}",
+                name,
+                normal.length()
+            );
+            assert!(
+                normal.dot(&outward(&position)) > 0.0,
+                "{}: normal {:?} at {:?} points inwards",
+                name,
+                normal,
+                position
+            );
+            assert!(
+                (0.0..=1.0).contains(&uv.x) && (0.0..=1.0).contains(&uv.y),
+                "{}: UV {:?} is outside the texture",
+                name,
+                uv
+            );
+        }
+        for triangle in mesh.triangles() {
+            assert!(faces_outward(&triangle), "{}: triangle faces inwards", name);
+        }
+    }
+
+    fn from_origin(position: &Vec3) -> Vec3 {
+        *position
+    }
+
+    #[test]
+    fn planes_face_up() {
+        for (columns, rows) in [(0, 0), (1, 1), (4, 3)] {
+            let mesh = Mesh::plane(3.0, 2.0, columns, rows);
+            check("plane", &mesh, |_| Vec3::new(0.0, 1.0, 0.0));
+            let squares = columns.max(1) * rows.max(1);
+            assert_eq!(mesh.indices.len(), 2 * squares);
+            assert_eq!((mesh.aabb.max.x, mesh.aabb.max.z), (1.5, 1.0));
+        }
+    }
+
+    #[test]
+    fn cubes_face_out() {
+        for subdivisions in [0, 1, 3] {
+            let mesh = Mesh::cube(2.0, subdivisions);
+            check("cube", &mesh, from_origin);
+            assert_eq!(mesh.indices.len(), 6 * 2 * subdivisions.max(1).pow(2));
+        }
+    }
+
+    #[test]
+    fn uv_spheres_face_out() {
+        for (segments, rings) in [(0, 0), (3, 2), (32, 16)] {
+            check(
+                "UV sphere",
+                &Mesh::uv_sphere(1.5, segments, rings),
+                from_origin,
+            );
+        }
+    }
+
+    #[test]
+    fn icospheres_face_out_and_are_cut_at_the_seam() {
+        for subdivisions in 0..4 {
+            let mesh = Mesh::icosphere(2.0, subdivisions);
+            check("icosphere", &mesh, from_origin);
+            for vertex in &mesh.vertices {
+                assert!((vertex.position.length() - 2.0).abs() < 1e-5);
+            }
+            // Triangles next to the seam would go all the way across the texture if they
+            // weren't cut. Near the poles, the first few subdivisions go far around anyway.
+            if subdivisions >= 2 {
+                for triangle in mesh.triangles() {
+                    let u = triangle.map(|vertex| vertex.uv.x);
+                    let span = u[0].max(u[1]).max(u[2]) - u[0].min(u[1]).min(u[2]);
+                    assert!(span < 0.5, "triangle across the seam: {:?}", u);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn cylinders_and_cones_face_out() {
+        for (segments, rings) in [(0, 0), (3, 1), (16, 4)] {
+            check(
+                "cylinder",
+                &Mesh::cylinder(1.0, 2.0, segments, rings),
+                from_origin,
+            );
+            check("cone", &Mesh::cone(1.0, 2.0, segments, rings), from_origin);
+        }
+    }
+
+    #[test]
+    fn tori_face_out_of_the_tube() {
+        for (segments, sides) in [(0, 0), (3, 3), (32, 16)] {
+            let mesh = Mesh::torus(0.75, 0.25, segments, sides);
+            check("torus", &mesh, |position| {
+                let around = Vec3::new(position.x, 0.0, position.z).normalize();
+                position.sub(&around.mul(0.75))
+            });
+        }
+    }
+
+    #[test]
+    fn capsules_face_out() {
+        for (segments, rings) in [(0, 0), (3, 1), (32, 8)] {
+            let mesh = Mesh::capsule(0.5, 1.0, segments, rings);
+            check("capsule", &mesh, from_origin);
+            assert!((mesh.aabb.max.y - 1.0).abs() < 1e-6);
+        }
+    }
+}
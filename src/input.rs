@@ -0,0 +1,352 @@
+use super::terminal;
+use std::io::Read;
+use std::sync::mpsc;
+use std::time::Duration;
+
+/// How long an escape, or the start of a sequence, at the end of the input waits for the
+/// rest of the sequence before it counts as keys typed on their own.
+pub const ESCAPE_TIMEOUT: Duration = Duration::from_millis(100);
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Key {
+    Char(char),
+    Up,
+    Down,
+    Left,
+    Right,
+    Tab,
+    Enter,
+    Escape,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum InputEvent {
+    Key(Key),
+    /// Mouse moved with the left button held, in character cells.
+    Drag {
+        dx: i32,
+        dy: i32,
+    },
+    /// Scroll wheel; positive is away from the user.
+    Scroll(i32),
+}
+
+/// Turns the raw bytes read from a terminal into input events.
+/// Escape sequences may be split across reads, so incomplete ones are kept until the next feed.
+/// That includes an escape on its own, which is only the escape key if nothing follows it
+/// in time, and `ESC [` or `ESC O`, which may just as well be typed; see `timeout`.
+#[derive(Default)]
+pub struct InputParser {
+    pending: Vec<u8>,
+    drag_origin: Option<(i32, i32)>,
+}
+
+enum Parsed {
+    Event(Option<InputEvent>, usize),
+    Incomplete,
+}
+
+impl InputParser {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    pub fn feed(&mut self, bytes: &[u8], events: &mut Vec<InputEvent>) {
+        let mut pending = std::mem::take(&mut self.pending);
+        pending.extend_from_slice(bytes);
+
+        let mut start = 0;
+        while start < pending.len() {
+            let (event, consumed) = match self.parse(&pending[start..]) {
+                Parsed::Event(event, consumed) => (event, consumed),
+                Parsed::Incomplete => break,
+            };
+
+            events.extend(event);
+            start += consumed;
+        }
+
+        pending.drain(..start);
+        self.pending = pending;
+    }
+
+    /// Whether the input ends in an escape or the start of a sequence, waiting for the rest.
+    pub fn waiting_for_escape(&self) -> bool {
+        !self.pending.is_empty()
+    }
+
+    /// Called when no more input arrived within `ESCAPE_TIMEOUT`. An escape still waiting
+    /// is the escape key after all, and so is one followed by only the `[` or `O` that
+    /// starts a sequence, which is then a key of its own. Whatever is left of a longer
+    /// sequence is dropped.
+    pub fn timeout(&mut self, events: &mut Vec<InputEvent>) {
+        let pending = std::mem::take(&mut self.pending);
+        if let [b'\x1b', rest @ ..] = pending.as_slice() {
+            if rest.len() <= 1 {
+                events.push(InputEvent::Key(Key::Escape));
+                self.feed(rest, events);
+            }
+        }
+    }
+
+    fn parse(&mut self, bytes: &[u8]) -> Parsed {
+        match bytes[0] {
+            b'\x1b' => match bytes.get(1) {
+                // Over a slow connection an arrow key may arrive in pieces, so
+                // wait and see whether the rest of a sequence follows.
+                None => Parsed::Incomplete,
+                Some(b'[') | Some(b'O') => self.parse_sequence(bytes),
+                Some(_) => Parsed::Event(Some(InputEvent::Key(Key::Escape)), 1),
+            },
+            b'\t' => Parsed::Event(Some(InputEvent::Key(Key::Tab)), 1),
+            b'\r' | b'\n' => Parsed::Event(Some(InputEvent::Key(Key::Enter)), 1),
+            byte if byte.is_ascii_graphic() || byte == b' ' => {
+                Parsed::Event(Some(InputEvent::Key(Key::Char(byte as char))), 1)
+            }
+            _ => Parsed::Event(None, 1),
+        }
+    }
+
+    fn parse_sequence(&mut self, bytes: &[u8]) -> Parsed {
+        // Skip parameter and intermediate bytes until the final byte of the sequence.
+        let mut end = 2;
+        loop {
+            match bytes.get(end) {
+                None => return Parsed::Incomplete,
+                Some(0x20..=0x3f) => end += 1,
+                Some(0x40..=0x7e) => break,
+                // Anything else can't be part of a sequence, so this one was cut off. Drop
+                // what there is of it and go on from there.
+                Some(_) => return Parsed::Event(None, end),
+            }
+        }
+
+        if bytes[1] == b'[' && bytes[2] == b'<' {
+            return self.parse_mouse(bytes, end);
+        }
+
+        let key = match bytes[end] {
+            b'A' => Some(Key::Up),
+            b'B' => Some(Key::Down),
+            b'C' => Some(Key::Right),
+            b'D' => Some(Key::Left),
+            _ => None,
+        };
+
+        Parsed::Event(key.map(InputEvent::Key), end + 1)
+    }
+
+    /// Parses an SGR mouse report, `ESC [ < button ; x ; y M` (or `m` on release), which
+    /// ends at `end`.
+    fn parse_mouse(&mut self, bytes: &[u8], end: usize) -> Parsed {
+        if bytes[end] != b'M' && bytes[end] != b'm' {
+            return Parsed::Event(None, end + 1);
+        }
+
+        let fields: Vec<i32> = std::str::from_utf8(&bytes[3..end])
+            .unwrap_or("")
+            .split(';')
+            .filter_map(|field| field.parse().ok())
+            .collect();
+
+        let event = match (fields.as_slice(), bytes[end]) {
+            (&[button, ..], _) if button & 64 != 0 => {
+                Some(InputEvent::Scroll(if button & 1 == 0 { 1 } else { -1 }))
+            }
+            (&[button, x, y], b'M') if button & 3 == 0 => {
+                if button & 32 != 0 {
+                    self.drag_to(x, y)
+                } else {
+                    self.drag_origin = Some((x, y));
+                    None
+                }
+            }
+            (_, b'm') => {
+                self.drag_origin = None;
+                None
+            }
+            _ => None,
+        };
+
+        Parsed::Event(event, end + 1)
+    }
+
+    fn drag_to(&mut self, x: i32, y: i32) -> Option<InputEvent> {
+        let origin = self.drag_origin.replace((x, y));
+        origin.map(|(ox, oy)| InputEvent::Drag {
+            dx: x - ox,
+            dy: y - oy,
+        })
+    }
+}
+
+/// Reads stdin on a background thread and sends parsed events over the returned channel.
+/// The thread ends when stdin closes or the receiver is dropped.
+pub fn spawn_reader() -> mpsc::Receiver<InputEvent> {
+    let (sender, receiver) = mpsc::channel();
+
+    std::thread::spawn(move || {
+        let mut parser = InputParser::new();
+        let mut stdin = std::io::stdin();
+        let mut buffer = [0u8; 256];
+        let mut events = vec![];
+
+        loop {
+            if parser.waiting_for_escape() && !terminal::wait_for_input(ESCAPE_TIMEOUT) {
+                parser.timeout(&mut events);
+            }
+            for event in events.drain(..) {
+                if sender.send(event).is_err() {
+                    return;
+                }
+            }
+
+            let n = match stdin.read(&mut buffer) {
+                Ok(0) | Err(_) => break,
+                Ok(n) => n,
+            };
+
+            parser.feed(&buffer[..n], &mut events);
+        }
+    });
+
+    receiver
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(chunks: &[&[u8]]) -> Vec<InputEvent> {
+        let mut parser = InputParser::new();
+        let mut events = vec![];
+        for chunk in chunks {
+            parser.feed(chunk, &mut events);
+        }
+        events
+    }
+
+    fn key(key: Key) -> InputEvent {
+        InputEvent::Key(key)
+    }
+
+    #[test]
+    fn parses_keys() {
+        assert_eq!(
+            parse(&[b"a \t\r\x1b[A\x1b[B\x1bOC\x1b[1;5D"]),
+            vec![
+                key(Key::Char('a')),
+                key(Key::Char(' ')),
+                key(Key::Tab),
+                key(Key::Enter),
+                key(Key::Up),
+                key(Key::Down),
+                key(Key::Right),
+                key(Key::Left),
+            ]
+        );
+    }
+
+    #[test]
+    fn ignores_unknown_sequences_and_control_characters() {
+        assert_eq!(parse(&[b"\x01\x1b[2~x"]), vec![key(Key::Char('x'))]);
+    }
+
+    #[test]
+    fn joins_sequences_split_across_reads() {
+        assert_eq!(parse(&[b"\x1b", b"[", b"A"]), vec![key(Key::Up)]);
+        assert_eq!(
+            parse(&[b"\x1b[<64;1", b"0;5M"]),
+            vec![InputEvent::Scroll(1)]
+        );
+    }
+
+    #[test]
+    fn trailing_escape_waits_for_timeout() {
+        let mut parser = InputParser::new();
+        let mut events = vec![];
+
+        parser.feed(b"\x1b", &mut events);
+        assert!(events.is_empty());
+        assert!(parser.waiting_for_escape());
+
+        parser.timeout(&mut events);
+        assert_eq!(events, vec![key(Key::Escape)]);
+        assert!(!parser.waiting_for_escape());
+
+        // Nothing is waiting any more, so a second timeout does nothing.
+        parser.timeout(&mut events);
+        assert_eq!(events.len(), 1);
+    }
+
+    #[test]
+    fn start_of_a_sequence_waits_for_timeout() {
+        for &(start, typed) in &[(b"\x1b[", '['), (b"\x1bO", 'O')] {
+            let mut parser = InputParser::new();
+            let mut events = vec![];
+
+            parser.feed(start, &mut events);
+            assert!(events.is_empty());
+            assert!(parser.waiting_for_escape());
+
+            parser.timeout(&mut events);
+            assert_eq!(events, vec![key(Key::Escape), key(Key::Char(typed))]);
+            assert!(!parser.waiting_for_escape());
+        }
+    }
+
+    #[test]
+    fn unfinished_sequence_is_dropped_on_timeout() {
+        let mut parser = InputParser::new();
+        let mut events = vec![];
+
+        parser.feed(b"\x1b[<0;10", &mut events);
+        assert!(parser.waiting_for_escape());
+        parser.timeout(&mut events);
+        assert!(!parser.waiting_for_escape());
+
+        parser.feed(b"a", &mut events);
+        assert_eq!(events, vec![key(Key::Char('a'))]);
+    }
+
+    #[test]
+    fn mouse_reports_end_where_their_parameters_do() {
+        // Keys typed after a cut off report aren't taken as the rest of it.
+        assert_eq!(
+            parse(&[b"\x1b[<0;5", b"\rq", b"M"]),
+            vec![key(Key::Enter), key(Key::Char('q')), key(Key::Char('M'))]
+        );
+        assert_eq!(
+            parse(&[b"\x1b[<64;1;1~x\x1b[<64;1;1M"]),
+            vec![key(Key::Char('x')), InputEvent::Scroll(1)]
+        );
+    }
+
+    #[test]
+    fn escape_followed_by_other_input_is_the_escape_key() {
+        assert_eq!(
+            parse(&[b"\x1bq"]),
+            vec![key(Key::Escape), key(Key::Char('q'))]
+        );
+    }
+
+    #[test]
+    fn mouse_drags_and_scrolling() {
+        assert_eq!(
+            parse(&[b"\x1b[<0;10;5M\x1b[<32;12;4M\x1b[<32;13;4M\x1b[<0;13;4m\x1b[<65;1;1M"]),
+            vec![
+                InputEvent::Drag { dx: 2, dy: -1 },
+                InputEvent::Drag { dx: 1, dy: 0 },
+                InputEvent::Scroll(-1),
+            ]
+        );
+    }
+
+    #[test]
+    fn drag_after_release_starts_over() {
+        assert_eq!(
+            parse(&[b"\x1b[<0;1;1M\x1b[<0;1;1m\x1b[<32;5;5M\x1b[<32;6;5M"]),
+            vec![InputEvent::Drag { dx: 1, dy: 0 }]
+        );
+    }
+}
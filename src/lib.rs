@@ -0,0 +1,22 @@
+extern crate ctrlc;
+extern crate failure;
+
+pub mod camera;
+pub mod display_device;
+pub mod geometry;
+pub mod input;
+pub mod loaders;
+pub mod math;
+pub mod object;
+pub mod rasterizer;
+pub mod render_target;
+pub mod renderer;
+pub mod scene;
+pub mod shaders;
+pub mod terminal;
+#[cfg(test)]
+mod testing;
+pub mod text;
+pub mod texture;
+pub mod video;
+pub mod watch;
@@ -0,0 +1,127 @@
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+/// What went wrong with a line of an OBJ or MTL file.
+#[derive(Debug)]
+pub enum ErrorKind {
+    /// The file couldn't be read.
+    Io(std::io::Error),
+    /// A directive that isn't part of the format.
+    UnknownDirective,
+    /// A directive is missing a value, such as "Y coordinate".
+    MissingValue(&'static str),
+    /// A value that should be a number but isn't.
+    InvalidNumber,
+    /// An index of zero, which isn't valid since indices start at 1.
+    ZeroIndex(&'static str),
+    /// An index past the elements of its kind declared so far.
+    IndexOutOfBounds { kind: &'static str, count: usize },
+    /// Anything else wrong with the values of a directive.
+    Invalid(String),
+    /// A texture that couldn't be loaded.
+    Texture(failure::Error),
+}
+
+/// An error in an OBJ or MTL file, with where it is.
+#[derive(Debug)]
+pub struct LoadError {
+    pub path: PathBuf,
+    /// Line number, starting at 1, unless the error is about the whole file.
+    pub line: Option<usize>,
+    /// Column of the token, starting at 1.
+    pub column: Option<usize>,
+    /// The word on the line the error is about.
+    pub token: Option<String>,
+    pub kind: ErrorKind,
+}
+
+impl LoadError {
+    pub fn io(path: &Path, error: std::io::Error) -> Self {
+        LoadError {
+            path: path.to_owned(),
+            line: None,
+            column: None,
+            token: None,
+            kind: ErrorKind::Io(error),
+        }
+    }
+}
+
+impl fmt::Display for LoadError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.path.display())?;
+        if let Some(line) = self.line {
+            write!(f, ":{}", line)?;
+        }
+        if let Some(column) = self.column {
+            write!(f, ":{}", column)?;
+        }
+
+        let token = self.token.as_deref().unwrap_or("");
+        match &self.kind {
+            ErrorKind::Io(error) => write!(f, ": {}", error),
+            ErrorKind::UnknownDirective => write!(f, ": unknown directive {:?}", token),
+            ErrorKind::MissingValue(what) => write!(f, ": no {}", what),
+            ErrorKind::InvalidNumber => write!(f, ": invalid number {:?}", token),
+            ErrorKind::ZeroIndex(kind) => write!(f, ": {} index 0; indices start at 1", kind),
+            ErrorKind::IndexOutOfBounds { kind, count } => write!(
+                f,
+                ": {} index {} out of bounds; there are {} so far",
+                kind, token, count
+            ),
+            ErrorKind::Invalid(message) => write!(f, ": {}", message),
+            ErrorKind::Texture(error) => write!(f, ": {}", error),
+        }
+    }
+}
+
+impl std::error::Error for LoadError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match &self.kind {
+            ErrorKind::Io(error) => Some(error),
+            _ => None,
+        }
+    }
+}
+
+/// An error found while parsing a line, before it's known which line that is.
+pub(super) struct Problem<'a> {
+    pub kind: ErrorKind,
+    pub token: Option<&'a str>,
+}
+
+impl<'a> Problem<'a> {
+    pub fn new(kind: ErrorKind, token: &'a str) -> Self {
+        Problem {
+            kind,
+            token: Some(token),
+        }
+    }
+
+    /// Places the problem on a line of a file. The token should be a slice of the line,
+    /// so that its column can be found.
+    pub fn at(self, path: &Path, line_number: usize, line: &str) -> LoadError {
+        let column = self.token.and_then(|token| {
+            let offset = (token.as_ptr() as usize).checked_sub(line.as_ptr() as usize)?;
+            if offset + token.len() <= line.len() && line.is_char_boundary(offset) {
+                Some(line[..offset].chars().count() + 1)
+            } else {
+                None
+            }
+        });
+
+        LoadError {
+            path: path.to_owned(),
+            line: Some(line_number),
+            column,
+            token: self.token.map(str::to_owned),
+            kind: self.kind,
+        }
+    }
+}
+
+impl From<ErrorKind> for Problem<'_> {
+    fn from(kind: ErrorKind) -> Self {
+        Problem { kind, token: None }
+    }
+}
@@ -0,0 +1,1068 @@
+use crate::geometry::*;
+use crate::math::*;
+use crate::texture::{Filter, Texture, Wrap};
+use serde_json::Value;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use ::failure::format_err;
+use ::failure::Error;
+
+const GLB_MAGIC: &[u8] = b"glTF";
+const GLB_JSON_CHUNK: u32 = 0x4e4f_534a;
+const GLB_BIN_CHUNK: u32 = 0x004e_4942;
+
+/// Deeper node hierarchies than this are taken to be cyclic.
+const MAX_DEPTH: usize = 256;
+
+/// Loads a glTF 2.0 model, either a `.gltf` file (with its buffers and images next to it
+/// or embedded as data URIs) or a binary `.glb` file.
+///
+/// Every primitive of every mesh in the scene becomes a part, with the node transforms
+/// applied. Materials get the PBR base color, its texture, and the emissive color.
+pub fn load(path: &Path) -> Result<Model, Error> {
+    load_with_warnings(path).map(|(model, _)| model)
+}
+
+/// Loads a model like `load`, along with warnings about textures that couldn't be loaded
+/// and were left out.
+pub fn load_with_warnings(path: &Path) -> Result<(Model, Vec<Error>), Error> {
+    load_with_sources(path, &mut vec![])
+}
+
+/// Loads a model like `load_with_warnings`, adding the file and the buffers and images it
+/// refers to, to `sources` before reading them, so that they're there even if loading fails.
+pub fn load_with_sources(
+    path: &Path,
+    sources: &mut Vec<PathBuf>,
+) -> Result<(Model, Vec<Error>), Error> {
+    let start = sources.len();
+    sources.push(path.to_owned());
+    let data = std::fs::read(path)?;
+
+    let (json, binary) = if data.starts_with(GLB_MAGIC) {
+        parse_glb(&data)?
+    } else {
+        (&data[..], None)
+    };
+
+    let json: Value = serde_json::from_slice(json)
+        .map_err(|e| format_err!("invalid glTF JSON in {}: {}", path.display(), e))?;
+
+    let version = json["asset"]["version"].as_str().unwrap_or("");
+    if !version.starts_with("2.") {
+        return Err(format_err!(
+            "unsupported glTF version {:?}; only 2.0 is supported",
+            version
+        ));
+    }
+
+    // Extensions that are only used can be ignored, but required ones change what the
+    // data means, so we can't just carry on without them.
+    let required: Vec<&str> = array(&json["extensionsRequired"])
+        .iter()
+        .filter_map(Value::as_str)
+        .collect();
+    if !required.is_empty() {
+        return Err(format_err!(
+            "unsupported glTF extensions required: {}",
+            required.join(", ")
+        ));
+    }
+
+    // Data URIs are part of the file, so only other URIs are files of their own.
+    for item in array(&json["buffers"]).iter().chain(array(&json["images"])) {
+        if let Some(uri) = item["uri"].as_str().filter(|uri| !uri.starts_with("data:")) {
+            sources.push(path.with_file_name(percent_decode(uri)));
+        }
+    }
+
+    let mut document = Document {
+        json: &json,
+        path,
+        buffers: vec![],
+        textures: HashMap::new(),
+        warnings: vec![],
+    };
+    document.buffers = array(&json["buffers"])
+        .iter()
+        .enumerate()
+        .map(|(i, buffer)| document.load_buffer(i, buffer, binary))
+        .collect::<Result<_, _>>()?;
+
+    let materials = array(&json["materials"])
+        .iter()
+        .map(|material| document.material(material))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let mut parts = vec![];
+    for node in document.scene_nodes()? {
+        document.add_node(node, &identity(), 0, &materials, &mut parts)?;
+    }
+
+    let model = Model {
+        parts,
+        materials,
+        sources: sources[start..].to_vec(),
+    };
+    Ok((model, document.warnings))
+}
+
+/// Splits a binary glTF file into its JSON and binary chunks.
+fn parse_glb(data: &[u8]) -> Result<(&[u8], Option<&[u8]>), Error> {
+    let u32_at = |offset: usize| -> Result<u32, Error> {
+        data.get(offset..offset + 4)
+            .map(|b| u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+            .ok_or(format_err!("GLB file is cut short"))
+    };
+
+    let version = u32_at(4)?;
+    if version != 2 {
+        return Err(format_err!("unsupported GLB version {}", version));
+    }
+    let length = (u32_at(8)? as usize).min(data.len());
+
+    let mut json = None;
+    let mut binary = None;
+    let mut offset = 12;
+    while offset + 8 <= length {
+        let chunk_length = u32_at(offset)? as usize;
+        let chunk_type = u32_at(offset + 4)?;
+        let chunk = data
+            .get(offset + 8..offset + 8 + chunk_length)
+            .ok_or(format_err!("GLB chunk is cut short"))?;
+
+        match chunk_type {
+            GLB_JSON_CHUNK if json.is_none() => json = Some(chunk),
+            GLB_BIN_CHUNK if binary.is_none() => binary = Some(chunk),
+            // Unknown chunks are meant to be skipped.
+            _ => {}
+        }
+
+        // Chunks are padded to 4 bytes.
+        offset += 8 + chunk_length.div_ceil(4) * 4;
+    }
+
+    Ok((
+        json.ok_or(format_err!("GLB file has no JSON chunk"))?,
+        binary,
+    ))
+}
+
+struct Document<'a> {
+    json: &'a Value,
+    path: &'a Path,
+    buffers: Vec<Vec<u8>>,
+    /// Textures by image and sampler index, since the sampler decides the filter and wrap mode.
+    textures: HashMap<(usize, Option<usize>), Arc<Texture>>,
+    warnings: Vec<Error>,
+}
+
+/// The data of an accessor, with every component as a float.
+struct Accessor {
+    values: Vec<f64>,
+    components: usize,
+    component_type: u64,
+    normalized: bool,
+}
+
+impl Accessor {
+    fn count(&self) -> usize {
+        self.values.len() / self.components
+    }
+
+    /// Returns the value of a component, mapping normalized integers to between 0 and 1
+    /// (or -1 and 1 for signed ones). `force` does so even if the accessor isn't marked
+    /// normalized, for attributes where integers can only mean that.
+    fn get(&self, element: usize, component: usize, force: bool) -> f32 {
+        let value = self.values[element * self.components + component];
+        if !(self.normalized || force) {
+            return value as f32;
+        }
+
+        (match self.component_type {
+            5120 => (value / 127.0).max(-1.0),
+            5121 => value / 255.0,
+            5122 => (value / 32767.0).max(-1.0),
+            5123 => value / 65535.0,
+            _ => value,
+        }) as f32
+    }
+}
+
+impl<'a> Document<'a> {
+    fn load_buffer(
+        &self,
+        index: usize,
+        buffer: &Value,
+        binary: Option<&[u8]>,
+    ) -> Result<Vec<u8>, Error> {
+        let length = buffer["byteLength"].as_u64().unwrap_or(0) as usize;
+
+        let data = match buffer["uri"].as_str() {
+            Some(uri) => self.load_uri(uri)?,
+            // The first buffer of a GLB file refers to the binary chunk.
+            None if index == 0 => binary
+                .ok_or(format_err!(
+                    "buffer 0 has no URI and there's no binary chunk"
+                ))?
+                .to_vec(),
+            None => return Err(format_err!("buffer {} has no URI", index)),
+        };
+
+        if data.len() < length {
+            return Err(format_err!(
+                "buffer {} is {} bytes long, but should be {}",
+                index,
+                data.len(),
+                length
+            ));
+        }
+        Ok(data)
+    }
+
+    /// Reads a data URI, or a file relative to the glTF file.
+    fn load_uri(&self, uri: &str) -> Result<Vec<u8>, Error> {
+        if uri.starts_with("data:") {
+            let comma = uri.find(',').ok_or(format_err!("invalid data URI"))?;
+            if !uri[..comma].ends_with(";base64") {
+                return Err(format_err!("only base64 data URIs are supported"));
+            }
+            return base64::decode(&uri[comma + 1..])
+                .map_err(|e| format_err!("invalid base64 data URI: {}", e));
+        }
+
+        let path = self.path.with_file_name(percent_decode(uri));
+        std::fs::read(&path).map_err(|e| format_err!("could not read {}: {}", path.display(), e))
+    }
+
+    fn accessor(&self, index: &Value) -> Result<Accessor, Error> {
+        let i = index
+            .as_u64()
+            .ok_or(format_err!("invalid accessor index {}", index))? as usize;
+        let accessor = array(&self.json["accessors"])
+            .get(i)
+            .ok_or(format_err!("accessor {} doesn't exist", i))?;
+
+        if !accessor["sparse"].is_null() {
+            return Err(format_err!("sparse accessors aren't supported"));
+        }
+
+        let count = accessor["count"].as_u64().unwrap_or(0) as usize;
+        let component_type = accessor["componentType"].as_u64().unwrap_or(0);
+        let components = match accessor["type"].as_str() {
+            Some("SCALAR") => 1,
+            Some("VEC2") => 2,
+            Some("VEC3") => 3,
+            Some("VEC4") => 4,
+            Some("MAT2") => 4,
+            Some("MAT3") => 9,
+            Some("MAT4") => 16,
+            t => return Err(format_err!("accessor {} has unknown type {:?}", i, t)),
+        };
+        let component_size = match component_type {
+            5120 | 5121 => 1,
+            5122 | 5123 => 2,
+            5125 | 5126 => 4,
+            t => {
+                return Err(format_err!(
+                    "accessor {} has unknown component type {}",
+                    i,
+                    t
+                ))
+            }
+        };
+
+        // Without a buffer view, everything is zero, which is only useful along with
+        // sparse data.
+        let view = accessor["bufferView"]
+            .as_u64()
+            .ok_or(format_err!("accessor {} has no buffer view", i))? as usize;
+        let view = array(&self.json["bufferViews"])
+            .get(view)
+            .ok_or(format_err!("buffer view {} doesn't exist", view))?;
+        let buffer = view["buffer"].as_u64().unwrap_or(0) as usize;
+        let buffer = self
+            .buffers
+            .get(buffer)
+            .ok_or(format_err!("buffer {} doesn't exist", buffer))?;
+
+        let view_data = buffer_view_data(buffer, view).ok_or(format_err!(
+            "buffer view of accessor {} is out of bounds",
+            i
+        ))?;
+
+        let offset = accessor["byteOffset"].as_u64().unwrap_or(0) as usize;
+        let element_size = components * component_size;
+        let stride = match view["byteStride"].as_u64() {
+            None => element_size,
+            // Elements mustn't overlap, or any count would fit in a single element.
+            Some(stride) if (4..=252).contains(&stride) && stride % 4 == 0 => {
+                let stride = stride as usize;
+                if stride < element_size {
+                    return Err(format_err!(
+                        "accessor {} has elements of {} bytes, which don't fit in a stride of {}",
+                        i,
+                        element_size,
+                        stride
+                    ));
+                }
+                stride
+            }
+            Some(stride) => {
+                return Err(format_err!(
+                    "accessor {} has an invalid byte stride {}",
+                    i,
+                    stride
+                ))
+            }
+        };
+
+        // The count comes straight from the file, so check that the elements are really
+        // there before allocating anything for them.
+        let end = match count {
+            0 => Some(0),
+            count => (count - 1)
+                .checked_mul(stride)
+                .and_then(|span| span.checked_add(offset))
+                .and_then(|start| start.checked_add(element_size)),
+        };
+        if end.is_none_or(|end| end > view_data.len()) {
+            return Err(format_err!("accessor {} is out of bounds", i));
+        }
+
+        let mut values = Vec::with_capacity(count * components);
+        for element in 0..count {
+            for component in 0..components {
+                let at = offset + element * stride + component * component_size;
+                let b = &view_data[at..at + component_size];
+                values.push(match component_type {
+                    5120 => b[0] as i8 as f64,
+                    5121 => b[0] as f64,
+                    5122 => i16::from_le_bytes([b[0], b[1]]) as f64,
+                    5123 => u16::from_le_bytes([b[0], b[1]]) as f64,
+                    5125 => u32::from_le_bytes([b[0], b[1], b[2], b[3]]) as f64,
+                    _ => f32::from_le_bytes([b[0], b[1], b[2], b[3]]) as f64,
+                });
+            }
+        }
+
+        Ok(Accessor {
+            values,
+            components,
+            component_type,
+            normalized: accessor["normalized"].as_bool().unwrap_or(false),
+        })
+    }
+
+    fn material(&mut self, material: &Value) -> Result<Material, Error> {
+        let pbr = &material["pbrMetallicRoughness"];
+        let mut result = Material::new(material["name"].as_str().unwrap_or("material"));
+
+        let base_color = floats(&pbr["baseColorFactor"]);
+        if base_color.len() == 4 {
+            result.diffuse = Vec3::new(base_color[0], base_color[1], base_color[2]);
+            result.opacity = base_color[3];
+        } else {
+            result.diffuse = Vec3::new(1.0, 1.0, 1.0);
+        }
+
+        let emissive = floats(&material["emissiveFactor"]);
+        if emissive.len() == 3 {
+            result.emissive = Vec3::new(emissive[0], emissive[1], emissive[2]);
+        }
+
+        // There's no metalness or roughness in our materials, so approximate them with highlights:
+        // metals reflect their own color, and rough surfaces have broad and dim highlights.
+        let metallic = pbr["metallicFactor"].as_f64().unwrap_or(1.0) as f32;
+        let roughness = pbr["roughnessFactor"].as_f64().unwrap_or(1.0) as f32;
+        let dielectric = Vec3::new(0.04, 0.04, 0.04);
+        result.specular = dielectric
+            .mul(1.0 - metallic)
+            .add(&result.diffuse.mul(metallic))
+            .mul(1.0 - roughness);
+        result.shininess = 2.0 / roughness.max(0.05).powi(4) - 2.0;
+        result.illumination = 2;
+        // With an opaque alpha mode, the alpha channel is meaningless.
+        if material["alphaMode"].as_str().unwrap_or("OPAQUE") == "OPAQUE" {
+            result.opacity = 1.0;
+        }
+
+        // A texture that can't be used leaves the material untextured, rather than the
+        // whole model unloadable; JPEG images, for one, can't be decoded.
+        if let Some(texture) = pbr["baseColorTexture"]["index"].as_u64() {
+            let texture = if pbr["baseColorTexture"]["texCoord"].as_u64().unwrap_or(0) != 0 {
+                Err(format_err!(
+                    "only the first set of texture coordinates is supported"
+                ))
+            } else {
+                self.texture(texture as usize)
+            };
+            match texture {
+                Ok(texture) => result.diffuse_texture = Some(texture),
+                Err(e) => self.warnings.push(format_err!(
+                    "{}: material {:?}: {}",
+                    self.path.display(),
+                    result.name,
+                    e
+                )),
+            }
+        }
+
+        Ok(result)
+    }
+
+    fn texture(&mut self, index: usize) -> Result<Arc<Texture>, Error> {
+        let texture = array(&self.json["textures"])
+            .get(index)
+            .ok_or(format_err!("texture {} doesn't exist", index))?;
+        let image = texture["source"]
+            .as_u64()
+            .ok_or(format_err!("texture {} has no image", index))? as usize;
+        let sampler = texture["sampler"].as_u64().map(|sampler| sampler as usize);
+
+        if let Some(texture) = self.textures.get(&(image, sampler)) {
+            return Ok(texture.clone());
+        }
+
+        let mut texture = self.image(image)?;
+        if let Some(sampler) =
+            sampler.and_then(|sampler| array(&self.json["samplers"]).get(sampler))
+        {
+            texture.filter = match sampler["magFilter"].as_u64() {
+                Some(9728) => Filter::Nearest,
+                _ => Filter::Bilinear,
+            };
+            // Both axes share a wrap mode here, so go by the horizontal one.
+            texture.wrap = match sampler["wrapS"].as_u64() {
+                Some(33071) => Wrap::Clamp,
+                Some(33648) => Wrap::Mirror,
+                _ => Wrap::Repeat,
+            };
+        }
+
+        let texture = Arc::new(texture);
+        self.textures.insert((image, sampler), texture.clone());
+        Ok(texture)
+    }
+
+    fn image(&self, index: usize) -> Result<Texture, Error> {
+        let image = array(&self.json["images"])
+            .get(index)
+            .ok_or(format_err!("image {} doesn't exist", index))?;
+
+        let (data, format) = if let Some(uri) = image["uri"].as_str() {
+            let format = if uri.starts_with("data:") {
+                mime_format(&uri[5..uri.find([';', ',']).unwrap_or(5)])
+            } else {
+                Path::new(uri)
+                    .extension()
+                    .and_then(|extension| extension.to_str())
+                    .unwrap_or("")
+            };
+            (self.load_uri(uri)?, format)
+        } else {
+            let view = image["bufferView"].as_u64().ok_or(format_err!(
+                "image {} has neither a URI nor a buffer view",
+                index
+            ))? as usize;
+            let view = array(&self.json["bufferViews"])
+                .get(view)
+                .ok_or(format_err!("buffer view {} doesn't exist", view))?;
+            let buffer = view["buffer"].as_u64().unwrap_or(0) as usize;
+            let data = self
+                .buffers
+                .get(buffer)
+                .and_then(|buffer| buffer_view_data(buffer, view))
+                .ok_or(format_err!("image {} is out of bounds", index))?;
+            (
+                data.to_vec(),
+                mime_format(image["mimeType"].as_str().unwrap_or("")),
+            )
+        };
+
+        Texture::decode(&data, format)
+            .map_err(|e| format_err!("could not load image {}: {}", index, e))
+    }
+
+    /// Returns the root nodes of the scene to show.
+    fn scene_nodes(&self) -> Result<Vec<usize>, Error> {
+        let scenes = array(&self.json["scenes"]);
+        let scene = self.json["scene"].as_u64().unwrap_or(0) as usize;
+
+        match scenes.get(scene) {
+            Some(scene) => Ok(array(&scene["nodes"])
+                .iter()
+                .filter_map(Value::as_u64)
+                .map(|node| node as usize)
+                .collect()),
+            None if scenes.is_empty() => {
+                // Without scenes, show every node that isn't a child of another.
+                let nodes = array(&self.json["nodes"]);
+                let children: Vec<u64> = nodes
+                    .iter()
+                    .flat_map(|node| array(&node["children"]))
+                    .filter_map(Value::as_u64)
+                    .collect();
+                Ok((0..nodes.len())
+                    .filter(|node| !children.contains(&(*node as u64)))
+                    .collect())
+            }
+            None => Err(format_err!("scene {} doesn't exist", scene)),
+        }
+    }
+
+    fn add_node(
+        &self,
+        index: usize,
+        parent: &Mat4x4,
+        depth: usize,
+        materials: &[Material],
+        parts: &mut Vec<Part>,
+    ) -> Result<(), Error> {
+        if depth > MAX_DEPTH {
+            return Err(format_err!("node hierarchy is cyclic"));
+        }
+
+        let node = array(&self.json["nodes"])
+            .get(index)
+            .ok_or(format_err!("node {} doesn't exist", index))?;
+        let transform = parent.mat_mul(&node_transform(node));
+
+        if let Some(mesh) = node["mesh"].as_u64() {
+            let mesh = array(&self.json["meshes"])
+                .get(mesh as usize)
+                .ok_or(format_err!("mesh {} doesn't exist", mesh))?;
+            let name = node["name"]
+                .as_str()
+                .or_else(|| mesh["name"].as_str())
+                .map(str::to_owned)
+                .unwrap_or_else(|| format!("node {}", index));
+
+            let primitives = array(&mesh["primitives"]);
+            for (i, primitive) in primitives.iter().enumerate() {
+                let mesh = self
+                    .primitive(primitive, &transform, materials)
+                    .map_err(|e| format_err!("in mesh {:?}: {}", name, e))?;
+                parts.push(Part {
+                    name: if primitives.len() > 1 {
+                        format!("{}/{}", name, i)
+                    } else {
+                        name.clone()
+                    },
+                    mesh,
+                });
+            }
+        }
+
+        for child in array(&node["children"]).iter().filter_map(Value::as_u64) {
+            self.add_node(child as usize, &transform, depth + 1, materials, parts)?;
+        }
+
+        Ok(())
+    }
+
+    fn primitive(
+        &self,
+        primitive: &Value,
+        transform: &Mat4x4,
+        materials: &[Material],
+    ) -> Result<Mesh, Error> {
+        let attributes = &primitive["attributes"];
+        let positions = self.accessor(&attributes["POSITION"])?;
+        let count = positions.count();
+
+        let optional = |name: &str| -> Result<Option<Accessor>, Error> {
+            if attributes[name].is_null() {
+                return Ok(None);
+            }
+            let accessor = self.accessor(&attributes[name])?;
+            if accessor.count() < count {
+                return Err(format_err!("{} has fewer elements than POSITION", name));
+            }
+            Ok(Some(accessor))
+        };
+        let normals = optional("NORMAL")?;
+        let uvs = optional("TEXCOORD_0")?;
+        let colors = optional("COLOR_0")?;
+
+        let material = primitive["material"]
+            .as_u64()
+            .map(|material| material as usize);
+        let base_color = match material.and_then(|material| materials.get(material)) {
+            Some(material) => Vec4::from_vec3(material.diffuse, material.opacity),
+            None => Vec4::new(1.0, 1.0, 1.0, 1.0),
+        };
+
+        let normal_transform = normal_matrix(transform);
+        let vertices: Vec<Vertex> = (0..count)
+            .map(|i| {
+                let position = Vec4::new(
+                    positions.get(i, 0, false),
+                    positions.get(i, 1, false),
+                    positions.get(i, 2, false),
+                    1.0,
+                );
+                let normal = normals.as_ref().map(|normals| {
+                    let normal = Vec4::new(
+                        normals.get(i, 0, false),
+                        normals.get(i, 1, false),
+                        normals.get(i, 2, false),
+                        0.0,
+                    );
+                    normal_transform.mul(&normal).xyz().normalize()
+                });
+                // glTF puts the origin of textures at the top left rather than the bottom left.
+                let uv = uvs
+                    .as_ref()
+                    .map(|uvs| Vec2::new(uvs.get(i, 0, true), 1.0 - uvs.get(i, 1, true)))
+                    .unwrap_or(Vec2::new(0.0, 0.0));
+                let color = colors
+                    .as_ref()
+                    .map(|colors| {
+                        let alpha = if colors.components == 4 {
+                            colors.get(i, 3, true)
+                        } else {
+                            1.0
+                        };
+                        Vec4::new(
+                            colors.get(i, 0, true) * base_color.x,
+                            colors.get(i, 1, true) * base_color.y,
+                            colors.get(i, 2, true) * base_color.z,
+                            alpha * base_color.w,
+                        )
+                    })
+                    .unwrap_or(base_color);
+
+                Vertex {
+                    position: transform.mul(&position).xyz(),
+                    uv,
+                    normal: normal.unwrap_or(Vec3::new(0.0, 0.0, 0.0)),
+                    color,
+                }
+            })
+            .collect();
+
+        let indices: Vec<usize> = match primitive["indices"] {
+            Value::Null => (0..count).collect(),
+            ref indices => {
+                let indices = self.accessor(indices)?;
+                indices.values.iter().map(|&index| index as usize).collect()
+            }
+        };
+        if count > u32::MAX as usize {
+            return Err(format_err!("primitive has too many vertices"));
+        }
+        if let Some(index) = indices.iter().find(|&&index| index >= count) {
+            return Err(format_err!("vertex index {} out of bounds", index));
+        }
+
+        let corners: Vec<[usize; 3]> = match primitive["mode"].as_u64().unwrap_or(4) {
+            4 => indices
+                .chunks_exact(3)
+                .map(|t| [t[0], t[1], t[2]])
+                .collect(),
+            // Every other triangle of a strip is wound the other way around.
+            5 => (2..indices.len())
+                .map(|i| {
+                    if i % 2 == 0 {
+                        [indices[i - 2], indices[i - 1], indices[i]]
+                    } else {
+                        [indices[i - 1], indices[i - 2], indices[i]]
+                    }
+                })
+                .collect(),
+            6 => (2..indices.len())
+                .map(|i| [indices[0], indices[i - 1], indices[i]])
+                .collect(),
+            mode => {
+                return Err(format_err!(
+                    "primitive mode {} isn't supported; only triangles are",
+                    mode
+                ))
+            }
+        };
+
+        let mut mesh = Mesh::new(
+            vertices,
+            corners
+                .iter()
+                .map(|&[a, b, c]| [a as u32, b as u32, c as u32])
+                .collect(),
+            material,
+        );
+
+        // Without normals, glTF asks for flat shading.
+        if normals.is_none() {
+            let flat = NormalOptions {
+                smooth: false,
+                ..NormalOptions::default()
+            };
+            mesh.generate_normals(&flat, None);
+        }
+
+        Ok(mesh)
+    }
+}
+
+/// The part of a buffer a buffer view covers, unless it's out of bounds.
+fn buffer_view_data<'b>(buffer: &'b [u8], view: &Value) -> Option<&'b [u8]> {
+    let offset = view["byteOffset"].as_u64().unwrap_or(0) as usize;
+    let length = view["byteLength"].as_u64().unwrap_or(0) as usize;
+    buffer.get(offset..offset.checked_add(length)?)
+}
+
+fn array(value: &Value) -> &[Value] {
+    value.as_array().map(Vec::as_slice).unwrap_or(&[])
+}
+
+fn floats(value: &Value) -> Vec<f32> {
+    array(value)
+        .iter()
+        .filter_map(Value::as_f64)
+        .map(|value| value as f32)
+        .collect()
+}
+
+/// Maps an image MIME type to the file extension `Texture::decode` expects.
+fn mime_format(mime: &str) -> &str {
+    match mime {
+        "image/png" => "png",
+        "image/jpeg" => "jpeg",
+        mime => mime,
+    }
+}
+
+/// Decodes `%20` and the like in relative URIs.
+fn percent_decode(uri: &str) -> String {
+    let bytes = uri.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let hex = bytes
+            .get(i + 1..i + 3)
+            .and_then(|hex| std::str::from_utf8(hex).ok())
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        match (bytes[i], hex) {
+            (b'%', Some(byte)) => {
+                decoded.push(byte);
+                i += 3;
+            }
+            (byte, _) => {
+                decoded.push(byte);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+fn identity() -> Mat4x4 {
+    Vec3::new(1.0, 1.0, 1.0).scaling()
+}
+
+/// The local transform of a node, given either as a matrix or as translation, rotation and scale.
+fn node_transform(node: &Value) -> Mat4x4 {
+    let matrix = floats(&node["matrix"]);
+    if matrix.len() == 16 {
+        // glTF matrices are stored column by column.
+        let mut m = [[0.0; 4]; 4];
+        for (i, value) in matrix.iter().enumerate() {
+            m[i % 4][i / 4] = *value;
+        }
+        return Mat4x4(m);
+    }
+
+    let translation = floats(&node["translation"]);
+    let rotation = floats(&node["rotation"]);
+    let scale = floats(&node["scale"]);
+
+    let translation = match translation[..] {
+        [x, y, z] => Vec3::new(x, y, z),
+        _ => Vec3::new(0.0, 0.0, 0.0),
+    };
+    let scale = match scale[..] {
+        [x, y, z] => Vec3::new(x, y, z),
+        _ => Vec3::new(1.0, 1.0, 1.0),
+    };
+    let rotation = match rotation[..] {
+        [x, y, z, w] => quaternion_rotation(x, y, z, w),
+        _ => identity(),
+    };
+
+    translation
+        .translation()
+        .mat_mul(&rotation)
+        .mat_mul(&scale.scaling())
+}
+
+fn quaternion_rotation(x: f32, y: f32, z: f32, w: f32) -> Mat4x4 {
+    Mat4x4([
+        [
+            1.0 - 2.0 * (y * y + z * z),
+            2.0 * (x * y - z * w),
+            2.0 * (x * z + y * w),
+            0.0,
+        ],
+        [
+            2.0 * (x * y + z * w),
+            1.0 - 2.0 * (x * x + z * z),
+            2.0 * (y * z - x * w),
+            0.0,
+        ],
+        [
+            2.0 * (x * z - y * w),
+            2.0 * (y * z + x * w),
+            1.0 - 2.0 * (x * x + y * y),
+            0.0,
+        ],
+        [0.0, 0.0, 0.0, 1.0],
+    ])
+}
+
+/// The inverse transpose of the upper 3x3 part of a transform, which keeps normals
+/// perpendicular to their surfaces under non-uniform scaling.
+fn normal_matrix(transform: &Mat4x4) -> Mat4x4 {
+    let Mat4x4(m) = *transform;
+    // The cofactor matrix is the inverse transpose times the determinant, and
+    // normals get normalized anyway, so the determinant only matters for its sign.
+    let cofactor =
+        |r0: usize, r1: usize, c0: usize, c1: usize| m[r0][c0] * m[r1][c1] - m[r0][c1] * m[r1][c0];
+    let c = [
+        [
+            cofactor(1, 2, 1, 2),
+            -cofactor(1, 2, 0, 2),
+            cofactor(1, 2, 0, 1),
+        ],
+        [
+            -cofactor(0, 2, 1, 2),
+            cofactor(0, 2, 0, 2),
+            -cofactor(0, 2, 0, 1),
+        ],
+        [
+            cofactor(0, 1, 1, 2),
+            -cofactor(0, 1, 0, 2),
+            cofactor(0, 1, 0, 1),
+        ],
+    ];
+    let determinant = m[0][0] * c[0][0] + m[0][1] * c[0][1] + m[0][2] * c[0][2];
+    let sign = if determinant < 0.0 { -1.0 } else { 1.0 };
+
+    let mut result = [[0.0; 4]; 4];
+    for (r, row) in c.iter().enumerate() {
+        for (col, value) in row.iter().enumerate() {
+            result[r][col] = value * sign;
+        }
+    }
+    result[3][3] = 1.0;
+    Mat4x4(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::temp_dir;
+    use serde_json::json;
+
+    /// A triangle's positions followed by its indices, as little endian floats and shorts.
+    fn triangle_buffer() -> Vec<u8> {
+        let positions = [0.0f32, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 1.0, 0.0];
+        let mut buffer: Vec<u8> = positions.iter().flat_map(|p| p.to_le_bytes()).collect();
+        buffer.extend([0u16, 1, 2].iter().flat_map(|i| i.to_le_bytes()));
+        buffer
+    }
+
+    fn triangle_document(buffer: &[u8]) -> Value {
+        json!({
+            "asset": { "version": "2.0" },
+            "buffers": [{
+                "byteLength": buffer.len(),
+                "uri": format!("data:application/octet-stream;base64,{}", base64::encode(buffer)),
+            }],
+            "bufferViews": [
+                { "buffer": 0, "byteLength": 36 },
+                { "buffer": 0, "byteOffset": 36, "byteLength": 6 },
+            ],
+            "accessors": [
+                { "bufferView": 0, "componentType": 5126, "count": 3, "type": "VEC3" },
+                { "bufferView": 1, "componentType": 5123, "count": 3, "type": "SCALAR" },
+            ],
+            "meshes": [{
+                "name": "triangle",
+                "primitives": [{ "attributes": { "POSITION": 0 }, "indices": 1 }],
+            }],
+            "nodes": [{ "mesh": 0, "translation": [0.0, 0.0, 5.0] }],
+            "scenes": [{ "nodes": [0] }],
+        })
+    }
+
+    fn load_document(name: &str, document: &Value) -> Result<(Model, Vec<Error>), Error> {
+        let path = temp_dir(name).join("model.gltf");
+        std::fs::write(&path, document.to_string()).unwrap();
+        load_with_warnings(&path)
+    }
+
+    #[test]
+    fn loads_a_triangle_with_its_node_transform() {
+        let (model, warnings) =
+            load_document("gltf-triangle", &triangle_document(&triangle_buffer())).unwrap();
+        assert!(warnings.is_empty());
+        assert_eq!(model.parts.len(), 1);
+        assert_eq!(model.parts[0].name, "triangle");
+
+        let mesh = &model.parts[0].mesh;
+        assert_eq!(mesh.indices, vec![[0, 1, 2]]);
+        let second = mesh.vertices[1].position;
+        assert_eq!((second.x, second.y, second.z), (1.0, 0.0, 5.0));
+        // Flat normals are made up when there are none.
+        assert_eq!(mesh.vertices[0].normal.z, 1.0);
+    }
+
+    #[test]
+    fn loads_binary_files() {
+        let buffer = triangle_buffer();
+        let mut document = triangle_document(&buffer);
+        document["buffers"][0] = json!({ "byteLength": buffer.len() });
+        let mut json = document.to_string().into_bytes();
+        json.resize(json.len().div_ceil(4) * 4, b' ');
+        let mut padded = buffer.clone();
+        padded.resize(buffer.len().div_ceil(4) * 4, 0);
+
+        let mut glb = GLB_MAGIC.to_vec();
+        glb.extend(2u32.to_le_bytes());
+        glb.extend(((12 + 8 + json.len() + 8 + padded.len()) as u32).to_le_bytes());
+        glb.extend((json.len() as u32).to_le_bytes());
+        glb.extend(GLB_JSON_CHUNK.to_le_bytes());
+        glb.extend(&json);
+        glb.extend((padded.len() as u32).to_le_bytes());
+        glb.extend(GLB_BIN_CHUNK.to_le_bytes());
+        glb.extend(&padded);
+
+        let path = temp_dir("gltf-glb").join("model.glb");
+        std::fs::write(&path, glb).unwrap();
+        let model = load(&path).unwrap();
+        assert_eq!(model.parts[0].mesh.indices.len(), 1);
+    }
+
+    #[test]
+    fn rejects_accessors_past_their_buffer_view() {
+        let buffer = triangle_buffer();
+        for (field, value) in [
+            ("count", json!(4)),
+            ("count", json!(u64::MAX)),
+            ("count", json!(1u64 << 60)),
+            ("byteOffset", json!(u64::MAX - 4)),
+        ] {
+            let mut document = triangle_document(&buffer);
+            document["accessors"][0][field] = value.clone();
+            let error = load_document("gltf-bounds", &document).unwrap_err();
+            assert!(
+                error.to_string().contains("out of bounds"),
+                "{} {}: {}",
+                field,
+                value,
+                error
+            );
+        }
+
+        // A stride of 0 would let any count fit in a single element.
+        for (stride, count, problem) in [
+            (0, 1u64 << 60, "invalid byte stride 0"),
+            (u64::MAX / 2, 3, "invalid byte stride"),
+            (6, 3, "invalid byte stride 6"),
+            (8, 3, "don't fit in a stride of 8"),
+            (256, 3, "invalid byte stride 256"),
+        ] {
+            let mut document = triangle_document(&buffer);
+            document["bufferViews"][0]["byteStride"] = json!(stride);
+            document["accessors"][0]["count"] = json!(count);
+            let error = load_document("gltf-stride", &document).unwrap_err();
+            assert!(error.to_string().contains(problem), "{}", error);
+        }
+        // A stride that fits still has to keep the elements inside the view.
+        let mut document = triangle_document(&buffer);
+        document["bufferViews"][0]["byteStride"] = json!(16);
+        let error = load_document("gltf-stride", &document).unwrap_err();
+        assert!(error.to_string().contains("out of bounds"), "{}", error);
+
+        let mut document = triangle_document(&buffer);
+        document["bufferViews"][0]["byteOffset"] = json!(u64::MAX);
+        assert!(load_document("gltf-view", &document).is_err());
+    }
+
+    #[test]
+    fn rejects_bad_indices_and_required_extensions() {
+        let mut buffer = triangle_buffer();
+        buffer[36..38].copy_from_slice(&7u16.to_le_bytes());
+        let error = load_document("gltf-index", &triangle_document(&buffer)).unwrap_err();
+        assert!(error.to_string().contains("vertex index 7 out of bounds"));
+
+        let mut document = triangle_document(&triangle_buffer());
+        document["extensionsRequired"] = json!(["KHR_draco_mesh_compression"]);
+        assert!(load_document("gltf-extension", &document).is_err());
+    }
+
+    #[test]
+    fn undecodable_textures_are_warnings() {
+        let mut document = triangle_document(&triangle_buffer());
+        document["images"] = json!([{ "uri": "data:image/jpeg;base64,/9j/4AAQ" }]);
+        document["textures"] = json!([{ "source": 0 }]);
+        document["materials"] = json!([{
+            "name": "photo",
+            "pbrMetallicRoughness": {
+                "baseColorFactor": [1.0, 0.5, 0.0, 1.0],
+                "baseColorTexture": { "index": 0 },
+            },
+        }]);
+        document["meshes"][0]["primitives"][0]["material"] = json!(0);
+
+        let (model, warnings) = load_document("gltf-texture", &document).unwrap();
+        assert_eq!(warnings.len(), 1);
+        assert!(warnings[0].to_string().contains("\"photo\""));
+        assert_eq!(model.materials[0].diffuse.y, 0.5);
+        assert!(model.materials[0].diffuse_texture.is_none());
+        assert_eq!(model.parts[0].mesh.material, Some(0));
+    }
+
+    #[test]
+    fn decodes_embedded_textures() {
+        let mut document = triangle_document(&triangle_buffer());
+        let image = base64::encode("P3 1 1 255 0 255 0");
+        // Unknown MIME types are taken as file extensions.
+        document["images"] = json!([{ "uri": format!("data:ppm;base64,{}", image) }]);
+        document["textures"] = json!([{ "source": 0, "sampler": 0 }]);
+        document["samplers"] = json!([{ "magFilter": 9728, "wrapS": 33071 }]);
+        document["materials"] = json!([{
+            "pbrMetallicRoughness": { "baseColorTexture": { "index": 0 } },
+        }]);
+
+        let (model, warnings) = load_document("gltf-embedded", &document).unwrap();
+        assert!(warnings.is_empty());
+        let texture = model.materials[0].diffuse_texture.as_ref().unwrap();
+        assert_eq!(texture.get_pixel(0, 0).y, 1.0);
+        assert_eq!(
+            (texture.filter, texture.wrap),
+            (Filter::Nearest, Wrap::Clamp)
+        );
+    }
+
+    #[test]
+    fn sources_include_external_buffers_even_when_loading_fails() {
+        let directory = temp_dir("gltf-sources");
+        let path = directory.join("model.gltf");
+        let buffer = triangle_buffer();
+        let mut document = triangle_document(&buffer);
+        document["buffers"][0]["uri"] = json!("triangle%20data.bin");
+        std::fs::write(&path, document.to_string()).unwrap();
+
+        let mut sources = vec![];
+        assert!(load_with_sources(&path, &mut sources).is_err());
+        let bin = directory.join("triangle data.bin");
+        assert_eq!(sources, vec![path.clone(), bin.clone()]);
+
+        std::fs::write(&bin, &buffer).unwrap();
+        let model = load(&path).unwrap();
+        assert_eq!(model.sources, vec![path, bin]);
+    }
+}
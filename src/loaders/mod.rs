@@ -1,2 +1,99 @@
+pub mod error;
+pub mod gltf;
 pub mod mtl;
 pub mod obj;
+pub mod ply;
+pub mod stl;
+
+use crate::geometry::{Mesh, Model, Part};
+use std::path::{Path, PathBuf};
+
+use ::failure::format_err;
+use ::failure::Error;
+
+/// Loads a model in any of the supported formats, picked by the file extension. Formats
+/// that only hold a single mesh give a model with one part, named after the file. PLY files
+/// without faces give a part whose mesh has vertices but no triangles.
+pub fn load_model(path: &Path) -> Result<Model, Error> {
+    load_model_with_warnings(path, false, &mut vec![]).map(|(model, _)| model)
+}
+
+/// Loads a model like `load_model`, along with warnings about what was left out: textures
+/// that couldn't be loaded, and in lenient mode, whatever the OBJ loader skipped. Every file
+/// the loader reads, or tries to, is added to `sources`, even if loading fails.
+pub fn load_model_with_warnings(
+    path: &Path,
+    lenient: bool,
+    sources: &mut Vec<PathBuf>,
+) -> Result<(Model, Vec<Error>), Error> {
+    let extension = path
+        .extension()
+        .and_then(|extension| extension.to_str())
+        .unwrap_or("")
+        .to_ascii_lowercase();
+    let single = |mesh: Mesh| Model {
+        parts: vec![Part {
+            name: path
+                .file_stem()
+                .and_then(|stem| stem.to_str())
+                .unwrap_or("default")
+                .to_owned(),
+            mesh,
+        }],
+        materials: vec![],
+        sources: vec![path.to_owned()],
+    };
+
+    match extension.as_str() {
+        "obj" => {
+            let options = obj::LoadOptions {
+                lenient,
+                ..obj::LoadOptions::default()
+            };
+            let (model, warnings) = obj::load_with_sources(path, &options, sources)?;
+            Ok((model, warnings.into_iter().map(Error::from).collect()))
+        }
+        "gltf" | "glb" => gltf::load_with_sources(path, sources),
+        "stl" => {
+            sources.push(path.to_owned());
+            Ok((single(stl::load(path)?), vec![]))
+        }
+        "ply" => {
+            sources.push(path.to_owned());
+            let mesh = match ply::load(path)?.geometry {
+                ply::Geometry::Mesh(mesh) => mesh,
+                ply::Geometry::Points(cloud) => Mesh::new(cloud.points, vec![], None),
+            };
+            Ok((single(mesh), vec![]))
+        }
+        _ => Err(format_err!(
+            "unknown model format {}; expected .obj, .gltf, .glb, .stl or .ply",
+            path.display()
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::temp_dir;
+
+    #[test]
+    fn ply_points_load_as_a_mesh_without_triangles() {
+        let path = temp_dir("load-model-points").join("cloud.ply");
+        std::fs::write(
+            &path,
+            "ply\nformat ascii 1.0\nelement vertex 2\nproperty float x\nproperty float y\n\
+             property float z\nend_header\n0 0 0\n1 2 3\n",
+        )
+        .unwrap();
+
+        let model = load_model(&path).unwrap();
+        assert_eq!(model.parts.len(), 1);
+        assert_eq!(model.parts[0].name, "cloud");
+        let mesh = &model.parts[0].mesh;
+        assert_eq!(mesh.vertices.len(), 2);
+        assert!(mesh.indices.is_empty());
+        assert_eq!(mesh.aabb.max.z, 3.0);
+    }
+}
@@ -1,67 +1,326 @@
-use crate::geometry::*;
+use super::error::*;
+use crate::geometry::Material;
 use crate::math::*;
+use crate::texture::Texture;
 use std::collections::HashMap;
-use std::io::BufRead;
+use std::io::{BufRead, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
 
-use ::failure::format_err;
-use ::failure::Error;
+/// Directives from the MTL specification, and common extensions to it, that we don't do
+/// anything with. They aren't unknown, so they don't cause errors.
+const IGNORED_DIRECTIVES: &[&str] = &[
+    "Tf",
+    "sharpness",
+    "map_Ka",
+    "map_Ks",
+    "map_Ns",
+    "map_Ke",
+    "map_aat",
+    "disp",
+    "decal",
+    "refl",
+    "norm",
+    "Pr",
+    "Pm",
+    "Ps",
+    "Pc",
+    "Pcr",
+    "aniso",
+    "anisor",
+    "map_Pr",
+    "map_Pm",
+    "map_Ps",
+];
 
-pub fn load(path: &std::path::Path, colors: &mut HashMap<String, Vec4>) -> Result<(), Error> {
-    static NO_COLOR: Vec4 = Vec4 {
-        x: 0.5,
-        y: 0.5,
-        z: 0.5,
-        w: 1.0,
-    };
+pub fn load(path: &Path) -> Result<Vec<Material>, LoadError> {
+    load_with_warnings(path, false).map(|(materials, _)| materials)
+}
 
-    let mut current_material = "default".to_owned();
-    colors.insert(current_material.to_owned(), NO_COLOR);
+/// Loads a material library. In lenient mode, unknown directives are skipped and returned
+/// as warnings instead of failing. Textures that can't be loaded are always only warned
+/// about, leaving the material without them.
+pub fn load_with_warnings(
+    path: &Path,
+    lenient: bool,
+) -> Result<(Vec<Material>, Vec<LoadError>), LoadError> {
+    let mut materials: Vec<Material> = vec![];
+    let mut warnings = vec![];
+    // Materials often share textures, so only load each once.
+    let mut textures: HashMap<PathBuf, Arc<Texture>> = HashMap::new();
 
-    let file = std::fs::File::open(path)?;
+    let file = std::fs::File::open(path).map_err(|e| LoadError::io(path, e))?;
     let lines = std::io::BufReader::new(file).lines();
 
-    for line in lines {
-        let line = line?;
-        let line = line.trim();
-        if line.chars().nth(0) == Some('#') {
+    for (number, line) in (1..).zip(lines) {
+        let raw = line.map_err(|e| LoadError {
+            line: Some(number),
+            ..LoadError::io(path, e)
+        })?;
+        let at = |problem: Problem| problem.at(path, number, &raw);
+        let line = raw.trim();
+        if line.starts_with('#') {
             // Comment; ignore
             continue;
         }
 
         let mut words = line.split_whitespace();
-        match words.next() {
-            Some("newmtl") => {
-                current_material = words
+        let directive = match words.next() {
+            Some(directive) => directive,
+            None => continue,
+        };
+
+        if directive == "newmtl" {
+            let name = words
+                .next()
+                .ok_or_else(|| at(ErrorKind::MissingValue("material name").into()))?;
+            materials.push(Material::new(name));
+            continue;
+        }
+
+        let material = match materials.last_mut() {
+            Some(material) => material,
+            // Properties before the first material don't belong to anything.
+            None => continue,
+        };
+
+        match directive {
+            "Ka" => material.ambient = parse_color(&mut words).map_err(at)?,
+            "Kd" => material.diffuse = parse_color(&mut words).map_err(at)?,
+            "Ks" => material.specular = parse_color(&mut words).map_err(at)?,
+            "Ke" => material.emissive = parse_color(&mut words).map_err(at)?,
+            "Ns" => material.shininess = parse_float(&mut words).map_err(at)?,
+            "Ni" => material.optical_density = parse_float(&mut words).map_err(at)?,
+            "d" => material.opacity = parse_float(&mut words).map_err(at)?,
+            "Tr" => material.opacity = 1.0 - parse_float(&mut words).map_err(at)?,
+            "illum" => {
+                let word = words
                     .next()
-                    .ok_or(format_err!("no material name"))?
-                    .to_owned();
+                    .ok_or_else(|| at(ErrorKind::MissingValue("illumination model").into()))?;
+                material.illumination = word
+                    .parse()
+                    .map_err(|_| at(Problem::new(ErrorKind::InvalidNumber, word)))?;
+            }
+            "map_Kd" => {
+                let (map, word) = parse_map(path, line).map_err(at)?;
+                let texture = match textures.get(&map) {
+                    Some(texture) => Some(texture.clone()),
+                    None => match Texture::load(&map) {
+                        Ok(texture) => {
+                            let texture = Arc::new(texture);
+                            textures.insert(map.clone(), texture.clone());
+                            Some(texture)
+                        }
+                        Err(e) => {
+                            warnings.push(at(Problem::new(ErrorKind::Texture(e), word)));
+                            None
+                        }
+                    },
+                };
+                material.diffuse_map = Some(map);
+                material.diffuse_texture = texture;
             }
-            Some("Kd") => {
-                colors.insert(current_material.to_owned(), parse_vec4(&mut words)?);
+            "map_Bump" | "map_bump" | "bump" => {
+                material.bump_map = Some(parse_map(path, line).map_err(at)?.0)
             }
-            //Some(d) => eprintln!("Unrecognized directive: {}", d),
-            _ => {
-                // Do nothing
+            "map_d" => material.opacity_map = Some(parse_map(path, line).map_err(at)?.0),
+            directive if IGNORED_DIRECTIVES.contains(&directive) => {}
+            directive => {
+                let error = at(Problem::new(ErrorKind::UnknownDirective, directive));
+                if lenient {
+                    warnings.push(error);
+                } else {
+                    return Err(error);
+                }
             }
         }
     }
 
+    Ok((materials, warnings))
+}
+
+fn parse_float<'a>(args: &mut std::str::SplitWhitespace<'a>) -> Result<f32, Problem<'a>> {
+    let word = args.next().ok_or(ErrorKind::MissingValue("value"))?;
+    word.parse()
+        .map_err(|_| Problem::new(ErrorKind::InvalidNumber, word))
+}
+
+/// Parses an RGB color. A single value is used for all three channels.
+fn parse_color<'a>(args: &mut std::str::SplitWhitespace<'a>) -> Result<Vec3, Problem<'a>> {
+    let r = parse_float(args)?;
+    let mut channel = || match args.next() {
+        Some(word) => word
+            .parse::<f32>()
+            .map_err(|_| Problem::new(ErrorKind::InvalidNumber, word)),
+        None => Ok(r),
+    };
+    let g = channel()?;
+    let b = channel()?;
+
+    Ok(Vec3::new(r, g, b))
+}
+
+/// Returns the file a texture map directive refers to, relative to the material library,
+/// along with the word it was named by.
+fn parse_map<'a>(path: &Path, line: &'a str) -> Result<(PathBuf, &'a str), Problem<'a>> {
+    // Options like `-bm 0.5` come before the file name, so it's whatever is last.
+    let file = line
+        .split_whitespace()
+        .skip(1)
+        .last()
+        .ok_or(ErrorKind::MissingValue("texture file name"))?;
+
+    Ok((path.with_file_name(file), file))
+}
+
+/// Saves materials as an MTL file. Texture maps are written relative to it where possible.
+pub fn save(materials: &[Material], path: &Path) -> std::io::Result<()> {
+    let file = std::fs::File::create(path)?;
+    let mut out = std::io::BufWriter::new(file);
+    let directory = path.parent().unwrap_or_else(|| Path::new(""));
+    write(materials, directory, &mut out)?;
+    out.flush()?;
     Ok(())
 }
 
-fn parse_vec4(args: &mut std::str::SplitWhitespace) -> Result<Vec4, Error> {
-    let x = args
-        .next()
-        .ok_or(format_err!("no X coordinate"))?
-        .parse::<f32>()?;
-    let y = args
-        .next()
-        .ok_or(format_err!("no Y coordinate"))?
-        .parse::<f32>()?;
+/// Writes materials in MTL format, with texture maps relative to `directory`.
+pub fn write(
+    materials: &[Material],
+    directory: &Path,
+    out: &mut impl Write,
+) -> std::io::Result<()> {
+    let color = |v: &Vec3| format!("{} {} {}", v.x, v.y, v.z);
+    let map = |map: &Path| {
+        map.strip_prefix(directory)
+            .unwrap_or(map)
+            .display()
+            .to_string()
+    };
 
-    let z = args.next().unwrap_or("0.0").parse::<f32>()?;
+    for (i, material) in materials.iter().enumerate() {
+        if i > 0 {
+            writeln!(out)?;
+        }
+        writeln!(out, "newmtl {}", material.name)?;
+        writeln!(out, "Ka {}", color(&material.ambient))?;
+        writeln!(out, "Kd {}", color(&material.diffuse))?;
+        writeln!(out, "Ks {}", color(&material.specular))?;
+        writeln!(out, "Ke {}", color(&material.emissive))?;
+        writeln!(out, "Ns {}", material.shininess)?;
+        writeln!(out, "Ni {}", material.optical_density)?;
+        writeln!(out, "d {}", material.opacity)?;
+        writeln!(out, "illum {}", material.illumination)?;
 
-    let w = args.next().unwrap_or("1.0").parse::<f32>()?;
+        if let Some(path) = &material.diffuse_map {
+            writeln!(out, "map_Kd {}", map(path))?;
+        }
+        if let Some(path) = &material.bump_map {
+            writeln!(out, "map_Bump {}", map(path))?;
+        }
+        if let Some(path) = &material.opacity_map {
+            writeln!(out, "map_d {}", map(path))?;
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::temp_dir;
+
+    #[test]
+    fn loads_materials() {
+        let directory = temp_dir("mtl-loads");
+        let path = directory.join("materials.mtl");
+        std::fs::write(
+            &path,
+            "# comment\nnewmtl red\nKd 1 0 0\nNs 10\nd 0.5\nillum 2\n\nnewmtl gray\nKa 0.25\nTr 0.25\n",
+        )
+        .unwrap();
+
+        let materials = load(&path).unwrap();
+        assert_eq!(materials.len(), 2);
+        assert_eq!(materials[0].name, "red");
+        let diffuse = materials[0].diffuse;
+        assert_eq!((diffuse.x, diffuse.y, diffuse.z), (1.0, 0.0, 0.0));
+        assert_eq!(materials[0].shininess, 10.0);
+        assert_eq!(materials[0].opacity, 0.5);
+        assert_eq!(materials[0].illumination, 2);
+        let ambient = materials[1].ambient;
+        assert_eq!((ambient.x, ambient.y, ambient.z), (0.25, 0.25, 0.25));
+        assert_eq!(materials[1].opacity, 0.75);
+    }
+
+    #[test]
+    fn unknown_directives_fail_unless_lenient() {
+        let directory = temp_dir("mtl-unknown");
+        let path = directory.join("materials.mtl");
+        std::fs::write(&path, "newmtl a\n  Kq 1\nKd 0 1 0\n").unwrap();
+
+        let error = load(&path).unwrap_err();
+        assert_eq!((error.line, error.column), (Some(2), Some(3)));
 
-    Ok(Vec4::new(x, y, z, w))
+        let (materials, warnings) = load_with_warnings(&path, true).unwrap();
+        assert_eq!(materials[0].diffuse.y, 1.0);
+        assert_eq!(warnings.len(), 1);
+    }
+
+    #[test]
+    fn missing_texture_is_a_warning() {
+        let directory = temp_dir("mtl-texture");
+        let path = directory.join("materials.mtl");
+        std::fs::write(&path, "newmtl a\nKd 0 0 1\nmap_Kd missing.ppm\n").unwrap();
+
+        let (materials, warnings) = load_with_warnings(&path, false).unwrap();
+        assert_eq!(materials.len(), 1);
+        assert_eq!(materials[0].diffuse.z, 1.0);
+        assert_eq!(
+            materials[0].diffuse_map,
+            Some(directory.join("missing.ppm"))
+        );
+        assert!(materials[0].diffuse_texture.is_none());
+        assert_eq!(warnings.len(), 1);
+        assert_eq!(warnings[0].line, Some(3));
+        assert!(matches!(warnings[0].kind, ErrorKind::Texture(_)));
+    }
+
+    #[test]
+    fn shares_textures_between_materials() {
+        let directory = temp_dir("mtl-shared");
+        std::fs::write(directory.join("white.ppm"), "P3 1 1 255 255 255 255").unwrap();
+        let path = directory.join("materials.mtl");
+        std::fs::write(
+            &path,
+            "newmtl a\nmap_Kd white.ppm\nnewmtl b\nmap_Kd white.ppm\n",
+        )
+        .unwrap();
+
+        let materials = load(&path).unwrap();
+        let a = materials[0].diffuse_texture.as_ref().unwrap();
+        let b = materials[1].diffuse_texture.as_ref().unwrap();
+        assert!(Arc::ptr_eq(a, b));
+    }
+
+    #[test]
+    fn round_trips_through_save() {
+        let directory = temp_dir("mtl-save");
+        let mut material = Material::new("shiny");
+        material.specular = Vec3::new(0.5, 0.5, 0.5);
+        material.shininess = 200.0;
+        material.diffuse_map = Some(directory.join("wood.ppm"));
+
+        let path = directory.join("saved.mtl");
+        save(&[material], &path).unwrap();
+        assert!(std::fs::read_to_string(&path)
+            .unwrap()
+            .contains("map_Kd wood.ppm\n"));
+
+        let (loaded, _) = load_with_warnings(&path, false).unwrap();
+        assert_eq!(loaded[0].name, "shiny");
+        assert_eq!(loaded[0].specular.x, 0.5);
+        assert_eq!(loaded[0].shininess, 200.0);
+        assert_eq!(loaded[0].diffuse_map, Some(directory.join("wood.ppm")));
+    }
 }
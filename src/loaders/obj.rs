@@ -1,22 +1,108 @@
+use super::error::*;
 use crate::geometry::*;
 use crate::math::*;
 use std::collections::HashMap;
-use std::io::BufRead;
+use std::fmt::Write as _;
+use std::io::{BufRead, Write};
+use std::path::{Path, PathBuf};
 
 use ::failure::format_err;
 use ::failure::Error;
 
-pub fn load(
-    path: &std::path::Path,
-    material_colors: Option<&HashMap<String, Vec4>>,
-) -> Result<Mesh, Error> {
-    let mut positions: Vec<Vec3> = vec![];
-    let mut uv: Vec<Vec2> = vec![];
-    let mut normals: Vec<Vec3> = vec![];
+/// Directives from the OBJ specification that we don't do anything with, such as
+/// free-form curves and surfaces. They aren't unknown, so they don't cause errors.
+const IGNORED_DIRECTIVES: &[&str] = &[
+    "l",
+    "p",
+    "vp",
+    "cstype",
+    "deg",
+    "bmat",
+    "step",
+    "curv",
+    "curv2",
+    "surf",
+    "parm",
+    "trim",
+    "hole",
+    "scrv",
+    "sp",
+    "end",
+    "con",
+    "mg",
+    "bevel",
+    "c_interp",
+    "d_interp",
+    "lod",
+    "usemap",
+    "maplib",
+    "shadow_obj",
+    "trace_obj",
+    "ctech",
+    "stech",
+    "call",
+    "csh",
+];
 
-    let mut triangles: Vec<Triangle> = vec![];
+#[derive(Default)]
+pub struct LoadOptions {
+    /// How to make normals for vertices that the file doesn't give any.
+    pub normals: NormalOptions,
+    /// Replace the normals from the file with generated ones as well.
+    pub recompute_normals: bool,
+    /// Skip unknown directives in the OBJ file and its material libraries with a warning,
+    /// instead of failing.
+    pub lenient: bool,
+}
+
+pub fn load(path: &Path) -> Result<Model, LoadError> {
+    load_with_options(path, &LoadOptions::default())
+}
+
+pub fn load_with_options(path: &Path, options: &LoadOptions) -> Result<Model, LoadError> {
+    load_with_warnings(path, options).map(|(model, _)| model)
+}
+
+/// Loads a model, along with warnings about what was skipped in lenient mode and about
+/// textures that couldn't be loaded.
+pub fn load_with_warnings(
+    path: &Path,
+    options: &LoadOptions,
+) -> Result<(Model, Vec<LoadError>), LoadError> {
+    load_with_sources(path, options, &mut vec![])
+}
+
+/// Loads a model like `load_with_warnings`, adding every file it reads, or tries to, to
+/// `sources` before reading it. Textures that are missing are added as well, so even when
+/// loading fails, `sources` has the files that fixing it could take.
+pub fn load_with_sources(
+    path: &Path,
+    options: &LoadOptions,
+    sources: &mut Vec<PathBuf>,
+) -> Result<(Model, Vec<LoadError>), LoadError> {
+    let mut data = VertexData {
+        positions: vec![],
+        colors: vec![],
+        uvs: vec![],
+        normals: vec![],
+    };
 
-    let file = std::fs::File::open(path)?;
+    let mut parts = Parts {
+        parts: vec![],
+        faces: vec![],
+        name: "default".to_owned(),
+        material: None,
+        current: None,
+    };
+
+    // Smoothing groups are off until the file turns them on, but files that never
+    // mention them get smoothed according to the options instead.
+    let mut smoothing_group = 0;
+    let mut uses_smoothing_groups = false;
+    let start = sources.len();
+    sources.push(path.to_owned());
+
+    let file = std::fs::File::open(path).map_err(|e| LoadError::io(path, e))?;
     let lines = std::io::BufReader::new(file).lines();
 
     static NO_COLOR: Vec4 = Vec4 {
@@ -26,136 +112,718 @@ pub fn load(
         w: 1.0,
     };
 
-    let mut model_colors = HashMap::new();
-    let mut current_color = *material_colors
-        .unwrap_or(&model_colors)
-        .get("default")
-        .unwrap_or(&NO_COLOR);
+    let mut materials: Vec<Material> = vec![];
+    let mut warnings = vec![];
+    let mut current_color = NO_COLOR;
 
-    for line in lines {
-        let line = line?;
-        let line = line.trim();
-        if line.chars().nth(0) == Some('#') {
+    for (number, line) in (1..).zip(lines) {
+        let raw = line.map_err(|e| LoadError {
+            line: Some(number),
+            ..LoadError::io(path, e)
+        })?;
+        let at = |problem: Problem| problem.at(path, number, &raw);
+        let line = raw.trim();
+        if line.starts_with('#') {
             // Comment; ignore
             continue;
         }
 
         let mut words = line.split_whitespace();
         match words.next() {
-            Some("v") => positions.push(parse_vec4(&mut words)?.xyz()),
-            Some("vt") => uv.push(parse_vec4(&mut words)?.xyz().xy()),
-            Some("vn") => normals.push(parse_vec4(&mut words)?.xyz()),
-            Some("f") => triangles.push(parse_triangle(
-                &mut words,
-                &positions,
-                &normals,
-                &uv,
-                &current_color,
-            )?),
+            Some("v") => {
+                let (position, color) = parse_position(&mut words).map_err(at)?;
+                data.positions.push(position);
+                data.colors.push(color);
+            }
+            Some("vt") => data
+                .uvs
+                .push(parse_vec4(&mut words).map_err(at)?.xyz().xy()),
+            Some("vn") => data.normals.push(parse_vec4(&mut words).map_err(at)?.xyz()),
+            Some("f") => {
+                let triangles = parse_face(&mut words, &data, &current_color).map_err(at)?;
+                parts.add_face(triangles, smoothing_group);
+            }
+            Some("s") => {
+                uses_smoothing_groups = true;
+                smoothing_group = match words.next() {
+                    Some("off") | None => 0,
+                    Some(group) => group.parse().map_err(|_| {
+                        at(Problem::new(
+                            ErrorKind::Invalid(format!("invalid smoothing group {:?}", group)),
+                            group,
+                        ))
+                    })?,
+                };
+            }
+            Some("o") | Some("g") => {
+                // Groups can have several names; just keep them together.
+                let name = words.collect::<Vec<&str>>().join(" ");
+                parts.set_name(if name.is_empty() {
+                    "default".to_owned()
+                } else {
+                    name
+                });
+            }
             Some("usemtl") => {
-                current_color = *words
+                let name = words
                     .next()
-                    .and_then(|mtl| material_colors.unwrap_or(&model_colors).get(mtl))
-                    .unwrap_or(&NO_COLOR)
+                    .ok_or_else(|| at(ErrorKind::MissingValue("material name").into()))?;
+                // Later definitions win, like they would in a single library.
+                let material = materials.iter().rposition(|material| material.name == name);
+
+                current_color = material
+                    .map(|id| Vec4::from_vec3(materials[id].diffuse, 1.0))
+                    .unwrap_or(NO_COLOR);
+                parts.set_material(material);
             }
             Some("mtllib") => {
-                super::mtl::load(
-                    &path.with_file_name(
-                        words
-                            .next()
-                            .ok_or(format_err!("no material lib file name"))?
-                            .to_owned(),
-                    ),
-                    &mut model_colors,
-                )?;
+                // A line can name several libraries.
+                for library in words {
+                    let library_path = path.with_file_name(library);
+                    sources.push(library_path.clone());
+                    let (library, library_warnings) =
+                        super::mtl::load_with_warnings(&library_path, options.lenient)?;
+                    sources.extend(library.iter().filter_map(|m| m.diffuse_map.clone()));
+                    materials.extend(library);
+                    warnings.extend(library_warnings);
+                }
             }
-            //Some(d) => eprintln!("Unrecognized directive: {}", d),
-            _ => {
-                // Do nothing
+            Some(directive) if IGNORED_DIRECTIVES.contains(&directive) => {}
+            Some(directive) => {
+                let error = at(Problem::new(ErrorKind::UnknownDirective, directive));
+                if options.lenient {
+                    warnings.push(error);
+                } else {
+                    return Err(error);
+                }
             }
+            None => {}
         }
     }
 
-    Ok(Mesh { triangles })
+    for (part, mut faces) in parts.parts.iter_mut().zip(parts.faces) {
+        // Corners that refer to the same position, UV and normal end up as one vertex.
+        let triangles = std::mem::take(&mut faces.triangles);
+        part.mesh = Mesh::from_triangles(triangles, part.mesh.material);
+
+        let groups = if uses_smoothing_groups {
+            Some(&faces.smoothing_groups[..])
+        } else {
+            None
+        };
+        let normals = part.mesh.compute_normals(&options.normals, groups);
+        part.mesh.map_corners(|t, k, vertex| {
+            if faces.missing_normals[t][k] || options.recompute_normals {
+                vertex.normal = normals[t][k];
+            }
+        });
+    }
+
+    let model = Model {
+        parts: parts.parts,
+        materials,
+        sources: sources[start..].to_vec(),
+    };
+    Ok((model, warnings))
+}
+
+/// Sorts faces into parts by the object or group name and material they're declared under.
+struct Parts {
+    parts: Vec<Part>,
+    /// What the normals of each part are generated from, by part index.
+    faces: Vec<FaceInfo>,
+    name: String,
+    material: Option<MaterialId>,
+    /// Index of the part for the current name and material, once a face needs it.
+    current: Option<usize>,
+}
+
+impl Parts {
+    fn set_name(&mut self, name: String) {
+        self.name = name;
+        self.current = None;
+    }
+
+    fn set_material(&mut self, material: Option<MaterialId>) {
+        self.material = material;
+        self.current = None;
+    }
+
+    fn add_face(&mut self, triangles: Vec<(Triangle, [bool; 3])>, smoothing_group: u32) {
+        let index = self.current();
+        for (triangle, missing_normals) in triangles {
+            self.faces[index].triangles.push(triangle);
+            self.faces[index].smoothing_groups.push(smoothing_group);
+            self.faces[index].missing_normals.push(missing_normals);
+        }
+    }
+
+    fn current(&mut self) -> usize {
+        let (name, material) = (&self.name, self.material);
+        // Files often switch back and forth, so look for an existing part first.
+        let existing = self.current.or_else(|| {
+            self.parts
+                .iter()
+                .position(|part| &part.name == name && part.mesh.material == material)
+        });
+        let index = match existing {
+            Some(index) => index,
+            None => {
+                self.parts.push(Part {
+                    name: name.clone(),
+                    mesh: Mesh::from_triangles(vec![], material),
+                });
+                self.faces.push(FaceInfo {
+                    triangles: vec![],
+                    smoothing_groups: vec![],
+                    missing_normals: vec![],
+                });
+                self.parts.len() - 1
+            }
+        };
+        self.current = Some(index);
+        index
+    }
 }
 
-fn parse_vec4(args: &mut std::str::SplitWhitespace) -> Result<Vec4, Error> {
-    let x = args
-        .next()
-        .ok_or(format_err!("no X coordinate"))?
-        .parse::<f32>()?;
-    let y = args
-        .next()
-        .ok_or(format_err!("no Y coordinate"))?
-        .parse::<f32>()?;
+/// Per triangle of a part, until the faces are all read: its corners, its smoothing group,
+/// and which corners had no normal in the file.
+struct FaceInfo {
+    triangles: Vec<Triangle>,
+    smoothing_groups: Vec<u32>,
+    missing_normals: Vec<[bool; 3]>,
+}
 
-    let z = args.next().unwrap_or("0.0").parse::<f32>()?;
+/// Everything faces can refer to, in the order it was declared.
+struct VertexData {
+    positions: Vec<Vec3>,
+    /// Colors given with the `v x y z r g b` extension, one per position.
+    colors: Vec<Option<Vec4>>,
+    uvs: Vec<Vec2>,
+    normals: Vec<Vec3>,
+}
 
-    let w = args.next().unwrap_or("1.0").parse::<f32>()?;
+fn parse_vec4<'a>(args: &mut std::str::SplitWhitespace<'a>) -> Result<Vec4, Problem<'a>> {
+    let x = parse_float(args.next().ok_or(ErrorKind::MissingValue("X coordinate"))?)?;
+    let y = parse_float(args.next().ok_or(ErrorKind::MissingValue("Y coordinate"))?)?;
+    let z = args.next().map(parse_float).transpose()?.unwrap_or(0.0);
+    let w = args.next().map(parse_float).transpose()?.unwrap_or(1.0);
 
     Ok(Vec4::new(x, y, z, w))
 }
 
-fn parse_triangle(
-    args: &mut std::str::SplitWhitespace,
-    vertices: &Vec<Vec3>,
-    normals: &Vec<Vec3>,
-    uv: &Vec<Vec2>,
+fn parse_float(word: &str) -> Result<f32, Problem<'_>> {
+    word.parse()
+        .map_err(|_| Problem::new(ErrorKind::InvalidNumber, word))
+}
+
+/// Parses a vertex position, optionally followed by a color: `x y z [w]` or `x y z [w] r g b`.
+fn parse_position<'a>(
+    args: &mut std::str::SplitWhitespace<'a>,
+) -> Result<(Vec3, Option<Vec4>), Problem<'a>> {
+    let values = args.map(parse_float).collect::<Result<Vec<f32>, _>>()?;
+
+    match values[..] {
+        [x, y, z] | [x, y, z, _] => Ok((Vec3::new(x, y, z), None)),
+        [x, y, z, r, g, b] | [x, y, z, _, r, g, b] => {
+            Ok((Vec3::new(x, y, z), Some(Vec4::new(r, g, b, 1.0))))
+        }
+        _ => Err(ErrorKind::Invalid(format!(
+            "vertex with {} values; expected x y z, optionally followed by w and r g b",
+            values.len()
+        ))
+        .into()),
+    }
+}
+
+/// Parses a face with any number of vertices, split into triangles. Along with each triangle
+/// comes which of its corners need a normal generated.
+fn parse_face<'a>(
+    args: &mut std::str::SplitWhitespace<'a>,
+    data: &VertexData,
     color: &Vec4,
-) -> Result<Triangle, Error> {
-    Ok([
-        parse_vertex(args, vertices, normals, uv, color)?,
-        parse_vertex(args, vertices, normals, uv, color)?,
-        parse_vertex(args, vertices, normals, uv, color)?,
-    ])
-}
-
-fn parse_vertex(
-    args: &mut std::str::SplitWhitespace,
-    positions: &Vec<Vec3>,
-    normals: &Vec<Vec3>,
-    uvs: &Vec<Vec2>,
+) -> Result<Vec<(Triangle, [bool; 3])>, Problem<'a>> {
+    let polygon = args
+        .map(|triple| parse_vertex(triple, data, color))
+        .collect::<Result<Vec<(Vertex, bool)>, _>>()?;
+
+    if polygon.len() < 3 {
+        return Err(
+            ErrorKind::Invalid(format!("face with only {} vertices", polygon.len())).into(),
+        );
+    }
+
+    let positions: Vec<Vec3> = polygon.iter().map(|(vertex, _)| vertex.position).collect();
+    Ok(triangulate(&positions)
+        .into_iter()
+        .map(|[a, b, c]| {
+            (
+                [polygon[a].0, polygon[b].0, polygon[c].0],
+                [!polygon[a].1, !polygon[b].1, !polygon[c].1],
+            )
+        })
+        .collect())
+}
+
+/// Turns a 1-based index, or a negative one counting back from the last element, into an
+/// index into a list of `count` elements.
+fn resolve_index<'a>(
+    index: &'a str,
+    count: usize,
+    kind: &'static str,
+) -> Result<usize, Problem<'a>> {
+    let value = index
+        .parse::<i64>()
+        .map_err(|_| Problem::new(ErrorKind::InvalidNumber, index))?;
+
+    let resolved = match value {
+        0 => return Err(Problem::new(ErrorKind::ZeroIndex(kind), index)),
+        value if value < 0 => count as i64 + value,
+        value => value - 1,
+    };
+
+    if resolved < 0 || resolved >= count as i64 {
+        return Err(Problem::new(
+            ErrorKind::IndexOutOfBounds { kind, count },
+            index,
+        ));
+    }
+
+    Ok(resolved as usize)
+}
+
+/// Parses a `v`, `v/vt`, `v//vn` or `v/vt/vn` vertex reference. Also returns whether
+/// it included a normal.
+fn parse_vertex<'a>(
+    triple: &'a str,
+    data: &VertexData,
     color: &Vec4,
-) -> Result<Vertex, Error> {
+) -> Result<(Vertex, bool), Problem<'a>> {
     static DEFAULT_UV: Vec2 = Vec2 { x: 0.0, y: 0.0 };
-    static DEFAULT_NORMAL: Vec3 = Vec3 {
-        x: 0.0,
-        y: 0.0,
-        z: -1.0,
+
+    let mut indices = triple.split('/');
+
+    let v = resolve_index(indices.next().unwrap_or(""), data.positions.len(), "vertex")?;
+
+    let uv = match indices.next() {
+        None | Some("") => DEFAULT_UV,
+        Some(index) => data.uvs[resolve_index(index, data.uvs.len(), "texture coordinate")?],
     };
 
-    let mut args = args
-        .next()
-        .ok_or(format_err!("no vertex triple"))?
-        .split('/');
-
-    let v = args
-        .next()
-        .ok_or(format_err!("no vertex index"))?
-        .parse::<usize>()?;
-
-    let uv = args
-        .next()
-        .and_then(|x| x.parse::<usize>().ok())
-        .and_then(|i| uvs.get(i - 1))
-        .unwrap_or(&DEFAULT_UV);
-
-    let normal = args
-        .next()
-        .and_then(|x| x.parse::<usize>().ok())
-        .and_then(|i| normals.get(i - 1))
-        .unwrap_or(&DEFAULT_NORMAL);
-
-    let position = positions
-        .get(v - 1)
-        .ok_or(format_err!("vertex index {} out of bounds", v))?;
-
-    /* TODO: add support for uv coords and normals */
-
-    Ok(Vertex {
-        position: *position,
-        uv: *uv,
-        color: *color,
-        normal: *normal,
-    })
+    // Missing normals are filled in once all faces are known.
+    let normal = match indices.next() {
+        None | Some("") => None,
+        Some(index) => Some(data.normals[resolve_index(index, data.normals.len(), "normal")?]),
+    };
+
+    let vertex = Vertex {
+        position: data.positions[v],
+        uv,
+        color: data.colors[v].unwrap_or(*color),
+        normal: normal.unwrap_or(Vec3::new(0.0, 0.0, 0.0)),
+    };
+    Ok((vertex, normal.is_some()))
+}
+
+/// Saves a mesh as an OBJ file, with an MTL file of the same name next to it. Triangles
+/// get a material for their color, so a mesh colored by `load` comes back the same.
+pub fn save(mesh: &Mesh, path: &Path) -> Result<(), Error> {
+    write_files(&[(None, mesh)], &[], path)
+}
+
+/// Saves a model as an OBJ file, with each part as an object and the materials in an MTL
+/// file of the same name next to it. Parts without a material get one for each color.
+pub fn save_model(model: &Model, path: &Path) -> Result<(), Error> {
+    let meshes: Vec<(Option<&str>, &Mesh)> = model
+        .parts
+        .iter()
+        .map(|part| (Some(part.name.as_str()), &part.mesh))
+        .collect();
+    write_files(&meshes, &model.materials, path)
+}
+
+/// Writes meshes, each as an object if it has a name, along with their materials.
+fn write_files(
+    meshes: &[(Option<&str>, &Mesh)],
+    materials: &[Material],
+    path: &Path,
+) -> Result<(), Error> {
+    let library = path.with_extension("mtl");
+    let library_name = library
+        .file_name()
+        .and_then(|name| name.to_str())
+        .ok_or(format_err!("invalid path {}", path.display()))?;
+
+    let mut writer = ObjWriter::default();
+    let mut materials = materials.to_vec();
+    writer.material_names = (0..materials.len())
+        .map(|id| (materials[id].name.clone(), id))
+        .collect();
+    for &(name, mesh) in meshes {
+        if let Some(name) = name {
+            writeln!(writer.faces, "o {}", name).unwrap();
+        }
+        writer.write_mesh(mesh, &mut materials);
+    }
+
+    let file = std::fs::File::create(path)?;
+    let mut out = std::io::BufWriter::new(file);
+    if !materials.is_empty() {
+        writeln!(out, "mtllib {}", library_name)?;
+    }
+    out.write_all(writer.positions.as_bytes())?;
+    out.write_all(writer.uvs.as_bytes())?;
+    out.write_all(writer.normals.as_bytes())?;
+    out.write_all(writer.faces.as_bytes())?;
+    out.flush()?;
+
+    if !materials.is_empty() {
+        super::mtl::save(&materials, &library)?;
+    }
+    Ok(())
+}
+
+/// Builds the sections of an OBJ file, writing every distinct position, texture coordinate
+/// and normal only once.
+#[derive(Default)]
+struct ObjWriter {
+    positions: String,
+    uvs: String,
+    normals: String,
+    faces: String,
+    /// Indices by the bits of their values, since that's what has to match exactly.
+    /// Written positions by their bits and those of their color, if they have one.
+    position_indices: HashMap<([u32; 3], Option<[u32; 3]>), usize>,
+    uv_indices: HashMap<[u32; 2], usize>,
+    normal_indices: HashMap<[u32; 3], usize>,
+    /// Materials by name, so that ones made for colors aren't added twice.
+    material_names: HashMap<String, MaterialId>,
+    /// Materials made for colors, by the color rounded the way their names are.
+    color_materials: HashMap<[u8; 4], MaterialId>,
+}
+
+impl ObjWriter {
+    fn write_mesh(&mut self, mesh: &Mesh, materials: &mut Vec<Material>) {
+        let mut current = None;
+
+        for triangle in mesh.triangles() {
+            // Meshes without a material are colored by their vertices, so make a material out
+            // of the color instead. Vertices whose color doesn't match the material keep theirs.
+            let material = match mesh.material.filter(|&id| id < materials.len()) {
+                Some(id) => id,
+                None => self.color_material(triangle[0].color, materials),
+            };
+            if current != Some(material) {
+                writeln!(self.faces, "usemtl {}", materials[material].name).unwrap();
+                current = Some(material);
+            }
+            let material_color = materials[material].diffuse;
+
+            self.faces.push('f');
+            for vertex in &triangle {
+                let color = vertex.color.xyz();
+                let color = if bits3(&color) == bits3(&material_color) {
+                    None
+                } else {
+                    Some(color)
+                };
+                let position = self.position(&vertex.position, color);
+                let uv = self.uv(&vertex.uv);
+                let normal = self.normal(&vertex.normal);
+                write!(self.faces, " {}/{}/{}", position, uv, normal).unwrap();
+            }
+            self.faces.push('\n');
+        }
+    }
+
+    fn position(&mut self, position: &Vec3, color: Option<Vec3>) -> usize {
+        let key = (bits3(position), color.map(|color| bits3(&color)));
+
+        let next = self.position_indices.len() + 1;
+        let out = &mut self.positions;
+        *self.position_indices.entry(key).or_insert_with(|| {
+            match color {
+                Some(c) => writeln!(
+                    out,
+                    "v {} {} {} {} {} {}",
+                    position.x, position.y, position.z, c.x, c.y, c.z
+                ),
+                None => writeln!(out, "v {} {} {}", position.x, position.y, position.z),
+            }
+            .unwrap();
+            next
+        })
+    }
+
+    fn uv(&mut self, uv: &Vec2) -> usize {
+        let next = self.uv_indices.len() + 1;
+        let out = &mut self.uvs;
+        *self
+            .uv_indices
+            .entry([uv.x.to_bits(), uv.y.to_bits()])
+            .or_insert_with(|| {
+                writeln!(out, "vt {} {}", uv.x, uv.y).unwrap();
+                next
+            })
+    }
+
+    /// Finds or adds a material with the given color, named after it.
+    fn color_material(&mut self, color: Vec4, materials: &mut Vec<Material>) -> MaterialId {
+        let channel = |value: f32| (clamp((0.0, 1.0), value) * 255.0).round() as u8;
+        let key = [
+            channel(color.x),
+            channel(color.y),
+            channel(color.z),
+            channel(color.w),
+        ];
+        if let Some(&id) = self.color_materials.get(&key) {
+            return id;
+        }
+
+        let name = format!(
+            "color_{:02x}{:02x}{:02x}{:02x}",
+            key[0], key[1], key[2], key[3]
+        );
+        let id = *self.material_names.entry(name).or_insert_with_key(|name| {
+            let mut material = Material::new(name);
+            material.diffuse = color.xyz();
+            material.opacity = color.w;
+            materials.push(material);
+            materials.len() - 1
+        });
+        self.color_materials.insert(key, id);
+        id
+    }
+
+    fn normal(&mut self, normal: &Vec3) -> usize {
+        let next = self.normal_indices.len() + 1;
+        let out = &mut self.normals;
+        *self.normal_indices.entry(bits3(normal)).or_insert_with(|| {
+            writeln!(out, "vn {} {} {}", normal.x, normal.y, normal.z).unwrap();
+            next
+        })
+    }
+}
+
+fn bits3(v: &Vec3) -> [u32; 3] {
+    [v.x.to_bits(), v.y.to_bits(), v.z.to_bits()]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::temp_dir;
+
+    fn vertex(x: f32, y: f32, color: Vec4) -> Vertex {
+        Vertex {
+            position: Vec3::new(x, y, 0.0),
+            uv: Vec2::new(x, y),
+            normal: Vec3::new(0.0, 0.0, 1.0),
+            color,
+        }
+    }
+
+    /// A square with a red and a blue half, colored by its vertices.
+    fn square() -> Mesh {
+        let red = Vec4::new(1.0, 0.0, 0.0, 1.0);
+        let blue = Vec4::new(0.0, 0.0, 1.0, 1.0);
+        Mesh::from_triangles(
+            vec![
+                [
+                    vertex(0.0, 0.0, red),
+                    vertex(1.0, 0.0, red),
+                    vertex(1.0, 1.0, red),
+                ],
+                [
+                    vertex(0.0, 0.0, blue),
+                    vertex(1.0, 1.0, blue),
+                    vertex(0.0, 1.0, blue),
+                ],
+            ],
+            None,
+        )
+    }
+
+    fn corners(mesh: &Mesh) -> Vec<[(f32, f32, f32, f32); 3]> {
+        mesh.triangles()
+            .map(|triangle| {
+                let corner = |i: usize| {
+                    let Vertex {
+                        position, color, ..
+                    } = triangle[i];
+                    (position.x, position.y, color.x, color.z)
+                };
+                [corner(0), corner(1), corner(2)]
+            })
+            .collect()
+    }
+
+    #[test]
+    fn round_trips_a_colored_mesh() {
+        let path = temp_dir("obj-round-trip").join("square.obj");
+        save(&square(), &path).unwrap();
+
+        let model = load(&path).unwrap();
+        assert_eq!(model.materials.len(), 2);
+        assert_eq!(model.materials[0].name, "color_ff0000ff");
+        assert_eq!(
+            model.sources,
+            vec![path.clone(), path.with_extension("mtl")]
+        );
+
+        let mesh = model.merge();
+        assert_eq!(corners(&mesh), corners(&square()));
+        assert_eq!(mesh.vertices.len(), square().vertices.len());
+    }
+
+    #[test]
+    fn keeps_black_vertices_apart_from_uncolored_ones() {
+        // Both triangles get the white material, so only the black corner is written with
+        // a color, at the same position as an uncolored one.
+        let white = Vec4::new(1.0, 1.0, 1.0, 1.0);
+        let black = Vec4::new(0.0, 0.0, 0.0, 1.0);
+        let mesh = Mesh::from_triangles(
+            vec![
+                [
+                    vertex(0.0, 0.0, white),
+                    vertex(1.0, 0.0, white),
+                    vertex(1.0, 1.0, white),
+                ],
+                [
+                    vertex(1.0, 0.0, white),
+                    vertex(1.0, 1.0, white),
+                    vertex(0.0, 0.0, black),
+                ],
+            ],
+            None,
+        );
+
+        let path = temp_dir("obj-black").join("mesh.obj");
+        save(&mesh, &path).unwrap();
+        let loaded = load(&path).unwrap().merge();
+        assert_eq!(corners(&loaded), corners(&mesh));
+    }
+
+    #[test]
+    fn round_trips_a_model_with_materials() {
+        let mut material = Material::new("gold");
+        material.diffuse = Vec3::new(1.0, 0.8, 0.0);
+        let mut textured = square();
+        textured.material = Some(0);
+        let model = Model {
+            parts: vec![
+                Part {
+                    name: "gold".to_owned(),
+                    mesh: textured,
+                },
+                Part {
+                    name: "colors".to_owned(),
+                    mesh: square(),
+                },
+            ],
+            materials: vec![material],
+            sources: vec![],
+        };
+
+        let path = temp_dir("obj-model").join("model.obj");
+        save_model(&model, &path).unwrap();
+        let loaded = load(&path).unwrap();
+
+        let names: Vec<&str> = loaded.materials.iter().map(|m| m.name.as_str()).collect();
+        assert_eq!(names, ["gold", "color_ff0000ff", "color_0000ffff"]);
+        let parts: Vec<(&str, Option<MaterialId>)> = loaded
+            .parts
+            .iter()
+            .map(|part| (part.name.as_str(), part.mesh.material))
+            .collect();
+        assert_eq!(
+            parts,
+            [("gold", Some(0)), ("colors", Some(1)), ("colors", Some(2))]
+        );
+    }
+
+    #[test]
+    fn reuses_materials_made_for_colors() {
+        let mut writer = ObjWriter::default();
+        let mut materials = vec![Material::new("color_ff0000ff")];
+        writer.material_names.insert("color_ff0000ff".to_owned(), 0);
+
+        let red = Vec4::new(1.0, 0.0, 0.0, 1.0);
+        let green = Vec4::new(0.0, 1.0, 0.0, 1.0);
+        assert_eq!(writer.color_material(red, &mut materials), 0);
+        assert_eq!(writer.color_material(green, &mut materials), 1);
+        assert_eq!(writer.color_material(green, &mut materials), 1);
+        // Close enough to round to the same name.
+        assert_eq!(
+            writer.color_material(Vec4::new(0.001, 1.0, 0.0, 1.0), &mut materials),
+            1
+        );
+        assert_eq!(materials.len(), 2);
+    }
+
+    #[test]
+    fn resolves_relative_indices_and_triangulates() {
+        let path = temp_dir("obj-relative").join("quad.obj");
+        std::fs::write(&path, "v 0 0 0\nv 1 0 0\nv 1 1 0\nv 0 1 0\nf -4 -3 -2 -1\n").unwrap();
+
+        let model = load(&path).unwrap();
+        let mesh = &model.parts[0].mesh;
+        assert_eq!(mesh.indices.len(), 2);
+        // Without normals in the file, they're made up.
+        assert_eq!(mesh.vertices[0].normal.z, 1.0);
+    }
+
+    #[test]
+    fn reports_where_errors_are() {
+        let directory = temp_dir("obj-errors");
+        let cases = [
+            ("v 0 0 0\nf 1 1 0\n", 2, "index 0"),
+            ("v 0 0 0\n\nf 1 1 5\n", 3, "out of bounds"),
+            ("v 0 0 x\n", 1, "invalid number"),
+            ("v 0 0 0\nf 1 1\n", 2, "only 2 vertices"),
+        ];
+        for (text, line, problem) in cases {
+            let path = directory.join("broken.obj");
+            std::fs::write(&path, text).unwrap();
+            let error = load(&path).unwrap_err();
+            assert_eq!(error.line, Some(line), "{}", error);
+            assert!(error.to_string().contains(problem), "{}", error);
+        }
+    }
+
+    #[test]
+    fn lenient_mode_turns_unknown_directives_into_warnings() {
+        let path = temp_dir("obj-lenient").join("odd.obj");
+        std::fs::write(&path, "v 0 0 0\nv 1 0 0\nv 0 1 0\nfoo bar\nf 1 2 3\n").unwrap();
+        assert!(load(&path).is_err());
+
+        let options = LoadOptions {
+            lenient: true,
+            ..LoadOptions::default()
+        };
+        let (model, warnings) = load_with_warnings(&path, &options).unwrap();
+        assert_eq!(model.parts[0].mesh.indices.len(), 1);
+        assert_eq!(warnings.len(), 1);
+        assert_eq!(warnings[0].line, Some(4));
+    }
+
+    #[test]
+    fn sources_include_libraries_and_textures_even_when_loading_fails() {
+        let directory = temp_dir("obj-sources");
+        let path = directory.join("model.obj");
+        std::fs::write(&path, "mtllib a.mtl\nmtllib b.mtl\n").unwrap();
+        std::fs::write(directory.join("a.mtl"), "newmtl a\nmap_Kd missing.ppm\n").unwrap();
+
+        let mut sources = vec![];
+        assert!(load_with_sources(&path, &LoadOptions::default(), &mut sources).is_err());
+        let names: Vec<&str> = sources
+            .iter()
+            .map(|source| source.file_name().unwrap().to_str().unwrap())
+            .collect();
+        assert_eq!(names, vec!["model.obj", "a.mtl", "missing.ppm", "b.mtl"]);
+
+        std::fs::write(directory.join("b.mtl"), "newmtl b\n").unwrap();
+        let model = load(&path).unwrap();
+        assert_eq!(model.sources, sources);
+    }
 }
@@ -0,0 +1,651 @@
+use crate::geometry::*;
+use crate::math::*;
+use std::path::Path;
+
+use ::failure::format_err;
+use ::failure::Error;
+
+/// What a PLY file describes: faces if it has any, or else just its vertices.
+#[derive(Debug)]
+pub enum Geometry {
+    Mesh(Mesh),
+    Points(PointCloud),
+}
+
+/// A loaded PLY file.
+#[derive(Debug)]
+pub struct Ply {
+    pub geometry: Geometry,
+    /// Every property that isn't part of the geometry, such as the confidence and
+    /// intensity of scanned vertices.
+    pub attributes: Vec<Attribute>,
+}
+
+/// A property of some element of a PLY file, with a value for each element.
+#[derive(Debug)]
+pub struct Attribute {
+    pub element: String,
+    pub name: String,
+    pub values: Values,
+}
+
+#[derive(Debug)]
+pub enum Values {
+    Scalars(Vec<f64>),
+    Lists(Vec<Vec<f64>>),
+}
+
+impl Ply {
+    pub fn attribute(&self, element: &str, name: &str) -> Option<&Attribute> {
+        self.attributes
+            .iter()
+            .find(|attribute| attribute.element == element && attribute.name == name)
+    }
+}
+
+/// Vertices without a color property get this one.
+const DEFAULT_COLOR: Vec4 = Vec4 {
+    x: 1.0,
+    y: 1.0,
+    z: 1.0,
+    w: 1.0,
+};
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+enum Encoding {
+    Ascii,
+    LittleEndian,
+    BigEndian,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+enum Type {
+    I8,
+    U8,
+    I16,
+    U16,
+    I32,
+    U32,
+    F32,
+    F64,
+}
+
+impl Type {
+    fn parse(name: &str) -> Result<Type, Error> {
+        Ok(match name {
+            "char" | "int8" => Type::I8,
+            "uchar" | "uint8" => Type::U8,
+            "short" | "int16" => Type::I16,
+            "ushort" | "uint16" => Type::U16,
+            "int" | "int32" => Type::I32,
+            "uint" | "uint32" => Type::U32,
+            "float" | "float32" => Type::F32,
+            "double" | "float64" => Type::F64,
+            name => return Err(format_err!("unknown property type {:?}", name)),
+        })
+    }
+
+    fn size(self) -> usize {
+        match self {
+            Type::I8 | Type::U8 => 1,
+            Type::I16 | Type::U16 => 2,
+            Type::I32 | Type::U32 | Type::F32 => 4,
+            Type::F64 => 8,
+        }
+    }
+
+    /// The largest value of integer types, which colors are stored as fractions of.
+    fn max(self) -> f64 {
+        match self {
+            Type::I8 => 127.0,
+            Type::U8 => 255.0,
+            Type::I16 => 32767.0,
+            Type::U16 => 65535.0,
+            Type::I32 => 2_147_483_647.0,
+            Type::U32 => 4_294_967_295.0,
+            Type::F32 | Type::F64 => 1.0,
+        }
+    }
+}
+
+struct Property {
+    name: String,
+    value_type: Type,
+    /// The type of the length, for list properties.
+    list: Option<Type>,
+}
+
+struct Element {
+    name: String,
+    count: usize,
+    properties: Vec<Property>,
+}
+
+/// The values of one property of an element.
+struct Column {
+    name: String,
+    value_type: Type,
+    values: Values,
+}
+
+/// Loads a PLY file. Files with faces become a mesh, with smooth normals if the
+/// vertices have none; files with only vertices become a point cloud.
+pub fn load(path: &Path) -> Result<Ply, Error> {
+    let data = std::fs::read(path)?;
+    parse(&data).map_err(|e| format_err!("could not load {}: {}", path.display(), e))
+}
+
+fn parse(data: &[u8]) -> Result<Ply, Error> {
+    let (encoding, elements, body) = parse_header(data)?;
+    let mut reader = Reader {
+        data: body,
+        position: 0,
+        encoding,
+    };
+
+    let mut vertices = vec![];
+    let mut faces: Option<Vec<Vec<f64>>> = None;
+    let mut attributes = vec![];
+
+    for element in &elements {
+        let columns = reader
+            .read_element(element)
+            .map_err(|e| format_err!("in element {:?}: {}", element.name, e))?;
+
+        let rest = match element.name.as_str() {
+            "vertex" => {
+                let (parsed, rest) = vertices_from(columns, element.count)?;
+                vertices = parsed;
+                rest
+            }
+            "face" => {
+                let mut rest = vec![];
+                for column in columns {
+                    match column.values {
+                        Values::Lists(lists)
+                            if column.name == "vertex_indices" || column.name == "vertex_index" =>
+                        {
+                            faces = Some(lists)
+                        }
+                        _ => rest.push(column),
+                    }
+                }
+                rest
+            }
+            _ => columns,
+        };
+
+        attributes.extend(rest.into_iter().map(|column| Attribute {
+            element: element.name.clone(),
+            name: column.name,
+            values: column.values,
+        }));
+    }
+
+    let geometry = match faces {
+        Some(faces) if !faces.is_empty() => Geometry::Mesh(mesh_from(vertices, &faces)?),
+        _ => Geometry::Points(PointCloud { points: vertices }),
+    };
+
+    Ok(Ply {
+        geometry,
+        attributes,
+    })
+}
+
+fn parse_header(data: &[u8]) -> Result<(Encoding, Vec<Element>, &[u8]), Error> {
+    let mut elements: Vec<Element> = vec![];
+    let mut encoding = None;
+    let mut position = 0;
+
+    if !data.starts_with(b"ply") {
+        return Err(format_err!("not a PLY file"));
+    }
+
+    loop {
+        let end = data[position..]
+            .iter()
+            .position(|&b| b == b'\n')
+            .ok_or(format_err!("header never ends"))?;
+        let line = std::str::from_utf8(&data[position..position + end])
+            .map_err(|_| format_err!("header isn't text"))?;
+        position += end + 1;
+
+        let mut words = line.split_whitespace();
+        match words.next() {
+            Some("format") => {
+                encoding = Some(match words.next() {
+                    Some("ascii") => Encoding::Ascii,
+                    Some("binary_little_endian") => Encoding::LittleEndian,
+                    Some("binary_big_endian") => Encoding::BigEndian,
+                    format => return Err(format_err!("unknown format {:?}", format)),
+                })
+            }
+            Some("element") => {
+                let name = words.next().ok_or(format_err!("element without a name"))?;
+                let count = words
+                    .next()
+                    .and_then(|count| count.parse().ok())
+                    .ok_or(format_err!("element {:?} has no count", name))?;
+                elements.push(Element {
+                    name: name.to_owned(),
+                    count,
+                    properties: vec![],
+                });
+            }
+            Some("property") => {
+                let element = elements
+                    .last_mut()
+                    .ok_or(format_err!("property before any element"))?;
+                let words: Vec<&str> = words.collect();
+                let property = match words[..] {
+                    ["list", length, value, name] => Property {
+                        name: name.to_owned(),
+                        value_type: Type::parse(value)?,
+                        list: Some(Type::parse(length)?),
+                    },
+                    [value, name] => Property {
+                        name: name.to_owned(),
+                        value_type: Type::parse(value)?,
+                        list: None,
+                    },
+                    _ => return Err(format_err!("invalid property {:?}", line)),
+                };
+                element.properties.push(property);
+            }
+            Some("end_header") => break,
+            // "ply", "comment" and "obj_info" don't matter.
+            _ => {}
+        }
+    }
+
+    let encoding = encoding.ok_or(format_err!("no format in header"))?;
+    Ok((encoding, elements, &data[position..]))
+}
+
+struct Reader<'a> {
+    data: &'a [u8],
+    position: usize,
+    encoding: Encoding,
+}
+
+impl Reader<'_> {
+    fn read_element(&mut self, element: &Element) -> Result<Vec<Column>, Error> {
+        // Every value takes at least a byte, so there can't be more elements than bytes
+        // left, whatever the header claims.
+        let capacity = element.count.min(self.data.len() - self.position);
+        let mut columns: Vec<Column> = element
+            .properties
+            .iter()
+            .map(|property| Column {
+                name: property.name.clone(),
+                value_type: property.value_type,
+                values: match property.list {
+                    Some(_) => Values::Lists(Vec::with_capacity(capacity)),
+                    None => Values::Scalars(Vec::with_capacity(capacity)),
+                },
+            })
+            .collect();
+
+        // Elements without properties take up no space, so there's nothing to read,
+        // however many of them there are.
+        if columns.is_empty() {
+            return Ok(columns);
+        }
+
+        for i in 0..element.count {
+            for (property, column) in element.properties.iter().zip(&mut columns) {
+                let read = match (property.list, &mut column.values) {
+                    (Some(length_type), Values::Lists(lists)) => self
+                        .read_list(length_type, property.value_type)
+                        .map(|list| lists.push(list)),
+                    (None, Values::Scalars(scalars)) => self
+                        .read(property.value_type)
+                        .map(|value| scalars.push(value)),
+                    _ => unreachable!(),
+                };
+                read.map_err(|e| format_err!("{} {}: {}", element.name, i, e))?;
+            }
+        }
+
+        Ok(columns)
+    }
+
+    fn read_list(&mut self, length_type: Type, value_type: Type) -> Result<Vec<f64>, Error> {
+        let length = self.read(length_type)?;
+        if !is_index(length) {
+            return Err(format_err!("invalid list length {}", length));
+        }
+        (0..length as usize)
+            .map(|_| self.read(value_type))
+            .collect()
+    }
+
+    fn read(&mut self, value_type: Type) -> Result<f64, Error> {
+        if self.encoding == Encoding::Ascii {
+            let rest = &self.data[self.position..];
+            let start = rest
+                .iter()
+                .position(|b| !b.is_ascii_whitespace())
+                .ok_or(format_err!("file ends early"))?;
+            let length = rest[start..]
+                .iter()
+                .position(|b| b.is_ascii_whitespace())
+                .unwrap_or(rest.len() - start);
+            self.position += start + length;
+
+            let word = std::str::from_utf8(&rest[start..start + length]).unwrap_or("");
+            return word
+                .parse()
+                .map_err(|_| format_err!("invalid number {:?}", word));
+        }
+
+        let size = value_type.size();
+        let bytes = self
+            .data
+            .get(self.position..self.position + size)
+            .ok_or(format_err!("file ends early"))?;
+        self.position += size;
+
+        let mut buffer = [0; 8];
+        buffer[..size].copy_from_slice(bytes);
+        if self.encoding == Encoding::BigEndian {
+            buffer[..size].reverse();
+        }
+        let [b0, b1, b2, b3, ..] = buffer;
+
+        Ok(match value_type {
+            Type::I8 => b0 as i8 as f64,
+            Type::U8 => b0 as f64,
+            Type::I16 => i16::from_le_bytes([b0, b1]) as f64,
+            Type::U16 => u16::from_le_bytes([b0, b1]) as f64,
+            Type::I32 => i32::from_le_bytes([b0, b1, b2, b3]) as f64,
+            Type::U32 => u32::from_le_bytes([b0, b1, b2, b3]) as f64,
+            Type::F32 => f32::from_le_bytes([b0, b1, b2, b3]) as f64,
+            Type::F64 => f64::from_le_bytes(buffer),
+        })
+    }
+}
+
+/// Vertex properties that go into `Vertex` rather than attributes.
+const VERTEX_PROPERTIES: &[&str] = &[
+    "x",
+    "y",
+    "z",
+    "nx",
+    "ny",
+    "nz",
+    "red",
+    "green",
+    "blue",
+    "alpha",
+    "diffuse_red",
+    "diffuse_green",
+    "diffuse_blue",
+    "s",
+    "t",
+    "u",
+    "v",
+    "texture_u",
+    "texture_v",
+];
+
+/// Builds vertices out of the vertex properties we know, and returns the rest.
+fn vertices_from(columns: Vec<Column>, count: usize) -> Result<(Vec<Vertex>, Vec<Column>), Error> {
+    let mut known: Vec<Column> = vec![];
+    let mut rest = vec![];
+    for column in columns {
+        let is_known = VERTEX_PROPERTIES.contains(&column.name.as_str());
+        if is_known && matches!(column.values, Values::Scalars(_)) {
+            known.push(column);
+        } else {
+            rest.push(column);
+        }
+    }
+
+    let find = |names: &[&str]| {
+        known
+            .iter()
+            .find(|column| names.contains(&column.name.as_str()))
+            .map(|column| match &column.values {
+                Values::Scalars(values) => (values, column.value_type),
+                Values::Lists(_) => unreachable!(),
+            })
+    };
+    let value = |column: Option<(&Vec<f64>, Type)>, i: usize, default: f32| {
+        column
+            .map(|(values, _)| values[i] as f32)
+            .unwrap_or(default)
+    };
+    // Integer colors go from 0 to the largest value of their type.
+    let color = |column: Option<(&Vec<f64>, Type)>, i: usize| {
+        column
+            .map(|(values, value_type)| (values[i] / value_type.max()) as f32)
+            .unwrap_or(1.0)
+    };
+
+    let (x, y, z) = match (find(&["x"]), find(&["y"]), find(&["z"])) {
+        (Some(x), Some(y), Some(z)) => (x, y, z),
+        _ => return Err(format_err!("vertices have no x, y and z")),
+    };
+    let normal = (find(&["nx"]), find(&["ny"]), find(&["nz"]));
+    let red = find(&["red", "diffuse_red"]);
+    let green = find(&["green", "diffuse_green"]);
+    let blue = find(&["blue", "diffuse_blue"]);
+    let alpha = find(&["alpha"]);
+    let u = find(&["s", "u", "texture_u"]);
+    let v = find(&["t", "v", "texture_v"]);
+    let has_color = red.is_some() || green.is_some() || blue.is_some();
+
+    let vertices = (0..count)
+        .map(|i| Vertex {
+            position: Vec3::new(
+                value(Some(x), i, 0.0),
+                value(Some(y), i, 0.0),
+                value(Some(z), i, 0.0),
+            ),
+            uv: Vec2::new(value(u, i, 0.0), value(v, i, 0.0)),
+            normal: Vec3::new(
+                value(normal.0, i, 0.0),
+                value(normal.1, i, 0.0),
+                value(normal.2, i, 0.0),
+            ),
+            color: if has_color {
+                Vec4::new(
+                    color(red, i),
+                    color(green, i),
+                    color(blue, i),
+                    color(alpha, i),
+                )
+            } else {
+                DEFAULT_COLOR
+            },
+        })
+        .collect();
+
+    // Normals only count if all three components are there; otherwise keep them around.
+    if normal.0.is_none() || normal.1.is_none() || normal.2.is_none() {
+        rest.extend(
+            known
+                .into_iter()
+                .filter(|column| ["nx", "ny", "nz"].contains(&column.name.as_str())),
+        );
+    }
+
+    Ok((vertices, rest))
+}
+
+/// Whether a value read from the file can be used as an index or length: values of any
+/// type can, as long as they're whole numbers that aren't negative.
+fn is_index(value: f64) -> bool {
+    value >= 0.0 && value.fract() == 0.0
+}
+
+fn mesh_from(vertices: Vec<Vertex>, faces: &[Vec<f64>]) -> Result<Mesh, Error> {
+    if vertices.len() > u32::MAX as usize {
+        return Err(format_err!("too many vertices for a mesh"));
+    }
+    let has_normals = vertices.iter().any(|vertex| vertex.normal.length() > 0.0);
+    let mut indices = Vec::with_capacity(faces.len());
+
+    for (i, face) in faces.iter().enumerate() {
+        let face: Vec<u32> = face
+            .iter()
+            .map(|&index| {
+                if !is_index(index) {
+                    Err(format_err!("face {} has invalid vertex index {}", i, index))
+                } else if index < vertices.len() as f64 {
+                    Ok(index as u32)
+                } else {
+                    Err(format_err!(
+                        "face {} has vertex index {} out of bounds; there are {} vertices",
+                        i,
+                        index,
+                        vertices.len()
+                    ))
+                }
+            })
+            .collect::<Result<_, _>>()?;
+
+        let corners = if face.len() == 3 {
+            vec![[0, 1, 2]]
+        } else {
+            let polygon: Vec<Vec3> = face
+                .iter()
+                .map(|&index| vertices[index as usize].position)
+                .collect();
+            triangulate(&polygon)
+        };
+        indices.extend(corners.iter().map(|&[a, b, c]| [face[a], face[b], face[c]]));
+    }
+
+    let mut mesh = Mesh::new(vertices, indices, None);
+    // Scans are smooth surfaces, so give them smooth normals if they have none.
+    if !has_normals {
+        mesh.generate_normals(&NormalOptions::default(), None);
+    }
+    Ok(mesh)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TRIANGLE_HEADER: &str = "ply\nformat ascii 1.0\ncomment made by hand\n\
+        element vertex 3\nproperty float x\nproperty float y\nproperty float z\n\
+        element face 1\nproperty list uchar int vertex_indices\nend_header\n";
+
+    fn parse_str(text: &str) -> Result<Ply, Error> {
+        parse(text.as_bytes())
+    }
+
+    fn mesh(ply: Ply) -> Mesh {
+        match ply.geometry {
+            Geometry::Mesh(mesh) => mesh,
+            Geometry::Points(_) => panic!("expected a mesh"),
+        }
+    }
+
+    #[test]
+    fn parses_ascii_meshes_with_smooth_normals() {
+        let ply = parse_str(&format!(
+            "{}0 0 0\n1 0 0\n0 1 0\n3 0 1 2\n",
+            TRIANGLE_HEADER
+        ))
+        .unwrap();
+        let mesh = mesh(ply);
+        assert_eq!(mesh.indices, vec![[0, 1, 2]]);
+        assert_eq!(mesh.vertices[2].position.y, 1.0);
+        assert_eq!(mesh.vertices[0].normal.z, 1.0);
+    }
+
+    #[test]
+    fn triangulates_polygons() {
+        let text = "ply\nformat ascii 1.0\nelement vertex 4\nproperty float x\n\
+            property float y\nproperty float z\nelement face 1\n\
+            property list uchar int vertex_index\nend_header\n\
+            0 0 0\n1 0 0\n1 1 0\n0 1 0\n4 0 1 2 3\n";
+        assert_eq!(mesh(parse_str(text).unwrap()).indices.len(), 2);
+    }
+
+    #[test]
+    fn parses_binary_points_with_colors_and_attributes() {
+        for (format, to_bytes) in [
+            (
+                "binary_little_endian",
+                f32::to_le_bytes as fn(f32) -> [u8; 4],
+            ),
+            ("binary_big_endian", f32::to_be_bytes),
+        ] {
+            let mut data = format!(
+                "ply\nformat {} 1.0\nelement vertex 2\nproperty float x\nproperty float y\n\
+                 property float z\nproperty uchar red\nproperty float confidence\nend_header\n",
+                format
+            )
+            .into_bytes();
+            for (position, red, confidence) in
+                [([1.0, 2.0, 3.0], 255, 0.5), ([4.0, 5.0, 6.0], 0, 1.0)]
+            {
+                for coordinate in position {
+                    data.extend(to_bytes(coordinate));
+                }
+                data.push(red);
+                data.extend(to_bytes(confidence));
+            }
+
+            let ply = parse(&data).unwrap();
+            let points = match &ply.geometry {
+                Geometry::Points(cloud) => &cloud.points,
+                Geometry::Mesh(_) => panic!("expected points"),
+            };
+            assert_eq!(points.len(), 2, "{}", format);
+            assert_eq!(points[1].position.z, 6.0);
+            assert_eq!((points[0].color.x, points[1].color.x), (1.0, 0.0));
+            match &ply.attribute("vertex", "confidence").unwrap().values {
+                Values::Scalars(values) => assert_eq!(values, &[0.5, 1.0]),
+                Values::Lists(_) => panic!("expected scalars"),
+            }
+        }
+    }
+
+    #[test]
+    fn rejects_indices_that_arent_whole_numbers_in_bounds() {
+        let faces = [
+            ("3 0 1 3", "out of bounds"),
+            ("3 0 1 -1", "invalid vertex index -1"),
+            ("3 0 1 1.5", "invalid vertex index 1.5"),
+            ("2.5 0 1 2", "invalid list length 2.5"),
+        ];
+        for (face, problem) in faces {
+            let header = TRIANGLE_HEADER.replace("uchar int", "float float");
+            let text = format!("{}0 0 0\n1 0 0\n0 1 0\n{}\n", header, face);
+            let error = parse_str(&text).unwrap_err().to_string();
+            assert!(error.contains(problem), "{}: {}", face, error);
+        }
+    }
+
+    #[test]
+    fn huge_counts_fail_without_allocating_them() {
+        let header = TRIANGLE_HEADER.replace("vertex 3", &format!("vertex {}", usize::MAX));
+        let error = parse_str(&format!("{}0 0 0\n", header)).unwrap_err();
+        assert!(error.to_string().contains("file ends early"), "{}", error);
+
+        // Nothing to read for elements without properties, so this mustn't take forever.
+        let text = format!(
+            "ply\nformat binary_little_endian 1.0\nelement nothing {}\nend_header\n",
+            usize::MAX
+        );
+        assert!(parse_str(&text).is_ok());
+    }
+
+    #[test]
+    fn rejects_bad_headers() {
+        assert!(parse_str("plx\n").is_err());
+        assert!(parse_str("ply\nformat ascii 1.0\n").is_err());
+        assert!(parse_str("ply\nend_header\n").is_err());
+        assert!(parse_str("ply\nformat ascii 1.0\nproperty float x\nend_header\n").is_err());
+        assert!(
+            parse_str("ply\nformat ascii 1.0\nelement v 1\nproperty half x\nend_header\n").is_err()
+        );
+    }
+}
@@ -0,0 +1,350 @@
+use crate::geometry::*;
+use crate::math::*;
+use std::io::{BufRead, Write};
+use std::path::Path;
+
+use ::failure::format_err;
+use ::failure::Error;
+
+/// Size of the header of a binary STL file, and of each triangle in it.
+const HEADER_SIZE: usize = 80;
+const TRIANGLE_SIZE: usize = 50;
+
+/// STL has no colors, so everything is this.
+const COLOR: Vec4 = Vec4 {
+    x: 1.0,
+    y: 1.0,
+    z: 1.0,
+    w: 1.0,
+};
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Format {
+    Ascii,
+    Binary,
+}
+
+/// Loads an ASCII or binary STL file. Every vertex gets the normal of its facet.
+pub fn load(path: &Path) -> Result<Mesh, Error> {
+    let file = std::fs::File::open(path)?;
+    let size = file.metadata()?.len() as usize;
+    let mut reader = std::io::BufReader::new(file);
+
+    // Binary files are supposed to not start with "solid", but plenty of exporters
+    // put it there anyway, so go by whether the size adds up instead.
+    let header = reader.fill_buf()?;
+    let binary = header.len() >= HEADER_SIZE + 4 && {
+        let count = u32::from_le_bytes([header[80], header[81], header[82], header[83]]) as usize;
+        size == HEADER_SIZE + 4 + count * TRIANGLE_SIZE
+    };
+    if !binary && !header.starts_with(b"solid") {
+        return Err(format_err!(
+            "could not load {}: not an ASCII STL file, and its size doesn't match the \
+             triangle count of a binary one",
+            path.display()
+        ));
+    }
+
+    let triangles = if binary {
+        read_binary(reader, size)
+    } else {
+        read_ascii(reader)
+    }
+    .map_err(|e| format_err!("could not load {}: {}", path.display(), e))?;
+
+    Ok(Mesh::from_triangles(triangles, None))
+}
+
+/// Reads a binary STL file of `size` bytes, which caps how many triangles it can hold
+/// whatever its header says.
+fn read_binary(mut reader: impl BufRead, size: usize) -> Result<Vec<Triangle>, Error> {
+    let mut header = [0; HEADER_SIZE + 4];
+    reader.read_exact(&mut header)?;
+    let count = u32::from_le_bytes([header[80], header[81], header[82], header[83]]) as usize;
+
+    let fits = size.saturating_sub(HEADER_SIZE + 4) / TRIANGLE_SIZE;
+    let mut triangles = Vec::with_capacity(count.min(fits));
+    let mut facet = [0; TRIANGLE_SIZE];
+    for i in 0..count {
+        reader
+            .read_exact(&mut facet)
+            .map_err(|_| format_err!("file ends at triangle {} of {}", i, count))?;
+
+        let vec3 = |offset: usize| {
+            let float = |at: usize| {
+                f32::from_le_bytes([facet[at], facet[at + 1], facet[at + 2], facet[at + 3]])
+            };
+            Vec3::new(float(offset), float(offset + 4), float(offset + 8))
+        };
+        // The last two bytes are an "attribute byte count" that nobody agrees on; skip it.
+        triangles.push(facet_triangle(vec3(0), [vec3(12), vec3(24), vec3(36)]));
+    }
+
+    Ok(triangles)
+}
+
+fn read_ascii(mut reader: impl BufRead) -> Result<Vec<Triangle>, Error> {
+    let mut triangles = vec![];
+    let mut normal = Vec3::new(0.0, 0.0, 0.0);
+    let mut positions = vec![];
+
+    // Reuse one line buffer; these files can have millions of lines.
+    let mut line = String::new();
+    let mut number = 0;
+    loop {
+        line.clear();
+        if reader.read_line(&mut line)? == 0 {
+            break;
+        }
+        number += 1;
+
+        let mut words = line.split_whitespace();
+        let parse = |words: &mut std::str::SplitWhitespace| -> Result<Vec3, Error> {
+            let mut coordinate = || -> Result<f32, Error> {
+                let word = words
+                    .next()
+                    .ok_or(format_err!("line {}: expected three numbers", number))?;
+                word.parse()
+                    .map_err(|_| format_err!("line {}: invalid number {:?}", number, word))
+            };
+            Ok(Vec3::new(coordinate()?, coordinate()?, coordinate()?))
+        };
+
+        match words.next() {
+            Some("facet") => {
+                if words.next() != Some("normal") {
+                    return Err(format_err!("line {}: expected \"facet normal\"", number));
+                }
+                normal = parse(&mut words)?;
+                positions.clear();
+            }
+            Some("vertex") => positions.push(parse(&mut words)?),
+            Some("endfacet") => match positions[..] {
+                [a, b, c] => triangles.push(facet_triangle(normal, [a, b, c])),
+                _ => {
+                    return Err(format_err!(
+                        "line {}: facet has {} vertices instead of 3",
+                        number,
+                        positions.len()
+                    ))
+                }
+            },
+            // "solid", "outer loop", "endloop" and "endsolid" carry nothing we need.
+            _ => {}
+        }
+    }
+
+    Ok(triangles)
+}
+
+/// Builds a triangle with the facet normal on every vertex. Normals are often left as
+/// zeros, and those get computed from the vertices instead.
+fn facet_triangle(normal: Vec3, positions: [Vec3; 3]) -> Triangle {
+    let normal = if normal.length() > 0.0 {
+        normal.normalize()
+    } else {
+        face_normal(&positions)
+    };
+
+    let vertex = |position: Vec3| Vertex {
+        position,
+        uv: Vec2::new(0.0, 0.0),
+        normal,
+        color: COLOR,
+    };
+    [
+        vertex(positions[0]),
+        vertex(positions[1]),
+        vertex(positions[2]),
+    ]
+}
+
+fn face_normal([a, b, c]: &[Vec3; 3]) -> Vec3 {
+    b.sub(a).cross(&c.sub(a)).normalize()
+}
+
+/// Saves a mesh as an STL file. The facet normals come from the winding of each
+/// triangle, or from its vertex normals if it has no area.
+pub fn save(mesh: &Mesh, path: &Path, format: Format) -> Result<(), Error> {
+    let file = std::fs::File::create(path)?;
+    let mut out = std::io::BufWriter::new(file);
+    let name = path
+        .file_stem()
+        .and_then(|stem| stem.to_str())
+        .unwrap_or("mesh");
+
+    match format {
+        Format::Ascii => write_ascii(mesh, name, &mut out)?,
+        Format::Binary => write_binary(mesh, name, &mut out)?,
+    }
+    out.flush()?;
+    Ok(())
+}
+
+pub fn write_ascii(mesh: &Mesh, name: &str, out: &mut impl Write) -> std::io::Result<()> {
+    writeln!(out, "solid {}", name)?;
+    for triangle in mesh.triangles() {
+        let n = facet_normal(&triangle);
+        writeln!(out, "  facet normal {:e} {:e} {:e}", n.x, n.y, n.z)?;
+        writeln!(out, "    outer loop")?;
+        for vertex in &triangle {
+            let p = vertex.position;
+            writeln!(out, "      vertex {:e} {:e} {:e}", p.x, p.y, p.z)?;
+        }
+        writeln!(out, "    endloop")?;
+        writeln!(out, "  endfacet")?;
+    }
+    writeln!(out, "endsolid {}", name)
+}
+
+pub fn write_binary(mesh: &Mesh, name: &str, out: &mut impl Write) -> std::io::Result<()> {
+    // The header mustn't start with "solid", or readers may take it for ASCII.
+    let mut header = [b' '; HEADER_SIZE];
+    let title = format!("binary STL {}", name);
+    let length = title.len().min(HEADER_SIZE);
+    header[..length].copy_from_slice(&title.as_bytes()[..length]);
+    out.write_all(&header)?;
+    out.write_all(&(mesh.indices.len() as u32).to_le_bytes())?;
+
+    let mut facet = [0; TRIANGLE_SIZE];
+    for triangle in mesh.triangles() {
+        let mut vectors = std::iter::once(facet_normal(&triangle))
+            .chain(triangle.iter().map(|vertex| vertex.position));
+        for chunk in facet[..48].chunks_exact_mut(12) {
+            let v = vectors.next().unwrap();
+            chunk[0..4].copy_from_slice(&v.x.to_le_bytes());
+            chunk[4..8].copy_from_slice(&v.y.to_le_bytes());
+            chunk[8..12].copy_from_slice(&v.z.to_le_bytes());
+        }
+        out.write_all(&facet)?;
+    }
+    Ok(())
+}
+
+fn facet_normal(triangle: &Triangle) -> Vec3 {
+    let normal = face_normal(&[
+        triangle[0].position,
+        triangle[1].position,
+        triangle[2].position,
+    ]);
+    if normal.length() > 0.0 {
+        normal
+    } else {
+        triangle[0]
+            .normal
+            .add(&triangle[1].normal)
+            .add(&triangle[2].normal)
+            .normalize()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::temp_dir;
+
+    fn square() -> Mesh {
+        let vertex = |x: f32, y: f32| Vertex {
+            position: Vec3::new(x, y, 0.0),
+            uv: Vec2::new(0.0, 0.0),
+            normal: Vec3::new(0.0, 0.0, 1.0),
+            color: COLOR,
+        };
+        Mesh::new(
+            vec![
+                vertex(0.0, 0.0),
+                vertex(1.0, 0.0),
+                vertex(1.0, 1.0),
+                vertex(0.0, 1.0),
+            ],
+            vec![[0, 1, 2], [0, 2, 3]],
+            None,
+        )
+    }
+
+    fn positions(mesh: &Mesh) -> Vec<[(f32, f32, f32); 3]> {
+        mesh.triangles()
+            .map(|triangle| {
+                let p = |i: usize| {
+                    let p = triangle[i].position;
+                    (p.x, p.y, p.z)
+                };
+                [p(0), p(1), p(2)]
+            })
+            .collect()
+    }
+
+    #[test]
+    fn round_trips_both_formats() {
+        let directory = temp_dir("stl-round-trip");
+        for (format, name) in [(Format::Ascii, "ascii.stl"), (Format::Binary, "binary.stl")] {
+            let path = directory.join(name);
+            save(&square(), &path, format).unwrap();
+            let loaded = load(&path).unwrap();
+
+            assert_eq!(positions(&loaded), positions(&square()), "{:?}", format);
+            // Shared corners are welded back together.
+            assert_eq!(loaded.vertices.len(), 4);
+            assert_eq!(loaded.vertices[0].normal.z, 1.0);
+        }
+    }
+
+    #[test]
+    fn binary_files_starting_with_solid_go_by_size() {
+        let mut data = vec![];
+        write_binary(&square(), "mesh", &mut data).unwrap();
+        data[..5].copy_from_slice(b"solid");
+        let path = temp_dir("stl-solid").join("mesh.stl");
+        std::fs::write(&path, data).unwrap();
+
+        assert_eq!(load(&path).unwrap().indices.len(), 2);
+    }
+
+    #[test]
+    fn rejects_binary_files_whose_size_doesnt_add_up() {
+        let directory = temp_dir("stl-size");
+        let mut data = vec![];
+        write_binary(&square(), "mesh", &mut data).unwrap();
+
+        let path = directory.join("truncated.stl");
+        std::fs::write(&path, &data[..data.len() - 1]).unwrap();
+        assert!(load(&path).is_err());
+
+        // A count that would take far more memory than the file holds.
+        data[80..84].copy_from_slice(&u32::MAX.to_le_bytes());
+        let path = directory.join("huge.stl");
+        std::fs::write(&path, &data).unwrap();
+        assert!(load(&path).is_err());
+    }
+
+    #[test]
+    fn read_binary_only_reserves_what_fits() {
+        let mut data = vec![0; HEADER_SIZE];
+        data.extend_from_slice(&u32::MAX.to_le_bytes());
+        let error = read_binary(&data[..], data.len()).unwrap_err();
+        assert!(error.to_string().contains("triangle 0 of"));
+    }
+
+    #[test]
+    fn computes_missing_normals_and_reports_bad_facets() {
+        let directory = temp_dir("stl-ascii");
+        let path = directory.join("zero.stl");
+        std::fs::write(
+            &path,
+            "solid t\nfacet normal 0 0 0\nouter loop\nvertex 0 0 0\nvertex 1 0 0\n\
+             vertex 0 1 0\nendloop\nendfacet\nendsolid t\n",
+        )
+        .unwrap();
+        assert_eq!(load(&path).unwrap().vertices[0].normal.z, 1.0);
+
+        let path = directory.join("short.stl");
+        std::fs::write(
+            &path,
+            "solid t\nfacet normal 0 0 1\nouter loop\nvertex 0 0 0\nvertex 1 0 0\n\
+             endloop\nendfacet\nendsolid t\n",
+        )
+        .unwrap();
+        let error = load(&path).unwrap_err().to_string();
+        assert!(error.contains("line 7: facet has 2 vertices"), "{}", error);
+    }
+}
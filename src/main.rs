@@ -1,23 +1,12 @@
-extern crate ctrlc;
-extern crate failure;
-
-mod camera;
-mod display_device;
-mod geometry;
-mod loaders;
-mod math;
-mod object;
-mod rasterizer;
-mod render_target;
-mod renderer;
-mod shaders;
-
-use display_device::DisplayDevice;
-use math::*;
+use hallon::display_device::DisplayDevice;
+use hallon::math::*;
+use hallon::render_target::RenderTarget;
+use hallon::*;
 use std::collections::HashMap;
 
 fn main() {
-    let dd = display_device::ConsoleDisplay { rgb: true };
+    let options = parse_options();
+    terminal::install_panic_hook();
 
     static STOP: std::sync::atomic::AtomicBool = std::sync::atomic::AtomicBool::new(false);
     ctrlc::set_handler(|| {
@@ -25,186 +14,452 @@ fn main() {
     })
     .unwrap();
 
-    let ground_color = Vec4::new(0.9, 0.8, 0.5, 1.0);
-    let quad_mesh = geometry::Mesh {
-        triangles: vec![
-            [
-                geometry::Vertex {
-                    position: Vec3::new(-1.0, -1.0, 0.0),
-                    color: ground_color,
-                    uv: Vec2::new(0.0, 0.0),
-                    normal: Vec3::new(0.0, 0.0, -1.0),
-                },
-                geometry::Vertex {
-                    position: Vec3::new(-1.0, 1.0, 0.0),
-                    color: ground_color,
-                    uv: Vec2::new(0.0, 1.0),
-                    normal: Vec3::new(0.0, 0.0, -1.0),
-                },
-                geometry::Vertex {
-                    position: Vec3::new(1.0, 1.0, 0.0),
-                    color: ground_color,
-                    uv: Vec2::new(1.0, 1.0),
-                    normal: Vec3::new(0.0, 0.0, -1.0),
-                },
-            ],
-            [
-                geometry::Vertex {
-                    position: Vec3::new(-1.0, -1.0, 0.0),
-                    color: ground_color,
-                    uv: Vec2::new(0.0, 0.0),
-                    normal: Vec3::new(0.0, 0.0, -1.0),
-                },
-                geometry::Vertex {
-                    position: Vec3::new(1.0, 1.0, 0.0),
-                    color: ground_color,
-                    uv: Vec2::new(1.0, 1.0),
-                    normal: Vec3::new(0.0, 0.0, -1.0),
-                },
-                geometry::Vertex {
-                    position: Vec3::new(1.0, -1.0, 0.0),
-                    color: ground_color,
-                    uv: Vec2::new(0.0, 1.0),
-                    normal: Vec3::new(0.0, 0.0, -1.0),
-                },
-            ],
-        ],
-    };
+    let mut scene = scene::Scene::load(&options.scene).unwrap_or_else(|e| {
+        eprintln!("Could not load scene: {}", e);
+        std::process::exit(1);
+    });
+    for warning in &scene.warnings {
+        eprintln!("Warning: {}", warning);
+    }
+    let mut watcher = scene::SceneWatcher::new(&scene);
+    // The camera is kept separate so that it stays put when the scene is reloaded.
+    let mut camera = scene.camera.clone();
+    let mut controller = scene.controller.clone();
 
-    let mut objects: Vec<object::Object> = vec![];
+    let time_step: f32 = 1.0 / 30.0;
+    let mut view = ViewSettings {
+        shader: 0,
+        wireframe: false,
+        auto_orbit: scene.auto_orbit,
+        stats: false,
+    };
 
-    let rabbit_mesh = loaders::obj::load(&std::path::Path::new("models/rabbit.obj"), None).unwrap();
-    let tree_mesh = loaders::obj::load(&std::path::Path::new("models/tree.obj"), None).unwrap();
+    let mut displays: Vec<Box<dyn DisplayDevice>> = vec![];
+    let rgb = options.display != "mono";
+    let primary = primary_display(&options);
+    displays.push(match &options.record {
+        Some(path) => Box::new(display_device::AsciicastRecorder::new(
+            primary,
+            create_file(path).unwrap_or_else(|e| exit_with_file_error(path, e)),
+            rgb,
+        )),
+        None => primary,
+    });
+    if let Some(address) = &options.serve {
+        let server =
+            display_device::TelnetServer::bind(address.as_str(), rgb).unwrap_or_else(|e| {
+                eprintln!("Could not listen on {}: {}", address, e);
+                std::process::exit(1);
+            });
+        displays.push(Box::new(server));
+    }
 
-    let cube_mesh = loaders::obj::load(
-        &std::path::Path::new("models/cube.obj"),
-        Some(&HashMap::new()),
-    )
-    .unwrap();
+    let frame_rate = (1.0 / time_step).round() as u32;
+    // Every video is kept with its path, to say which one failed if writing it does.
+    let mut sinks: Vec<(&std::path::Path, Box<dyn video::FrameSink>)> = vec![];
+    if let Some(path) = &options.y4m {
+        let sink = create_file(path)
+            .and_then(|file| video::Y4mWriter::new(file, frame_rate))
+            .unwrap_or_else(|e| exit_with_file_error(path, e));
+        sinks.push((path, Box::new(sink)));
+    }
+    if let Some(path) = &options.gif {
+        let sink = create_file(path)
+            .and_then(|file| video::GifWriter::new(file, frame_rate))
+            .unwrap_or_else(|e| exit_with_file_error(path, e));
+        sinks.push((path, Box::new(sink)));
+    }
 
-    //objects.push(object::Object::new(&cube_mesh));
-    let mut rabbit = object::Object::new(&rabbit_mesh);
-    let mut tree = object::Object::new(&tree_mesh);
-
-    rabbit.translation.x = 1.0;
-    rabbit.translation.y = -1.0;
-    rabbit.rotation.y = 0.2;
-
-    tree.translation.x = -1.0;
-    tree.translation.y = -1.0;
-    tree.scale = tree.scale.mul(0.5);
-
-    let mut ground = object::Object::new(&quad_mesh);
-    ground.rotation.x = std::f32::consts::FRAC_PI_2;
-    ground.translation.y = -1.0;
-    ground.translation.z = 0.5;
-    ground.scale.x = 1.5;
-    ground.scale.y = 1.5;
-    objects.push(ground);
-    objects.push(rabbit);
-    objects.push(tree);
-
-    tree.translation.x = 0.5;
-    tree.translation.z = 1.5;
-    tree.rotation.y = 0.823;
-    objects.push(tree);
-
-    let time_step = 1.0 / 30.0;
-    let mut t: f32 = 0.0;
-    let mut camera = camera::Camera {
-        translation: Vec3::new(0.0, 1.25, 0.0),
-        rotation: Vec3::new(std::f32::consts::FRAC_PI_6, 0.0, 0.0),
+    // Without a terminal on stdin (e.g. when piping input), there's just no interactivity.
+    let accepts_input = displays.iter().any(|display| display.capabilities().input);
+    let raw_mode = if accepts_input {
+        terminal::RawMode::enable(true).ok()
+    } else {
+        None
     };
+    let input = raw_mode.as_ref().map(|_| input::spawn_reader());
 
-    dd.setup();
-    let mut dimensions: (usize, usize) = (10, 10);
-    let mut rt = render_target::RenderTarget::new(dimensions);
-    let mut depth = render_target::RenderTarget::new(dimensions);
-
-    while !STOP.load(std::sync::atomic::Ordering::Relaxed) {
-        let new_dimensions = dd.dimensions().unwrap_or((20, 10));
-        if new_dimensions != dimensions {
-            dimensions = new_dimensions;
-            rt = render_target::RenderTarget::new(dimensions);
-            depth = render_target::RenderTarget::new(dimensions);
+    // The first thing to go wrong stops the loop, and gets reported once the terminal is
+    // back to normal.
+    let mut error: Option<failure::Error> = None;
+    for display in &mut displays {
+        if let Err(e) = display.begin() {
+            error = Some(failure::format_err!("Could not set up display: {}", e));
+            break;
         }
+    }
 
-        camera.translation.x = t.cos() * 3.0;
-        camera.translation.z = t.sin() * 3.0 + 0.5;
-        camera.rotation.y = t + std::f32::consts::FRAC_PI_2;
+    // Color and depth buffers for every viewport of every display, reused between frames.
+    let mut frames: HashMap<(usize, usize), (RenderTarget, RenderTarget)> = HashMap::new();
 
-        dd.prepare();
-        rt.clear(&Vec4::new(0.3, 0.3, 0.3, 1.0));
-        depth.clear(&Vec4::new(1.0, 1.0, 1.0, 1.0));
+    while !STOP.load(std::sync::atomic::Ordering::Relaxed) && error.is_none() {
+        for event in input.iter().flat_map(|input| input.try_iter()) {
+            if !handle_input(event, &mut controller, &mut view, scene.shaders.len()) {
+                STOP.store(true, std::sync::atomic::Ordering::Relaxed);
+            }
+        }
 
-        render(&mut rt, &mut depth, &objects, &camera);
+        if let Some(reloaded) = watcher.poll() {
+            scene = reloaded;
+            view.shader %= scene.shaders.len();
+        }
 
-        // Currently broken because line doesn't work without a depth map
-        // because I can't figure out how to make it work with the borrow checker.
-        rasterizer::line_2d(
-            &mut rt,
-            &shaders::SolidShader(Vec4::new(1.0, 0.0, 0.0, 1.0)),
-            &Vec2::new(-1.0, -1.0),
-            &Vec2::new(-1.0, 1.0),
-        );
+        if view.auto_orbit {
+            controller.rotate(time_step, 0.0);
+        }
+        controller.apply(&mut camera);
 
-        rasterizer::line_2d(
-            &mut rt,
-            &shaders::SolidShader(Vec4::new(1.0, 0.0, 0.0, 1.0)),
-            &Vec2::new(1.0, -1.0),
-            &Vec2::new(1.0, 1.0),
-        );
+        let mut previous_frames = std::mem::take(&mut frames);
+        for (index, display) in displays.iter_mut().enumerate() {
+            for viewport in display.viewports() {
+                let key = (index, viewport.id);
+                let (mut rt, mut depth) = previous_frames
+                    .remove(&key)
+                    .filter(|(rt, _)| rt.dimensions() == viewport.dimensions)
+                    .unwrap_or_else(|| {
+                        (
+                            RenderTarget::new(viewport.dimensions),
+                            RenderTarget::new(viewport.dimensions),
+                        )
+                    });
 
-        rasterizer::line_2d(
-            &mut rt,
-            &shaders::SolidShader(Vec4::new(1.0, 0.0, 0.0, 1.0)),
-            &Vec2::new(-1.0, 1.0),
-            &Vec2::new(1.0, 1.0),
-        );
+                rt.clear(&scene.background);
+                depth.clear(&Vec4::new(1.0, 1.0, 1.0, 1.0));
 
-        rasterizer::line_2d(
-            &mut rt,
-            &shaders::SolidShader(Vec4::new(1.0, 0.0, 0.0, 1.0)),
-            &Vec2::new(-1.0, -1.0),
-            &Vec2::new(1.0, -1.0),
-        );
+                let aspect = rt.aspect_ratio() * viewport.pixel_aspect;
+                let stats = render(
+                    &mut rt,
+                    &mut depth,
+                    aspect,
+                    &scene,
+                    &camera,
+                    scene.shaders[view.shader].as_ref(),
+                    view.wireframe,
+                );
+                draw_border(&mut rt);
+                if view.stats {
+                    let lines = [
+                        format!("drawn {}", stats.objects_drawn),
+                        format!("culled {}", stats.objects_culled),
+                        format!("triangles {}", stats.triangles),
+                        format!("warnings {}", scene.warnings.len()),
+                    ];
+                    draw_panel(&mut rt, &lines, true, &Vec4::new(1.0, 1.0, 1.0, 1.0));
+                }
+                if let Some(message) = &watcher.error {
+                    draw_message(&mut rt, message, &Vec4::new(1.0, 0.4, 0.4, 1.0));
+                } else if view.stats && !scene.warnings.is_empty() {
+                    let warnings = scene.warnings.join("\n");
+                    draw_message(&mut rt, &warnings, &Vec4::new(1.0, 0.9, 0.3, 1.0));
+                }
+
+                if let Err(e) = display.present(viewport.id, &rt) {
+                    error.get_or_insert(failure::format_err!("Display error: {}", e));
+                }
+
+                // Videos show whatever the main display shows.
+                if index == 0 {
+                    for (path, sink) in &mut sinks {
+                        if let Err(e) = sink.write_frame(&rt) {
+                            error.get_or_insert(write_error(path, e));
+                        }
+                    }
+                }
 
-        dd.show(&rt);
+                frames.insert(key, (rt, depth));
+            }
+        }
 
         std::thread::sleep(std::time::Duration::from_millis(
             (time_step * 1000.0) as u64,
         ));
+    }
+
+    for display in &mut displays {
+        if let Err(e) = display.end() {
+            error.get_or_insert(failure::format_err!("Display error: {}", e));
+        }
+    }
+    drop(raw_mode);
+
+    for (path, sink) in &mut sinks {
+        if let Err(e) = sink.finish() {
+            error.get_or_insert(write_error(path, e));
+        }
+    }
+
+    if let Some(e) = error {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    }
+}
+
+fn primary_display(options: &Options) -> Box<dyn DisplayDevice> {
+    match options.display.as_str() {
+        "sixel" => Box::new(display_device::SixelDisplay::stdout()),
+        "kitty" => Box::new(display_device::KittyDisplay::stdout()),
+        display => {
+            let mut console = display_device::ConsoleDisplay::stdout(display != "mono");
+            console.cell_aspect = options.cell_aspect;
+            Box::new(console)
+        }
+    }
+}
+
+fn write_error(path: &std::path::Path, e: std::io::Error) -> failure::Error {
+    failure::format_err!("Could not write {}: {}", path.display(), e)
+}
 
-        t += time_step;
+/// For files that can't be written before anything is shown, so there's nothing to clean up.
+fn exit_with_file_error(path: &std::path::Path, e: std::io::Error) -> ! {
+    eprintln!("{}", write_error(path, e));
+    std::process::exit(1);
+}
+
+fn create_file(path: &std::path::Path) -> std::io::Result<std::io::BufWriter<std::fs::File>> {
+    Ok(std::io::BufWriter::new(std::fs::File::create(path)?))
+}
+
+struct Options {
+    scene: std::path::PathBuf,
+    display: String,
+    cell_aspect: Option<f32>,
+    record: Option<std::path::PathBuf>,
+    y4m: Option<std::path::PathBuf>,
+    gif: Option<std::path::PathBuf>,
+    serve: Option<String>,
+}
+
+fn parse_options() -> Options {
+    let mut options = Options {
+        scene: "scenes/demo.toml".into(),
+        display: "console".to_string(),
+        cell_aspect: None,
+        record: None,
+        y4m: None,
+        gif: None,
+        serve: None,
+    };
+
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--scene" => {
+                options.scene = args
+                    .next()
+                    .expect("--scene needs the path of the scene file to show")
+                    .into();
+            }
+            "--display" => {
+                let display = args
+                    .next()
+                    .expect("--display needs one of console, mono, sixel or kitty");
+                if !["console", "mono", "sixel", "kitty"].contains(&display.as_str()) {
+                    eprintln!("Unknown display: {}", display);
+                    std::process::exit(2);
+                }
+                options.display = display;
+            }
+            "--cell-aspect" => {
+                options.cell_aspect = Some(
+                    args.next()
+                        .and_then(|value| value.parse().ok())
+                        .expect("--cell-aspect needs the width/height ratio of a character cell"),
+                );
+            }
+            "--record" => {
+                options.record = Some(
+                    args.next()
+                        .expect("--record needs the path of the .cast file to write")
+                        .into(),
+                );
+            }
+            "--y4m" => {
+                options.y4m = Some(
+                    args.next()
+                        .expect("--y4m needs the path of the video file to write")
+                        .into(),
+                );
+            }
+            "--gif" => {
+                options.gif = Some(
+                    args.next()
+                        .expect("--gif needs the path of the animation to write")
+                        .into(),
+                );
+            }
+            "--serve" => {
+                let address = args
+                    .next()
+                    .expect("--serve needs a port or address to listen on");
+                // A bare port number only listens on the loopback interface.
+                options.serve = Some(if address.parse::<u16>().is_ok() {
+                    format!("127.0.0.1:{}", address)
+                } else {
+                    address
+                });
+            }
+            _ => {
+                eprintln!("Unknown argument: {}", arg);
+                std::process::exit(2);
+            }
+        }
+    }
+
+    options
+}
+
+struct ViewSettings {
+    shader: usize,
+    wireframe: bool,
+    auto_orbit: bool,
+    /// Whether to show how many objects and triangles were drawn.
+    stats: bool,
+}
+
+/// Applies a single input event. Returns false if the user asked to quit.
+fn handle_input(
+    event: input::InputEvent,
+    controller: &mut camera::CameraController,
+    view: &mut ViewSettings,
+    shader_count: usize,
+) -> bool {
+    use input::{InputEvent, Key};
+
+    let step = 0.1;
+    let turn = 0.05;
+
+    match event {
+        InputEvent::Key(Key::Char('q')) | InputEvent::Key(Key::Escape) => return false,
+        InputEvent::Key(Key::Char('c')) => view.shader = (view.shader + 1) % shader_count,
+        InputEvent::Key(Key::Char('x')) => view.wireframe = !view.wireframe,
+        InputEvent::Key(Key::Char('v')) => controller.toggle_mode(),
+        InputEvent::Key(Key::Char(' ')) => view.auto_orbit = !view.auto_orbit,
+        InputEvent::Key(Key::Char('i')) => view.stats = !view.stats,
+        event => {
+            // Anything else moves the camera, so stop the automatic orbit from fighting the user.
+            view.auto_orbit = false;
+            match event {
+                InputEvent::Key(Key::Char('w')) => controller.travel(step, 0.0),
+                InputEvent::Key(Key::Char('s')) => controller.travel(-step, 0.0),
+                InputEvent::Key(Key::Char('a')) => controller.travel(0.0, -step),
+                InputEvent::Key(Key::Char('d')) => controller.travel(0.0, step),
+                InputEvent::Key(Key::Left) => controller.rotate(-turn, 0.0),
+                InputEvent::Key(Key::Right) => controller.rotate(turn, 0.0),
+                InputEvent::Key(Key::Up) => controller.rotate(0.0, -turn),
+                InputEvent::Key(Key::Down) => controller.rotate(0.0, turn),
+                InputEvent::Drag { dx, dy } => {
+                    controller.rotate(dx as f32 * turn, dy as f32 * turn)
+                }
+                InputEvent::Scroll(amount) => controller.travel(amount as f32 * step * 2.0, 0.0),
+                _ => {}
+            }
+        }
+    }
+
+    true
+}
+
+fn draw_border(rt: &mut RenderTarget) {
+    let shader = shaders::SolidShader(Vec4::new(1.0, 0.0, 0.0, 1.0));
+
+    // Currently broken because line doesn't work without a depth map
+    // because I can't figure out how to make it work with the borrow checker.
+    rasterizer::line_2d(rt, &shader, &Vec2::new(-1.0, -1.0), &Vec2::new(-1.0, 1.0));
+    rasterizer::line_2d(rt, &shader, &Vec2::new(1.0, -1.0), &Vec2::new(1.0, 1.0));
+    rasterizer::line_2d(rt, &shader, &Vec2::new(-1.0, 1.0), &Vec2::new(1.0, 1.0));
+    rasterizer::line_2d(rt, &shader, &Vec2::new(-1.0, -1.0), &Vec2::new(1.0, -1.0));
+}
+
+/// Shows a message, such as an error, over the bottom of the frame on a darkened background.
+fn draw_message(rt: &mut RenderTarget, message: &str, color: &Vec4) {
+    let scale = text_scale(rt);
+    let columns = (rt.width / scale).saturating_sub(2) / text::ADVANCE;
+    let mut lines = text::wrap(message, columns);
+    // The cause of an error comes last, so that's the part to keep when there isn't room
+    // for everything.
+    let room = (rt.height / scale).saturating_sub(1) / text::LINE_HEIGHT;
+    lines.drain(..lines.len().saturating_sub(room));
+    draw_panel(rt, &lines, false, color);
+}
+
+/// Keeps text readable on displays with lots of pixels.
+fn text_scale(rt: &RenderTarget) -> usize {
+    (rt.width / 320).max(1)
+}
+
+/// Shows lines of text in the top or bottom left corner of the frame, on a darkened background.
+fn draw_panel(rt: &mut RenderTarget, lines: &[String], top: bool, color: &Vec4) {
+    let scale = text_scale(rt);
+    let height = ((lines.len() * text::LINE_HEIGHT + 1) * scale).min(rt.height);
+    let longest = lines
+        .iter()
+        .map(|line| line.chars().count())
+        .max()
+        .unwrap_or(0);
+    let width = ((longest * text::ADVANCE + 1) * scale).min(rt.width);
+    let start = if top { 0 } else { rt.height - height };
+
+    for y in start..start + height {
+        for x in 0..width {
+            let pixel = rt.get_pixel(x, y).mul(0.25);
+            rt.set_pixel(x, y, &Vec4::new(pixel.x, pixel.y, pixel.z, 1.0));
+        }
+    }
+    for (i, line) in lines.iter().enumerate() {
+        let y = start + (1 + i * text::LINE_HEIGHT) * scale;
+        text::draw_text(rt, (scale, y), scale, line, color);
     }
-    dd.restore();
 }
 
 fn render(
     rt: &mut render_target::RenderTarget,
     depth: &mut render_target::RenderTarget,
-    objects: &Vec<object::Object>,
+    aspect: f32,
+    scene: &scene::Scene,
     camera: &camera::Camera,
-) {
-    let mut renderer = renderer::Renderer::new(
-        std::f32::consts::PI / 6.0,
-        rt.aspect_ratio(),
-        0.1,
-        10.0,
-        rt,
-        depth,
-        &camera,
-    );
-
-    let white = Vec4::new(1.0, 1.0, 1.0, 1.0);
-    let shader = shaders::SolidShader(white);
-    let diffuse_shader = shaders::DiffuseShader(Vec3::new(-0.707, -0.707, 0.0));
-    for object in objects {
-        renderer.draw(
+    shader: &dyn shaders::FragmentShader,
+    wireframe: bool,
+) -> renderer::RenderStats {
+    let mut renderer =
+        renderer::Renderer::new(scene.fov, aspect, scene.near, scene.far, rt, depth, camera);
+    renderer.wireframe = wireframe;
+
+    // Translucent objects don't write depth, so they're drawn after everything else, the
+    // furthest first, to blend with what's behind them.
+    let mut objects: Vec<(Option<f32>, object::Object)> = scene
+        .objects()
+        .into_iter()
+        .filter(|object| object.visible)
+        .map(|object| {
+            let translucent = object.material.is_some_and(|m| m.opacity < 1.0);
+            let distance = object
+                .mesh
+                .bounding_sphere
+                .transform(&object.transform())
+                .center
+                .sub(&camera.translation)
+                .length();
+            (if translucent { Some(-distance) } else { None }, object)
+        })
+        .collect();
+    objects.sort_by(|(a, _), (b, _)| a.partial_cmp(b).unwrap_or(std::cmp::Ordering::Equal));
+
+    for (_, object) in &objects {
+        // Meshes without triangles are point clouds.
+        let draw = if object.mesh.indices.is_empty() {
+            renderer::Renderer::draw_points
+        } else {
+            renderer::Renderer::draw
+        };
+        draw(
+            &mut renderer,
             object.mesh,
             &object.transform(),
             &object.normal_transform(),
-            &diffuse_shader,
+            object.material,
+            shader,
         );
     }
+    renderer.stats
 }
@@ -75,7 +75,7 @@ pub struct Vec4 {
 
 impl Vec2 {
     pub fn new(x: f32, y: f32) -> Vec2 {
-        Vec2 { x: x, y: y }
+        Vec2 { x, y }
     }
 
     pub fn mul(&self, f: f32) -> Vec2 {
@@ -101,7 +101,7 @@ impl Vec2 {
 
 impl Vec3 {
     pub fn new(x: f32, y: f32, z: f32) -> Vec3 {
-        Vec3 { x: x, y: y, z: z }
+        Vec3 { x, y, z }
     }
 
     pub fn to_vec4(&self) -> Vec4 {
@@ -197,16 +197,29 @@ impl Vec3 {
     pub fn dot(&self, other: &Vec3) -> f32 {
         self.x * other.x + self.y * other.y + self.z * other.z
     }
+
+    pub fn cross(&self, other: &Vec3) -> Vec3 {
+        Vec3::new(
+            self.y * other.z - self.z * other.y,
+            self.z * other.x - self.x * other.z,
+            self.x * other.y - self.y * other.x,
+        )
+    }
+
+    /// Returns the vector scaled to length 1, or unchanged if it has no length.
+    pub fn normalize(&self) -> Vec3 {
+        let length = self.length();
+        if length > 0.0 {
+            self.mul(1.0 / length)
+        } else {
+            *self
+        }
+    }
 }
 
 impl Vec4 {
     pub fn new(x: f32, y: f32, z: f32, w: f32) -> Vec4 {
-        Vec4 {
-            x: x,
-            y: y,
-            z: z,
-            w: w,
-        }
+        Vec4 { x, y, z, w }
     }
 
     pub fn from_vec3(xyz: Vec3, w: f32) -> Vec4 {
@@ -214,7 +227,7 @@ impl Vec4 {
             x: xyz.x,
             y: xyz.y,
             z: xyz.z,
-            w: w,
+            w,
         }
     }
 
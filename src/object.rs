@@ -4,22 +4,39 @@ use super::math::*;
 #[derive(Copy, Clone)]
 pub struct Object<'a> {
     pub mesh: &'a Mesh,
+    pub material: Option<&'a Material>,
 
     pub translation: Vec3,
     pub scale: Vec3,
     pub rotation: Vec3,
+
+    pub visible: bool,
 }
 
 impl<'a> Object<'a> {
     pub fn new(mesh: &'a Mesh) -> Self {
         Object {
             mesh,
+            material: None,
             translation: Vec3::new(0.0, 0.0, 0.0),
             scale: Vec3::new(1.0, 1.0, 1.0),
             rotation: Vec3::new(0.0, 0.0, 0.0),
+            visible: true,
         }
     }
 
+    /// Creates an object for every part of a model, all at the origin.
+    pub fn from_model(model: &'a Model) -> Vec<Self> {
+        model
+            .parts
+            .iter()
+            .map(|part| Object {
+                material: model.material(part.mesh.material),
+                ..Object::new(&part.mesh)
+            })
+            .collect()
+    }
+
     pub fn transform(&self) -> Mat4x4 {
         // We scale first, then rotate, and finally translate
         self.translation
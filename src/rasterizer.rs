@@ -31,7 +31,7 @@ pub fn to_normalized((width, height): (usize, usize), (x, y): (i32, i32)) -> Vec
 pub fn triangle(
     rt: &mut RenderTarget,
     depth: &mut RenderTarget,
-    shader: &dyn FragmentShader,
+    shading: &Shading,
     a: &Vertex,
     b: &Vertex,
     c: &Vertex,
@@ -42,14 +42,23 @@ pub fn triangle(
     }
 
     if wireframe {
-        line(rt, Some(depth), shader, a, b);
-        line(rt, Some(depth), shader, b, c);
-        line(rt, Some(depth), shader, c, a);
+        line(rt, Some(depth), shading, a, b);
+        line(rt, Some(depth), shading, b, c);
+        line(rt, Some(depth), shading, c, a);
     } else {
-        triangle_parallel(rt, depth, shader, a, b, c);
+        triangle_parallel(rt, depth, shading, a, b, c);
     }
 }
 
+pub fn point(rt: &mut RenderTarget, depth: &mut RenderTarget, shading: &Shading, a: &Vertex) {
+    if a.position.z < 0.0 {
+        return;
+    }
+
+    let (x, y) = from_normalized(rt.dimensions(), &a.position.xy());
+    pixel(rt, Some(depth), shading, x, y, a);
+}
+
 // A method that works great in parallell (read: on hardware)
 // but perhaps isn't great for CPUs. We check every pixel
 // (as an optimization, only those in a rectangle that just covers the triangle),
@@ -58,7 +67,7 @@ pub fn triangle(
 fn triangle_parallel(
     rt: &mut RenderTarget,
     depth: &mut RenderTarget,
-    shader: &dyn FragmentShader,
+    shading: &Shading,
     a: &Vertex,
     b: &Vertex,
     c: &Vertex,
@@ -83,7 +92,7 @@ fn triangle_parallel(
                 pixel(
                     rt,
                     Some(depth),
-                    shader,
+                    shading,
                     x,
                     y,
                     &barycentric_interpolation(
@@ -96,9 +105,9 @@ fn triangle_parallel(
     }
 
     //let black = super::shaders::SolidShader(Vec4::new(0.0, 0.0, 0.0, 1.0));
-    //line(rt, Some(depth), shader, a, b);
-    //line(rt, Some(depth), shader, b, c);
-    //line(rt, Some(depth), shader, c, a);
+    //line(rt, Some(depth), shading, a, b);
+    //line(rt, Some(depth), shading, b, c);
+    //line(rt, Some(depth), shading, c, a);
 }
 
 fn is_inside_triangle(a: &Vec2, b: &Vec2, c: &Vec2, p: &Vec2) -> bool {
@@ -171,11 +180,11 @@ pub fn nearest_interpolation([a, b, c]: Triangle, position: Vec2) -> Vertex {
     let dc = pc.sub(&position).length2();
 
     if da <= db && da <= dc {
-        return a;
+        a
     } else if db <= da && db <= dc {
-        return b;
+        b
     } else {
-        return c;
+        c
     }
 }
 
@@ -197,10 +206,17 @@ pub fn linear_interpolation((a, b): (Vertex, Vertex), position: Vec2) -> Vertex
 }
 
 pub fn line_2d(rt: &mut RenderTarget, shader: &dyn FragmentShader, a: &Vec2, b: &Vec2) {
+    let material = Material::default();
+    let shading = Shading {
+        shader,
+        material: &material,
+        view_directions: ViewDirections::constant(Vec3::new(0.0, 0.0, 1.0)),
+    };
+
     line(
         rt,
         None,
-        shader,
+        &shading,
         &Vertex {
             position: Vec3::new(a.x, a.y, 0.0),
             color: Vec4::new(1.0, 1.0, 1.0, 1.0),
@@ -219,7 +235,7 @@ pub fn line_2d(rt: &mut RenderTarget, shader: &dyn FragmentShader, a: &Vec2, b:
 pub fn line(
     rt: &mut RenderTarget,
     depth: Option<&mut RenderTarget>,
-    shader: &dyn FragmentShader,
+    shading: &Shading,
     a: &Vertex,
     b: &Vertex,
 ) {
@@ -233,7 +249,7 @@ pub fn line(
     let (x1, y1) = from_normalized(rt.dimensions(), &a.position.xy());
     let (x2, y2) = from_normalized(rt.dimensions(), &b.position.xy());
 
-    pixel(rt, Some(depth), shader, x1, y1, a);
+    pixel(rt, Some(depth), shading, x1, y1, a);
 
     if x1 == x2 && y1 == y2 {
         return;
@@ -252,7 +268,7 @@ pub fn line(
             pixel(
                 rt,
                 Some(depth),
-                shader,
+                shading,
                 x + x1,
                 y + y1,
                 &linear_interpolation((*a, *b), to_normalized(rt.dimensions(), (x + x1, y + y1))),
@@ -267,7 +283,7 @@ pub fn line(
             pixel(
                 rt,
                 Some(depth),
-                shader,
+                shading,
                 x + x1,
                 y + y1,
                 &linear_interpolation((*a, *b), to_normalized(rt.dimensions(), (x + x1, y + y1))),
@@ -279,7 +295,7 @@ pub fn line(
 fn pixel(
     rt: &mut RenderTarget,
     depth: Option<&mut RenderTarget>,
-    shader: &dyn FragmentShader,
+    shading: &Shading,
     x: i32,
     y: i32,
     interpolated_vertex: &Vertex,
@@ -288,29 +304,35 @@ fn pixel(
         let input = FragmentInput {
             vertex: *interpolated_vertex,
             screen_uv: to_normalized(rt.dimensions(), (x, y)),
+            material: shading.material,
+            view_direction: shading.view_directions.at(&interpolated_vertex.position),
         };
 
-        let draw = depth
-            .map(|t| {
-                let new_depth = interpolated_vertex.position.z;
-                let old_depth = t.get_pixel(x as usize, y as usize).x;
+        let new_depth = interpolated_vertex.position.z;
+        let draw = match &depth {
+            Some(t) => new_depth < t.get_pixel(x as usize, y as usize).x,
+            None => true,
+        };
 
-                if new_depth < old_depth {
+        if draw {
+            let color = shading.shader.fragment_color(&input);
+            if color.w < 1.0 {
+                // Blend translucent fragments with whatever was drawn before. They don't
+                // write depth, so that whatever is behind them can still show through.
+                let behind = rt.get_pixel(x as usize, y as usize);
+                let alpha = clamp((0.0, 1.0), color.w);
+                let blended = color.mul(alpha).add(&behind.mul(1.0 - alpha));
+                rt.set_pixel(x as usize, y as usize, &Vec4::from_vec3(blended.xyz(), 1.0));
+            } else {
+                rt.set_pixel(x as usize, y as usize, &color);
+                if let Some(t) = depth {
                     t.set_pixel(
                         x as usize,
                         y as usize,
                         &Vec4::new(new_depth, new_depth, new_depth, 1.0),
                     );
-                    true
-                } else {
-                    false
                 }
-            })
-            .unwrap_or(true);
-
-        if draw {
-            let color = shader.fragment_color(&input);
-            rt.set_pixel(x as usize, y as usize, &color);
+            }
         }
 
         draw
@@ -318,3 +340,61 @@ fn pixel(
         false
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn draw_point(rt: &mut RenderTarget, depth: &mut RenderTarget, z: f32, color: Vec4) {
+        let material = Material::default();
+        let shader = SolidShader(color);
+        let shading = Shading {
+            shader: &shader,
+            material: &material,
+            view_directions: ViewDirections::constant(Vec3::new(0.0, 0.0, 1.0)),
+        };
+        let vertex = Vertex {
+            position: Vec3::new(0.0, 0.0, z),
+            uv: Vec2::new(0.0, 0.0),
+            normal: Vec3::new(0.0, 0.0, -1.0),
+            color: Vec4::new(1.0, 1.0, 1.0, 1.0),
+        };
+        point(rt, depth, &shading, &vertex);
+    }
+
+    fn color(rt: &RenderTarget) -> (f32, f32, f32) {
+        let pixel = rt.get_pixel(0, 0);
+        (pixel.x, pixel.y, pixel.z)
+    }
+
+    fn targets() -> (RenderTarget, RenderTarget) {
+        let mut rt = RenderTarget::new((1, 1));
+        rt.clear(&Vec4::new(0.0, 0.0, 0.0, 1.0));
+        let mut depth = RenderTarget::new((1, 1));
+        depth.clear(&Vec4::new(1.0, 1.0, 1.0, 1.0));
+        (rt, depth)
+    }
+
+    #[test]
+    fn opaque_fragments_are_depth_tested() {
+        let (mut rt, mut depth) = targets();
+        draw_point(&mut rt, &mut depth, 0.5, Vec4::new(1.0, 0.0, 0.0, 1.0));
+        draw_point(&mut rt, &mut depth, 0.7, Vec4::new(0.0, 1.0, 0.0, 1.0));
+
+        assert_eq!(color(&rt), (1.0, 0.0, 0.0));
+        assert_eq!(depth.get_pixel(0, 0).x, 0.5);
+    }
+
+    #[test]
+    fn translucent_fragments_blend_without_writing_depth() {
+        let (mut rt, mut depth) = targets();
+        draw_point(&mut rt, &mut depth, 0.7, Vec4::new(0.0, 0.0, 1.0, 1.0));
+        draw_point(&mut rt, &mut depth, 0.2, Vec4::new(1.0, 0.0, 0.0, 0.5));
+        assert_eq!(color(&rt), (0.5, 0.0, 0.5));
+        assert_eq!(depth.get_pixel(0, 0).x, 0.7);
+
+        // Something drawn later between the two still shows through.
+        draw_point(&mut rt, &mut depth, 0.5, Vec4::new(0.0, 1.0, 0.0, 0.5));
+        assert_eq!(color(&rt), (0.25, 0.5, 0.25));
+    }
+}
@@ -9,8 +9,8 @@ pub struct RenderTarget {
 impl RenderTarget {
     pub fn new((width, height): (usize, usize)) -> RenderTarget {
         RenderTarget {
-            width: width,
-            height: height,
+            width,
+            height,
             pixels: vec![
                 Vec4 {
                     x: 0.0,
@@ -5,11 +5,31 @@ use super::rasterizer;
 use super::render_target::RenderTarget;
 use super::shaders::*;
 
+/// What the renderer got through, counted from when it was created.
+#[derive(Debug, Default, Copy, Clone, PartialEq)]
+pub struct RenderStats {
+    pub objects_drawn: usize,
+    /// Objects skipped without looking at their triangles, since they're out of view.
+    pub objects_culled: usize,
+    pub triangles: usize,
+}
+
 pub struct Renderer<'a> {
     pub target: &'a mut RenderTarget,
     pub depth: &'a mut RenderTarget,
+    pub wireframe: bool,
+    pub stats: RenderStats,
     projection_matrix: Mat4x4,
+    /// The sides of what the camera sees, in view space, as normals pointing inwards along
+    /// with their distances from the camera.
+    frustum: [(Vec3, f32); 6],
+    view_directions: ViewDirections,
     camera: &'a Camera,
+    default_material: Material,
+    /// The vertices of the mesh being drawn, transformed to screen space. Every vertex is
+    /// transformed once per draw, however many triangles share it, and the buffer is
+    /// reused between draws.
+    transformed: Vec<Vertex>,
 }
 
 fn projection_matrix(fov: f32, aspect: f32, near: f32, far: f32) -> Mat4x4 {
@@ -25,6 +45,38 @@ fn projection_matrix(fov: f32, aspect: f32, near: f32, far: f32) -> Mat4x4 {
     ])
 }
 
+fn frustum(fov: f32, aspect: f32, near: f32, far: f32) -> [(Vec3, f32); 6] {
+    // Points are in view when the projection puts them within [-1, 1] on X and Y, so the
+    // sides go through the camera at slopes matching the projection matrix.
+    let u = 1.0 / fov.tan();
+    let a = 1.0 / aspect;
+    let side = |x: f32, y: f32| (Vec3::new(x, y, 1.0).normalize(), 0.0);
+
+    [
+        (Vec3::new(0.0, 0.0, 1.0), -near),
+        (Vec3::new(0.0, 0.0, -1.0), far),
+        side(u * a, 0.0),
+        side(-u * a, 0.0),
+        side(0.0, u),
+        side(0.0, -u),
+    ]
+}
+
+fn view_directions(fov: f32, aspect: f32, near: f32, far: f32, camera: &Camera) -> ViewDirections {
+    // The projection divides X and Y by the distance along the view axis and scales them to
+    // the field of view, and maps that distance linearly from [near, far] to [0, 1] for depth.
+    // A rotation turns view space back into world space, and the inverse of a rotation is its
+    // transpose, which leaves out the translation for directions.
+    let u = 1.0 / fov.tan();
+    let a = 1.0 / aspect;
+    let unscale = Vec3::new(1.0 / (u * a), 1.0 / u, 1.0).scaling();
+
+    ViewDirections {
+        depth: (far - near, near),
+        unproject: camera.view_matrix().transpose().mat_mul(&unscale),
+    }
+}
+
 fn to_screen_space(matrix: &Mat4x4, point: &Vec3) -> Vec3 {
     let mut point = Vec4::new(point.x, point.y, point.z, 1.0);
     point = matrix.mul(&point);
@@ -52,44 +104,313 @@ impl Renderer<'_> {
         camera: &'a Camera,
     ) -> Renderer<'a> {
         Renderer {
-            target: target,
-            depth: depth,
-            camera: camera,
+            target,
+            depth,
+            camera,
+            wireframe: false,
+            stats: RenderStats::default(),
             projection_matrix: projection_matrix(fov, aspect, near, far),
+            frustum: frustum(fov, aspect, near, far),
+            view_directions: view_directions(fov, aspect, near, far, camera),
+            default_material: Material::default(),
+            transformed: vec![],
+        }
+    }
+
+    /// Whether any of a mesh can be in view once it's been transformed.
+    pub fn in_view(&self, mesh: &Mesh, transform: &Mat4x4) -> bool {
+        let sphere = mesh
+            .bounding_sphere
+            .transform(&self.camera.view_matrix().mat_mul(transform));
+        self.frustum
+            .iter()
+            .all(|(normal, distance)| normal.dot(&sphere.center) + distance >= -sphere.radius)
+    }
+
+    /// Draws a mesh, unless it's out of view.
+    pub fn draw(
+        &mut self,
+        mesh: &Mesh,
+        transform: &Mat4x4,
+        normal_transform: &Mat4x4,
+        material: Option<&Material>,
+        fragment: &dyn FragmentShader,
+    ) {
+        if !self.in_view(mesh, transform) {
+            self.stats.objects_culled += 1;
+            return;
+        }
+        self.stats.objects_drawn += 1;
+        self.stats.triangles += mesh.indices.len();
+
+        let shading = Shading {
+            shader: fragment,
+            material: material.unwrap_or(&self.default_material),
+            view_directions: self.view_directions,
+        };
+
+        let matrix = self
+            .projection_matrix
+            .mat_mul(&self.camera.view_matrix().mat_mul(transform));
+
+        self.transformed.clear();
+        self.transformed.extend(mesh.vertices.iter().map(|vertex| {
+            let mut vertex = *vertex;
+            vertex.position = to_screen_space(&matrix, &vertex.position);
+            // Convert normals to world-space since the diffuse shader expects them to be.
+            vertex.normal = normal_transform.mul(&vertex.normal.to_vec4()).xyz();
+            vertex
+        }));
+
+        for &[a, b, c] in &mesh.indices {
+            rasterizer::triangle(
+                self.target,
+                self.depth,
+                &shading,
+                &self.transformed[a as usize],
+                &self.transformed[b as usize],
+                &self.transformed[c as usize],
+                self.wireframe,
+            );
         }
     }
 
-    pub fn draw<'b>(
-        &'b mut self,
+    /// Draws every vertex of a mesh as a single pixel, ignoring its triangles. Point clouds
+    /// are meshes without any triangles, drawn this way.
+    pub fn draw_points(
+        &mut self,
         mesh: &Mesh,
         transform: &Mat4x4,
         normal_transform: &Mat4x4,
+        material: Option<&Material>,
         fragment: &dyn FragmentShader,
     ) {
-        for [a, b, c] in &mesh.triangles {
-            let matrix = self
-                .projection_matrix
-                .mat_mul(&self.camera.view_matrix().mat_mul(transform));
+        if !self.in_view(mesh, transform) {
+            self.stats.objects_culled += 1;
+            return;
+        }
+        self.stats.objects_drawn += 1;
 
-            let pa = to_screen_space(&matrix, &a.position);
-            let pb = to_screen_space(&matrix, &b.position);
-            let pc = to_screen_space(&matrix, &c.position);
+        let shading = Shading {
+            shader: fragment,
+            material: material.unwrap_or(&self.default_material),
+            view_directions: self.view_directions,
+        };
+        let matrix = self
+            .projection_matrix
+            .mat_mul(&self.camera.view_matrix().mat_mul(transform));
 
-            let mut a = *a;
-            a.position = pa;
+        for point in &mesh.vertices {
+            let mut point = *point;
+            point.position = to_screen_space(&matrix, &point.position);
+            point.normal = normal_transform.mul(&point.normal.to_vec4()).xyz();
 
-            let mut b = *b;
-            b.position = pb;
+            rasterizer::point(self.target, self.depth, &shading, &point);
+        }
+    }
+}
 
-            let mut c = *c;
-            c.position = pc;
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-            // Convert normals to world-space since the diffuse shader expects them to be.
-            a.normal = normal_transform.mul(&a.normal.to_vec4()).xyz();
-            b.normal = normal_transform.mul(&b.normal.to_vec4()).xyz();
-            c.normal = normal_transform.mul(&c.normal.to_vec4()).xyz();
+    fn vertex(x: f32, y: f32, z: f32) -> Vertex {
+        Vertex {
+            position: Vec3::new(x, y, z),
+            uv: Vec2::new(0.0, 0.0),
+            normal: Vec3::new(0.0, 0.0, -1.0),
+            color: Vec4::new(1.0, 1.0, 1.0, 1.0),
+        }
+    }
+
+    #[test]
+    fn draw_points_draws_every_vertex_unless_out_of_view() {
+        let mut rt = RenderTarget::new((5, 5));
+        rt.clear(&Vec4::new(0.0, 0.0, 0.0, 1.0));
+        let mut depth = RenderTarget::new((5, 5));
+        depth.clear(&Vec4::new(1.0, 1.0, 1.0, 1.0));
+        let camera = Camera {
+            translation: Vec3::new(0.0, 0.0, 0.0),
+            rotation: Vec3::new(0.0, 0.0, 0.0),
+        };
+        let mut renderer = Renderer::new(0.5, 1.0, 0.1, 100.0, &mut rt, &mut depth, &camera);
+        let shader = SolidShader(Vec4::new(1.0, 0.0, 0.0, 1.0));
+        let identity = Vec3::new(0.0, 0.0, 0.0).translation();
+
+        let cloud = Mesh::new(vec![vertex(0.0, 0.0, 5.0)], vec![], None);
+        renderer.draw_points(&cloud, &identity, &identity, None, &shader);
+        let behind = Mesh::new(vec![vertex(0.0, 0.0, -5.0)], vec![], None);
+        renderer.draw_points(&behind, &identity, &identity, None, &shader);
+
+        assert_eq!(
+            renderer.stats,
+            RenderStats {
+                objects_drawn: 1,
+                objects_culled: 1,
+                triangles: 0,
+            }
+        );
+        let lit: Vec<(usize, usize)> = (0..25)
+            .map(|i| (i % 5, i / 5))
+            .filter(|&(x, y)| rt.get_pixel(x, y).x > 0.0)
+            .collect();
+        assert_eq!(lit, vec![(2, 2)]);
+    }
+
+    /// A triangle at the origin, half a unit across.
+    fn speck() -> Mesh {
+        let vertices = vec![
+            vertex(-0.25, 0.0, 0.0),
+            vertex(0.25, 0.0, 0.0),
+            vertex(0.0, 0.25, 0.0),
+        ];
+        Mesh::new(vertices, vec![[0, 1, 2]], None)
+    }
+
+    fn at(x: f32, y: f32, z: f32) -> Mat4x4 {
+        Vec3::new(x, y, z).translation()
+    }
+
+    /// Whether a mesh is in view of a camera with a field of view of 2 * 0.5 radians, where
+    /// the sides of the view are about 5.5 units from the middle at a distance of 10.
+    fn visible(camera: &Camera, mesh: &Mesh, transform: &Mat4x4) -> bool {
+        let mut rt = RenderTarget::new((4, 4));
+        let mut depth = RenderTarget::new((4, 4));
+        let renderer = Renderer::new(0.5, 1.0, 0.1, 100.0, &mut rt, &mut depth, camera);
+        renderer.in_view(mesh, transform)
+    }
+
+    fn camera(rotation: Vec3) -> Camera {
+        Camera {
+            translation: Vec3::new(0.0, 0.0, 0.0),
+            rotation,
+        }
+    }
+
+    #[test]
+    fn culls_objects_beyond_each_side_of_the_view() {
+        let camera = camera(Vec3::new(0.0, 0.0, 0.0));
+        let mesh = speck();
+
+        for &(x, y, z) in &[
+            (0.0, 0.0, 10.0),
+            (5.0, 0.0, 10.0),
+            (0.0, -5.0, 10.0),
+            (0.0, 0.0, 99.0),
+        ] {
+            assert!(visible(&camera, &mesh, &at(x, y, z)), "{:?}", (x, y, z));
+        }
+
+        let outside = [
+            // Left, right, top and bottom.
+            (-8.0, 0.0, 10.0),
+            (8.0, 0.0, 10.0),
+            (0.0, 8.0, 10.0),
+            (0.0, -8.0, 10.0),
+            // Beyond the far plane and behind the camera.
+            (0.0, 0.0, 101.0),
+            (0.0, 0.0, -1.0),
+        ];
+        for &(x, y, z) in &outside {
+            assert!(!visible(&camera, &mesh, &at(x, y, z)), "{:?}", (x, y, z));
+        }
+
+        // Something small enough to fit between the camera and the near plane.
+        let tiny = Vec3::new(0.01, 0.01, 0.01).scaling();
+        assert!(!visible(&camera, &mesh, &at(0.0, 0.0, 0.05).mat_mul(&tiny)));
+        assert!(visible(&camera, &mesh, &at(0.0, 0.0, 0.15).mat_mul(&tiny)));
+    }
+
+    #[test]
+    fn draws_meshes_that_cross_the_edge_of_the_view() {
+        let mut rt = RenderTarget::new((4, 4));
+        let mut depth = RenderTarget::new((4, 4));
+        depth.clear(&Vec4::new(1.0, 1.0, 1.0, 1.0));
+        let camera = camera(Vec3::new(0.0, 0.0, 0.0));
+        let mut renderer = Renderer::new(0.5, 1.0, 0.1, 100.0, &mut rt, &mut depth, &camera);
+        let shader = SolidShader(Vec4::new(1.0, 0.0, 0.0, 1.0));
+
+        // Straddling the right side, the far plane and the camera itself.
+        let straddling = [
+            vec![
+                vertex(4.0, 0.0, 10.0),
+                vertex(30.0, 0.0, 10.0),
+                vertex(4.0, 1.0, 10.0),
+            ],
+            vec![
+                vertex(0.0, 0.0, 90.0),
+                vertex(1.0, 0.0, 120.0),
+                vertex(0.0, 1.0, 90.0),
+            ],
+            vec![
+                vertex(0.0, 0.0, -5.0),
+                vertex(1.0, 0.0, 5.0),
+                vertex(0.0, 1.0, 5.0),
+            ],
+        ];
+        let identity = at(0.0, 0.0, 0.0);
+        for vertices in straddling.iter() {
+            let mesh = Mesh::new(vertices.clone(), vec![[0, 1, 2]], None);
+            renderer.draw(&mesh, &identity, &identity, None, &shader);
+        }
+        renderer.draw(&speck(), &at(20.0, 0.0, 10.0), &identity, None, &shader);
+
+        assert_eq!(
+            renderer.stats,
+            RenderStats {
+                objects_drawn: 3,
+                objects_culled: 1,
+                triangles: 3,
+            }
+        );
+    }
+
+    #[test]
+    fn culls_the_bounding_sphere_where_the_transform_puts_it() {
+        let mesh = speck();
+        let ahead = camera(Vec3::new(0.0, 0.0, 0.0));
+
+        // Stretching the mesh sideways brings it back into view from beyond the right side.
+        assert!(!visible(&ahead, &mesh, &at(8.0, 0.0, 10.0)));
+        let stretched = at(8.0, 0.0, 10.0).mat_mul(&Vec3::new(20.0, 1.0, 1.0).scaling());
+        assert!(visible(&ahead, &mesh, &stretched));
+
+        // So does turning the camera around to face it.
+        let behind = at(0.0, 0.0, -10.0);
+        assert!(!visible(&ahead, &mesh, &behind));
+        let around = camera(Vec3::new(0.0, std::f32::consts::PI, 0.0));
+        assert!(visible(&around, &mesh, &behind));
+        assert!(!visible(&around, &mesh, &at(0.0, 0.0, 10.0)));
+    }
+
+    #[test]
+    fn view_directions_point_from_the_camera_to_each_fragment() {
+        let camera = Camera {
+            translation: Vec3::new(1.0, -2.0, 3.0),
+            rotation: Vec3::new(0.3, -1.1, 0.2),
+        };
+        let (fov, aspect, near, far) = (0.6, 1.5, 0.1, 50.0);
+        let matrix = projection_matrix(fov, aspect, near, far).mat_mul(&camera.view_matrix());
+        let directions = view_directions(fov, aspect, near, far, &camera);
+
+        // Points all around the camera, of which those in front of it are on the screen.
+        let mut checked = 0;
+        for i in 0..125 {
+            let offset = |step: usize| (step % 5) as f32 * 2.5 - 5.0;
+            let point =
+                camera
+                    .translation
+                    .add(&Vec3::new(offset(i), offset(i / 5), offset(i / 25)));
+            let screen = to_screen_space(&matrix, &point);
+            if screen.z <= 0.0 {
+                continue;
+            }
 
-            rasterizer::triangle(self.target, self.depth, fragment, &a, &b, &c, false);
+            let expected = point.sub(&camera.translation).normalize();
+            let direction = directions.at(&screen);
+            assert!(direction.sub(&expected).length() < 1e-4, "{:?}", point);
+            checked += 1;
         }
+        assert!(checked > 40, "{}", checked);
     }
 }
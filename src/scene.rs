@@ -0,0 +1,684 @@
+use super::camera::*;
+use super::geometry::*;
+use super::loaders;
+use super::math::*;
+use super::object::Object;
+use super::shaders::*;
+use super::watch::FileWatcher;
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+use ::failure::format_err;
+use ::failure::Error;
+
+/// Everything that's shown: the models with where to put them, the camera, the light,
+/// the shaders to pick from and the background, as described by a scene file.
+pub struct Scene {
+    /// The scene file.
+    pub path: PathBuf,
+    /// Loaded models, by the name the scene file gives them.
+    pub models: BTreeMap<String, Model>,
+    pub instances: Vec<Instance>,
+    pub camera: Camera,
+    pub controller: CameraController,
+    /// Whether the camera starts out orbiting on its own.
+    pub auto_orbit: bool,
+    /// Field of view, in radians.
+    pub fov: f32,
+    pub near: f32,
+    pub far: f32,
+    /// Direction of the light, which the lit shaders use.
+    pub light_direction: Vec3,
+    /// Shaders to switch between, starting with the first.
+    pub shaders: Vec<Box<dyn FragmentShader>>,
+    pub background: Vec4,
+    /// Problems with the model files that didn't keep them from loading, such as textures
+    /// that couldn't be loaded, or what was skipped in lenient mode.
+    pub warnings: Vec<String>,
+}
+
+/// A placement of a model in the scene.
+pub struct Instance {
+    pub model: String,
+    /// Only show this part of the model.
+    pub part: Option<String>,
+    pub translation: Vec3,
+    /// Rotation in radians.
+    pub rotation: Vec3,
+    pub scale: Vec3,
+    pub visible: bool,
+}
+
+impl Scene {
+    /// Loads a scene file, along with the models it refers to. Paths in it are relative
+    /// to the scene file.
+    pub fn load(path: &Path) -> Result<Scene, Error> {
+        Scene::load_with_sources(path, &mut vec![])
+    }
+
+    /// Loads a scene like `load`, adding every file it reads, or tries to, to `sources`.
+    /// When loading fails, those are the files that fixing it could take.
+    pub fn load_with_sources(path: &Path, sources: &mut Vec<PathBuf>) -> Result<Scene, Error> {
+        sources.push(path.to_owned());
+        let text = std::fs::read_to_string(path)
+            .map_err(|e| format_err!("could not read {}: {}", path.display(), e))?;
+        let file: SceneFile = toml::from_str(&text)
+            .map_err(|e| format_err!("invalid scene {}: {}", path.display(), e))?;
+
+        Scene::build(file, path, sources)
+            .map_err(|e| format_err!("in scene {}: {}", path.display(), e))
+    }
+
+    fn build(file: SceneFile, path: &Path, sources: &mut Vec<PathBuf>) -> Result<Scene, Error> {
+        let directory = path.parent().unwrap_or_else(|| Path::new(""));
+        let mut models = BTreeMap::new();
+        let mut warnings = vec![];
+        for (name, mesh) in file.meshes {
+            let mut model = match (&mesh.path, &mesh.primitive) {
+                (Some(path), None) => {
+                    let (model, model_warnings) = loaders::load_model_with_warnings(
+                        &directory.join(path),
+                        mesh.lenient,
+                        sources,
+                    )?;
+                    warnings.extend(model_warnings.iter().map(|warning| warning.to_string()));
+                    model
+                }
+                (None, Some(primitive)) => Model {
+                    parts: vec![Part {
+                        name: name.clone(),
+                        mesh: primitive
+                            .mesh()
+                            .map_err(|e| format_err!("mesh {:?}: {}", name, e))?,
+                    }],
+                    materials: vec![],
+                    sources: vec![],
+                },
+                _ => {
+                    return Err(format_err!(
+                        "mesh {:?} needs either a path or a primitive",
+                        name
+                    ))
+                }
+            };
+
+            // A color replaces the materials, so the whole model gets it.
+            if let Some(color) = &mesh.color {
+                let color = parse_color(color)?;
+                for part in &mut model.parts {
+                    part.mesh.set_color(color);
+                    part.mesh.material = None;
+                }
+            }
+            models.insert(name, model);
+        }
+
+        let instances = file
+            .objects
+            .into_iter()
+            .map(|object| {
+                let model = models
+                    .get(&object.mesh)
+                    .ok_or(format_err!("there's no mesh named {:?}", object.mesh))?;
+                if let Some(part) = &object.part {
+                    model.part(part).ok_or(format_err!(
+                        "mesh {:?} has no part named {:?}",
+                        object.mesh,
+                        part
+                    ))?;
+                }
+
+                Ok(Instance {
+                    model: object.mesh,
+                    part: object.part,
+                    translation: vec3(object.translation),
+                    rotation: vec3(object.rotation).mul(std::f32::consts::PI / 180.0),
+                    scale: match object.scale {
+                        Scale::Uniform(scale) => Vec3::new(scale, scale, scale),
+                        Scale::Axes(scale) => vec3(scale),
+                    },
+                    visible: object.visible,
+                })
+            })
+            .collect::<Result<Vec<_>, Error>>()?;
+
+        // Only one light is supported for now.
+        if file.lights.len() > 1 {
+            warnings.push(format!(
+                "only the first of the {} lights is used",
+                file.lights.len()
+            ));
+        }
+        let light_direction = file
+            .lights
+            .first()
+            .map(|light| vec3(light.direction))
+            .unwrap_or(Vec3::new(-0.707, -0.707, 0.0));
+
+        let shaders = file
+            .shaders
+            .iter()
+            .map(|name| shader(name, light_direction))
+            .collect::<Result<Vec<_>, Error>>()?;
+        if shaders.is_empty() {
+            return Err(format_err!("the list of shaders is empty"));
+        }
+
+        let camera_file = &file.camera;
+        let radians = std::f32::consts::PI / 180.0;
+        let mut controller = CameraController::orbit(
+            vec3(camera_file.target),
+            camera_file.distance,
+            camera_file.yaw * radians,
+            camera_file.pitch * radians,
+        );
+        match camera_file.mode.as_str() {
+            "orbit" => {}
+            "fly" => {
+                controller.mode = CameraMode::Fly;
+                if let Some(position) = camera_file.position {
+                    controller.position = vec3(position);
+                }
+            }
+            mode => {
+                return Err(format_err!(
+                    "unknown camera mode {:?}; expected orbit or fly",
+                    mode
+                ))
+            }
+        }
+        let mut camera = Camera {
+            translation: Vec3::new(0.0, 0.0, 0.0),
+            rotation: Vec3::new(0.0, 0.0, 0.0),
+        };
+        controller.apply(&mut camera);
+
+        Ok(Scene {
+            path: path.to_owned(),
+            models,
+            instances,
+            camera,
+            controller,
+            auto_orbit: camera_file.auto_orbit,
+            fov: camera_file.fov * radians,
+            near: camera_file.near,
+            far: camera_file.far,
+            light_direction,
+            shaders,
+            background: parse_color(&file.background)?,
+            warnings,
+        })
+    }
+
+    /// The scene file and all the files its models were read from.
+    pub fn sources(&self) -> Vec<PathBuf> {
+        let mut sources = vec![self.path.clone()];
+        for model in self.models.values() {
+            sources.extend(model.sources.iter().cloned());
+        }
+        sources
+    }
+
+    /// Creates the objects to draw, one for every part of every instance.
+    pub fn objects(&self) -> Vec<Object<'_>> {
+        let mut objects = vec![];
+        for instance in &self.instances {
+            let model = &self.models[&instance.model];
+            for (part, mut object) in model.parts.iter().zip(Object::from_model(model)) {
+                if let Some(name) = &instance.part {
+                    if &part.name != name {
+                        continue;
+                    }
+                }
+                object.translation = instance.translation;
+                object.rotation = instance.rotation;
+                object.scale = instance.scale;
+                object.visible = instance.visible;
+                objects.push(object);
+            }
+        }
+        objects
+    }
+}
+
+/// Loads a scene again whenever any of its files change.
+pub struct SceneWatcher {
+    path: PathBuf,
+    files: FileWatcher,
+    /// Why the last reload failed, until one works again.
+    pub error: Option<String>,
+}
+
+impl SceneWatcher {
+    pub fn new(scene: &Scene) -> Self {
+        SceneWatcher {
+            path: scene.path.clone(),
+            files: FileWatcher::new(scene.sources()),
+            error: None,
+        }
+    }
+
+    /// Returns the scene loaded again if any of its files changed since the last time. If
+    /// loading fails, there's no new scene, so the last one that loaded stays in use.
+    pub fn poll(&mut self) -> Option<Scene> {
+        if !self.files.poll() {
+            return None;
+        }
+
+        // The scene may have started using other files, whether it loads or not.
+        let mut sources = vec![];
+        let scene = Scene::load_with_sources(&self.path, &mut sources);
+        self.files = FileWatcher::new(sources);
+        match scene {
+            Ok(scene) => {
+                self.error = None;
+                Some(scene)
+            }
+            Err(e) => {
+                self.error = Some(e.to_string());
+                None
+            }
+        }
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct SceneFile {
+    #[serde(default = "default_background")]
+    background: Vec<f32>,
+    #[serde(default)]
+    camera: CameraFile,
+    #[serde(default)]
+    lights: Vec<LightFile>,
+    #[serde(default = "default_shaders")]
+    shaders: Vec<String>,
+    #[serde(default)]
+    meshes: BTreeMap<String, MeshFile>,
+    #[serde(default)]
+    objects: Vec<ObjectFile>,
+}
+
+/// How the camera starts out. Angles are in degrees.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields, default)]
+struct CameraFile {
+    mode: String,
+    target: [f32; 3],
+    distance: f32,
+    /// Position in fly mode, where there's no target.
+    position: Option<[f32; 3]>,
+    yaw: f32,
+    pitch: f32,
+    fov: f32,
+    near: f32,
+    far: f32,
+    auto_orbit: bool,
+}
+
+impl Default for CameraFile {
+    fn default() -> Self {
+        CameraFile {
+            mode: "orbit".to_owned(),
+            target: [0.0, 0.0, 0.0],
+            distance: 3.5,
+            position: None,
+            yaw: 90.0,
+            pitch: 30.0,
+            fov: 30.0,
+            near: 0.1,
+            far: 10.0,
+            auto_orbit: true,
+        }
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct LightFile {
+    direction: [f32; 3],
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct MeshFile {
+    path: Option<String>,
+    primitive: Option<PrimitiveFile>,
+    color: Option<Vec<f32>>,
+    /// Skip what can't be understood in the file with a warning, instead of failing.
+    #[serde(default)]
+    lenient: bool,
+}
+
+/// A generated mesh. Anything left out gets a default that makes it fit from -1 to 1.
+#[derive(Deserialize)]
+#[serde(tag = "shape", rename_all = "snake_case", deny_unknown_fields)]
+enum PrimitiveFile {
+    Plane {
+        width: Option<f32>,
+        depth: Option<f32>,
+        columns: Option<usize>,
+        rows: Option<usize>,
+    },
+    Cube {
+        size: Option<f32>,
+        subdivisions: Option<usize>,
+    },
+    UvSphere {
+        radius: Option<f32>,
+        segments: Option<usize>,
+        rings: Option<usize>,
+    },
+    Icosphere {
+        radius: Option<f32>,
+        subdivisions: Option<usize>,
+    },
+    Cylinder {
+        radius: Option<f32>,
+        height: Option<f32>,
+        segments: Option<usize>,
+        rings: Option<usize>,
+    },
+    Cone {
+        radius: Option<f32>,
+        height: Option<f32>,
+        segments: Option<usize>,
+        rings: Option<usize>,
+    },
+    Torus {
+        major_radius: Option<f32>,
+        minor_radius: Option<f32>,
+        segments: Option<usize>,
+        sides: Option<usize>,
+    },
+    Capsule {
+        radius: Option<f32>,
+        height: Option<f32>,
+        segments: Option<usize>,
+        rings: Option<usize>,
+    },
+}
+
+impl PrimitiveFile {
+    /// Generates the mesh, checking that it doesn't get more detailed than anyone could
+    /// see, which would take a long time or need more vertices than `u32` indices reach.
+    fn mesh(&self) -> Result<Mesh, Error> {
+        let count = |name, value, default| count_up_to(name, value, default, MAX_DIVISIONS);
+        Ok(match self {
+            PrimitiveFile::Plane {
+                width,
+                depth,
+                columns,
+                rows,
+            } => Mesh::plane(
+                width.unwrap_or(2.0),
+                depth.unwrap_or(2.0),
+                count("columns", *columns, 1)?,
+                count("rows", *rows, 1)?,
+            ),
+            PrimitiveFile::Cube { size, subdivisions } => Mesh::cube(
+                size.unwrap_or(2.0),
+                count("subdivisions", *subdivisions, 1)?,
+            ),
+            PrimitiveFile::UvSphere {
+                radius,
+                segments,
+                rings,
+            } => Mesh::uv_sphere(
+                radius.unwrap_or(1.0),
+                count("segments", *segments, 32)?,
+                count("rings", *rings, 16)?,
+            ),
+            PrimitiveFile::Icosphere {
+                radius,
+                subdivisions,
+            } => Mesh::icosphere(
+                radius.unwrap_or(1.0),
+                count_up_to("subdivisions", *subdivisions, 3, MAX_ICOSPHERE_SUBDIVISIONS)?,
+            ),
+            PrimitiveFile::Cylinder {
+                radius,
+                height,
+                segments,
+                rings,
+            } => Mesh::cylinder(
+                radius.unwrap_or(1.0),
+                height.unwrap_or(2.0),
+                count("segments", *segments, 32)?,
+                count("rings", *rings, 1)?,
+            ),
+            PrimitiveFile::Cone {
+                radius,
+                height,
+                segments,
+                rings,
+            } => Mesh::cone(
+                radius.unwrap_or(1.0),
+                height.unwrap_or(2.0),
+                count("segments", *segments, 32)?,
+                count("rings", *rings, 1)?,
+            ),
+            PrimitiveFile::Torus {
+                major_radius,
+                minor_radius,
+                segments,
+                sides,
+            } => Mesh::torus(
+                major_radius.unwrap_or(0.75),
+                minor_radius.unwrap_or(0.25),
+                count("segments", *segments, 32)?,
+                count("sides", *sides, 16)?,
+            ),
+            PrimitiveFile::Capsule {
+                radius,
+                height,
+                segments,
+                rings,
+            } => Mesh::capsule(
+                radius.unwrap_or(0.5),
+                height.unwrap_or(1.0),
+                count("segments", *segments, 32)?,
+                count("rings", *rings, 8)?,
+            ),
+        })
+    }
+}
+
+/// The most columns, rows, segments, rings or sides a generated mesh can have, which keeps
+/// it to a few million vertices.
+const MAX_DIVISIONS: usize = 1024;
+/// The number of triangles of an icosphere goes up fourfold with every subdivision, to
+/// 20 * 4^7, or about 330 thousand, at the most.
+const MAX_ICOSPHERE_SUBDIVISIONS: usize = 7;
+
+fn count_up_to(
+    name: &str,
+    value: Option<usize>,
+    default: usize,
+    max: usize,
+) -> Result<usize, Error> {
+    match value {
+        Some(value) if value > max => Err(format_err!(
+            "{} can be at most {}, not {}",
+            name,
+            max,
+            value
+        )),
+        value => Ok(value.unwrap_or(default)),
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ObjectFile {
+    mesh: String,
+    part: Option<String>,
+    #[serde(default)]
+    translation: [f32; 3],
+    /// Angles in degrees.
+    #[serde(default)]
+    rotation: [f32; 3],
+    #[serde(default = "default_scale")]
+    scale: Scale,
+    #[serde(default = "default_visible")]
+    visible: bool,
+}
+
+/// Either the same scale along every axis, or one for each.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum Scale {
+    Uniform(f32),
+    Axes([f32; 3]),
+}
+
+fn default_background() -> Vec<f32> {
+    vec![0.3, 0.3, 0.3]
+}
+
+fn default_shaders() -> Vec<String> {
+    vec!["diffuse".to_owned()]
+}
+
+fn default_scale() -> Scale {
+    Scale::Uniform(1.0)
+}
+
+fn default_visible() -> bool {
+    true
+}
+
+fn vec3([x, y, z]: [f32; 3]) -> Vec3 {
+    Vec3::new(x, y, z)
+}
+
+/// Parses an RGB or RGBA color.
+fn parse_color(color: &[f32]) -> Result<Vec4, Error> {
+    match *color {
+        [r, g, b] => Ok(Vec4::new(r, g, b, 1.0)),
+        [r, g, b, a] => Ok(Vec4::new(r, g, b, a)),
+        _ => Err(format_err!(
+            "a color has 3 or 4 components, not {}",
+            color.len()
+        )),
+    }
+}
+
+fn shader(name: &str, light_direction: Vec3) -> Result<Box<dyn FragmentShader>, Error> {
+    Ok(match name {
+        "diffuse" => Box::new(DiffuseShader(light_direction)),
+        "material" => Box::new(MaterialShader(light_direction)),
+        "texture" => Box::new(TextureShader(light_direction)),
+        "debug" => Box::new(DebugShader),
+        "solid" => Box::new(SolidShader(Vec4::new(1.0, 1.0, 1.0, 1.0))),
+        "screen_uv" => Box::new(ScreenUVShader),
+        "rainbow" => Box::new(RainbowShader),
+        name => return Err(format_err!("unknown shader {:?}", name)),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::temp_dir;
+
+    #[test]
+    fn lenient_meshes_load_with_warnings() {
+        let directory = temp_dir("scene-lenient");
+        std::fs::write(
+            directory.join("quad.obj"),
+            "v 0 0 0\nv 1 0 0\nv 1 1 0\nbogus 1 2 3\nf 1 2 3\n",
+        )
+        .unwrap();
+        let strict = directory.join("strict.toml");
+        std::fs::write(&strict, "[meshes]\nquad = { path = \"quad.obj\" }\n").unwrap();
+        let lenient = directory.join("lenient.toml");
+        std::fs::write(
+            &lenient,
+            "[meshes]\nquad = { path = \"quad.obj\", lenient = true }\n",
+        )
+        .unwrap();
+
+        assert!(Scene::load(&strict).is_err());
+        let scene = Scene::load(&lenient).unwrap();
+        assert!(scene.models.contains_key("quad"));
+        assert_eq!(scene.warnings.len(), 1);
+        assert!(scene.warnings[0].contains("bogus"), "{}", scene.warnings[0]);
+    }
+
+    #[test]
+    fn lights_after_the_first_are_a_warning() {
+        let path = temp_dir("scene-lights").join("scene.toml");
+        let light = "[[lights]]\ndirection = [0.0, -1.0, 0.0]\n";
+
+        std::fs::write(&path, light).unwrap();
+        assert!(Scene::load(&path).unwrap().warnings.is_empty());
+
+        std::fs::write(&path, light.repeat(3)).unwrap();
+        let scene = Scene::load(&path).unwrap();
+        assert_eq!(
+            scene.warnings,
+            vec!["only the first of the 3 lights is used".to_string()]
+        );
+    }
+
+    #[test]
+    fn watcher_keeps_watching_what_a_broken_scene_refers_to() {
+        let directory = temp_dir("scene-watcher");
+        let path = directory.join("scene.toml");
+        let model = directory.join("triangle.obj");
+        std::fs::write(&path, "[meshes]\ntriangle = { path = \"triangle.obj\" }\n").unwrap();
+        std::fs::write(&model, "v 0 0 0\nv 1 0 0\nv 0 1 0\nf 1 2 3\n").unwrap();
+        let mut watcher = SceneWatcher::new(&Scene::load(&path).unwrap());
+
+        // Refer to a library that isn't there yet.
+        std::fs::write(
+            &model,
+            "mtllib triangle.mtl\nv 0 0 0\nv 1 0 0\nv 0 1 0\nf 1 2 3\n",
+        )
+        .unwrap();
+        assert!(watcher.poll().is_none());
+        assert!(watcher.poll().is_none());
+        assert!(watcher.error.is_some());
+        let library = directory.join("triangle.mtl");
+        assert!(watcher.files.files().any(|file| file == library));
+
+        std::fs::write(&library, "newmtl red\nKd 1 0 0\n").unwrap();
+        assert!(watcher.poll().is_none());
+        let scene = watcher.poll().unwrap();
+        assert!(watcher.error.is_none());
+        assert_eq!(scene.models["triangle"].materials.len(), 1);
+    }
+
+    #[test]
+    fn primitives_are_limited_in_detail() {
+        let path = temp_dir("scene-primitives").join("scene.toml");
+        let cases = [
+            ("shape = \"icosphere\", subdivisions = 4", None),
+            (
+                "shape = \"icosphere\", subdivisions = 15",
+                Some("subdivisions can be at most 7"),
+            ),
+            ("shape = \"plane\", columns = 1024, rows = 2", None),
+            (
+                "shape = \"plane\", columns = 100000",
+                Some("columns can be at most 1024"),
+            ),
+            (
+                "shape = \"torus\", sides = 4294967296",
+                Some("sides can be at most 1024"),
+            ),
+        ];
+        for (primitive, error) in cases {
+            let text = format!("[meshes]\nshape = {{ primitive = {{ {} }} }}\n", primitive);
+            std::fs::write(&path, text).unwrap();
+            match (Scene::load(&path), error) {
+                (Ok(_), None) => {}
+                (Err(e), Some(error)) => assert!(e.to_string().contains(error), "{}", e),
+                (Ok(_), Some(error)) => panic!("{} loaded, expected {:?}", primitive, error),
+                (Err(e), None) => panic!("{} failed: {}", primitive, e),
+            }
+        }
+    }
+}
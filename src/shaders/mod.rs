@@ -4,15 +4,60 @@ use super::geometry::*;
 use super::math::*;
 use rand::prelude::*;
 
-pub struct FragmentInput {
+pub struct FragmentInput<'a> {
     pub vertex: Vertex,
     pub screen_uv: Vec2,
+    pub material: &'a Material,
+    /// Direction from the camera to the fragment, in world space.
+    pub view_direction: Vec3,
 }
 
 pub trait FragmentShader {
     fn fragment_color(&self, input: &FragmentInput) -> Vec4;
 }
 
+/// A fragment shader along with the state of the draw call it's used for.
+pub struct Shading<'a> {
+    pub shader: &'a dyn FragmentShader,
+    pub material: &'a Material,
+    pub view_directions: ViewDirections,
+}
+
+/// Works out the direction from the camera to a fragment, in world space, from where the
+/// fragment is in screen space, by undoing the projection.
+#[derive(Debug, Copy, Clone)]
+pub struct ViewDirections {
+    /// Turns the depth of a fragment in screen space into its distance along the view axis.
+    pub depth: (f32, f32),
+    /// Turns a point in screen space, with X and Y multiplied by the distance along the view
+    /// axis, into a direction in world space.
+    pub unproject: Mat4x4,
+}
+
+impl ViewDirections {
+    /// The same direction for every fragment.
+    pub fn constant(direction: Vec3) -> ViewDirections {
+        ViewDirections {
+            depth: (0.0, 1.0),
+            unproject: Mat4x4([
+                [0.0, 0.0, direction.x, 0.0],
+                [0.0, 0.0, direction.y, 0.0],
+                [0.0, 0.0, direction.z, 0.0],
+                [0.0, 0.0, 0.0, 1.0],
+            ]),
+        }
+    }
+
+    pub fn at(&self, position: &Vec3) -> Vec3 {
+        let (scale, offset) = self.depth;
+        let w = position.z * scale + offset;
+        self.unproject
+            .mul(&Vec4::new(position.x * w, position.y * w, w, 0.0))
+            .xyz()
+            .normalize()
+    }
+}
+
 pub struct SolidShader(pub Vec4);
 impl FragmentShader for SolidShader {
     fn fragment_color(&self, _input: &FragmentInput) -> Vec4 {
@@ -51,6 +96,80 @@ impl FragmentShader for DiffuseShader {
     }
 }
 
+/// Blinn-Phong lighting from a single directional light, using the ambient, specular,
+/// emissive and opacity values of the material. The diffuse color comes from the vertex
+/// and the diffuse texture.
+pub struct MaterialShader(pub Vec3);
+impl FragmentShader for MaterialShader {
+    fn fragment_color(&self, input: &FragmentInput) -> Vec4 {
+        let &MaterialShader(light_direction) = self;
+        let material = input.material;
+        let to_light = light_direction.mul(-1.0).normalize();
+        // This expects vertex normals in world-space.
+        let normal = input.vertex.normal.normalize();
+
+        let albedo = diffuse_color(input);
+        let ambient = material.ambient.mul(AMBIENT_LIGHT);
+        let diffuse = albedo.xyz().mul(normal.dot(&to_light).max(0.0));
+
+        let specular = if material.has_highlights() {
+            let to_camera = input.view_direction.mul(-1.0).normalize();
+            let halfway = to_light.add(&to_camera).normalize();
+            material
+                .specular
+                .mul(normal.dot(&halfway).max(0.0).powf(material.shininess))
+        } else {
+            Vec3::new(0.0, 0.0, 0.0)
+        };
+
+        let color = ambient.add(&diffuse).add(&specular).add(&material.emissive);
+
+        Vec4::new(
+            clamp((0.0, 1.0), color.x),
+            clamp((0.0, 1.0), color.y),
+            clamp((0.0, 1.0), color.z),
+            material.opacity * albedo.w,
+        )
+    }
+}
+
+/// How bright the light reaching every surface from all around is.
+const AMBIENT_LIGHT: f32 = 0.1;
+
+/// Like `DiffuseShader`, but with the color taken from the material's diffuse texture.
+pub struct TextureShader(pub Vec3);
+impl FragmentShader for TextureShader {
+    fn fragment_color(&self, input: &FragmentInput) -> Vec4 {
+        let &TextureShader(light_direction) = self;
+        let light_direction = light_direction.mul(-1.0);
+
+        let color = diffuse_color(input);
+        let lighting = map(
+            (-1.0, 1.0),
+            (0.0, 1.0),
+            input.vertex.normal.dot(&light_direction),
+        );
+        Vec4::from_vec3(color.xyz().mul(lighting), color.w)
+    }
+}
+
+/// The vertex color, multiplied by the diffuse texture of the material if it has one.
+fn diffuse_color(input: &FragmentInput) -> Vec4 {
+    let color = input.vertex.color;
+    match &input.material.diffuse_texture {
+        Some(texture) => {
+            let texel = texture.sample(&input.vertex.uv);
+            Vec4::new(
+                color.x * texel.x,
+                color.y * texel.y,
+                color.z * texel.z,
+                color.w * texel.w,
+            )
+        }
+        None => color,
+    }
+}
+
 pub struct ScreenUVShader;
 impl FragmentShader for ScreenUVShader {
     fn fragment_color(&self, input: &FragmentInput) -> Vec4 {
@@ -62,6 +181,6 @@ pub struct RainbowShader;
 impl FragmentShader for RainbowShader {
     fn fragment_color(&self, _input: &FragmentInput) -> Vec4 {
         let mut rng = rand::thread_rng();
-        return Vec4::new(rng.gen(), rng.gen(), rng.gen(), 1.0);
+        Vec4::new(rng.gen(), rng.gen(), rng.gen(), 1.0)
     }
 }
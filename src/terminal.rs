@@ -0,0 +1,193 @@
+use std::io::Write;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+
+/// Switches to the alternate screen, resets colors and hides the cursor.
+pub const ENTER_SCREEN: &[u8] = b"\x1b[?1049h\x1b[0m\x1b[2J\x1b[?25l";
+/// Undoes `ENTER_SCREEN`, bringing back whatever was in the terminal before.
+pub const LEAVE_SCREEN: &[u8] = b"\x1b[0m\x1b[?25h\x1b[?1049l";
+
+const ENABLE_MOUSE: &[u8] = b"\x1b[?1002h\x1b[?1006h";
+const DISABLE_MOUSE: &[u8] = b"\x1b[?1006l\x1b[?1002l";
+
+// What has been done to the terminal and needs undoing, kept globally so that
+// the panic hook can get at it no matter where the panic happens.
+static SCREEN_ACTIVE: AtomicBool = AtomicBool::new(false);
+static RAW_MODE: Mutex<Option<(libc::termios, bool)>> = Mutex::new(None);
+
+/// Puts the terminal in non-canonical mode without echo, so that key presses
+/// reach us one at a time. The previous mode is restored when this is dropped.
+///
+/// Signal generation is left on, so Ctrl-C still ends up in the ctrlc handler.
+pub struct RawMode(());
+
+impl RawMode {
+    pub fn enable(mouse: bool) -> std::io::Result<RawMode> {
+        let original = unsafe {
+            let mut termios: libc::termios = std::mem::zeroed();
+            if libc::tcgetattr(libc::STDIN_FILENO, &mut termios) != 0 {
+                return Err(std::io::Error::last_os_error());
+            }
+            termios
+        };
+
+        let mut raw = original;
+        raw.c_lflag &= !(libc::ICANON | libc::ECHO);
+        raw.c_cc[libc::VMIN] = 1;
+        raw.c_cc[libc::VTIME] = 0;
+
+        if unsafe { libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, &raw) } != 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+
+        *RAW_MODE.lock().unwrap() = Some((original, mouse));
+
+        if mouse {
+            // Report button presses and drags (1002) using the SGR encoding (1006),
+            // which doesn't break down for coordinates above 223.
+            let mut stdout = std::io::stdout();
+            stdout.write_all(ENABLE_MOUSE)?;
+            stdout.flush()?;
+        }
+
+        Ok(RawMode(()))
+    }
+}
+
+impl Drop for RawMode {
+    fn drop(&mut self) {
+        restore_mode();
+    }
+}
+
+/// Records whether stdout is currently showing the alternate screen, so that `restore` knows to leave it.
+pub fn set_screen_active(active: bool) {
+    SCREEN_ACTIVE.store(active, Ordering::Relaxed);
+}
+
+/// Puts the terminal back the way we found it. Safe to call more than once.
+pub fn restore() {
+    restore_mode();
+
+    if SCREEN_ACTIVE.swap(false, Ordering::Relaxed) {
+        let mut stdout = std::io::stdout();
+        let _ = stdout.write_all(LEAVE_SCREEN);
+        let _ = stdout.flush();
+    }
+}
+
+/// Makes panics restore the terminal before the message is printed; otherwise it
+/// would end up on the alternate screen and disappear along with it.
+pub fn install_panic_hook() {
+    let previous = std::panic::take_hook();
+    std::panic::set_hook(Box::new(move |info| {
+        restore();
+        previous(info);
+    }));
+}
+
+fn restore_mode() {
+    // The lock is poisoned if we panicked while holding it, but the data is still good.
+    let saved = RAW_MODE
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+        .take();
+
+    if let Some((original, mouse)) = saved {
+        if mouse {
+            let mut stdout = std::io::stdout();
+            let _ = stdout.write_all(DISABLE_MOUSE);
+            let _ = stdout.flush();
+        }
+
+        unsafe {
+            libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, &original);
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct WindowSize {
+    pub columns: usize,
+    pub rows: usize,
+    /// Size of the text area in pixels. Terminals that don't know (or don't tell) report zero.
+    pub pixel_width: usize,
+    pub pixel_height: usize,
+}
+
+impl WindowSize {
+    /// Size of one character cell in pixels, if the terminal reports it.
+    pub fn cell_size(&self) -> Option<(usize, usize)> {
+        if self.pixel_width == 0 || self.pixel_height == 0 || self.columns == 0 || self.rows == 0 {
+            None
+        } else {
+            Some((
+                self.pixel_width / self.columns,
+                self.pixel_height / self.rows,
+            ))
+        }
+    }
+
+    /// Width divided by height of one character cell, if the terminal reports its pixel size.
+    pub fn cell_aspect(&self) -> Option<f32> {
+        self.cell_size().map(|_| {
+            (self.pixel_width as f32 / self.columns as f32)
+                / (self.pixel_height as f32 / self.rows as f32)
+        })
+    }
+}
+
+/// Asks the terminal attached to stdout (or failing that, stdin or stderr) for its size.
+pub fn window_size() -> Option<WindowSize> {
+    [libc::STDOUT_FILENO, libc::STDIN_FILENO, libc::STDERR_FILENO]
+        .iter()
+        .filter_map(|&fd| unsafe {
+            let mut size: libc::winsize = std::mem::zeroed();
+            if libc::ioctl(fd, libc::TIOCGWINSZ, &mut size) == 0 && size.ws_col > 0 {
+                Some(WindowSize {
+                    columns: size.ws_col as usize,
+                    rows: size.ws_row as usize,
+                    pixel_width: size.ws_xpixel as usize,
+                    pixel_height: size.ws_ypixel as usize,
+                })
+            } else {
+                None
+            }
+        })
+        .next()
+}
+
+/// Waits until there's something to read on stdin, for at most `timeout`.
+/// Returns whether there is.
+pub fn wait_for_input(timeout: std::time::Duration) -> bool {
+    let mut fd = libc::pollfd {
+        fd: libc::STDIN_FILENO,
+        events: libc::POLLIN,
+        revents: 0,
+    };
+    let milliseconds = timeout.as_millis().min(libc::c_int::MAX as u128) as libc::c_int;
+    unsafe { libc::poll(&mut fd, 1, milliseconds) > 0 }
+}
+
+static RESIZED: AtomicBool = AtomicBool::new(false);
+
+extern "C" fn on_window_change(_: libc::c_int) {
+    RESIZED.store(true, Ordering::Relaxed);
+}
+
+/// Starts listening for SIGWINCH. Until `take_resized` is called for the first time,
+/// it reports a resize, so callers can size their buffers in the same place they handle resizes.
+pub fn watch_resize() {
+    RESIZED.store(true, Ordering::Relaxed);
+    unsafe {
+        libc::signal(
+            libc::SIGWINCH,
+            on_window_change as extern "C" fn(libc::c_int) as libc::sighandler_t,
+        );
+    }
+}
+
+/// Returns whether the terminal has been resized since the last call.
+pub fn take_resized() -> bool {
+    RESIZED.swap(false, Ordering::Relaxed)
+}
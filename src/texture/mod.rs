@@ -0,0 +1,185 @@
+use super::math::*;
+use std::path::Path;
+
+use ::failure::format_err;
+use ::failure::Error;
+
+#[cfg(feature = "png")]
+mod png;
+mod ppm;
+mod tga;
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Filter {
+    /// Use the texel closest to the sampled point; blocky up close.
+    Nearest,
+    /// Blend the four closest texels.
+    Bilinear,
+}
+
+/// What happens to texture coordinates outside 0 to 1.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Wrap {
+    /// Tile the texture.
+    Repeat,
+    /// Stretch the edge texels.
+    Clamp,
+    /// Tile the texture, flipping every other copy.
+    Mirror,
+}
+
+/// An image that can be sampled at texture coordinates.
+#[derive(Clone)]
+pub struct Texture {
+    pub width: usize,
+    pub height: usize,
+    pub filter: Filter,
+    pub wrap: Wrap,
+    /// Rows from top to bottom, with RGBA components between 0 and 1.
+    pixels: Vec<Vec4>,
+}
+
+impl Texture {
+    /// Panics if the texture would be empty, since there'd be nothing to sample.
+    pub fn new(width: usize, height: usize, pixels: Vec<Vec4>) -> Texture {
+        assert!(width > 0 && height > 0, "texture is empty");
+        assert_eq!(pixels.len(), width * height, "wrong number of pixels");
+        Texture {
+            width,
+            height,
+            filter: Filter::Bilinear,
+            wrap: Wrap::Repeat,
+            pixels,
+        }
+    }
+
+    /// Loads a texture, with the format picked by the file extension.
+    pub fn load(path: &Path) -> Result<Texture, Error> {
+        let data = std::fs::read(path)
+            .map_err(|e| format_err!("could not read texture {}: {}", path.display(), e))?;
+        let extension = path
+            .extension()
+            .and_then(|extension| extension.to_str())
+            .unwrap_or("");
+
+        Texture::decode(&data, extension)
+            .map_err(|e| format_err!("could not load texture {}: {}", path.display(), e))
+    }
+
+    /// Decodes an image file that's already in memory. `format` is its file extension.
+    pub fn decode(data: &[u8], format: &str) -> Result<Texture, Error> {
+        match format.to_ascii_lowercase().as_str() {
+            "ppm" | "pgm" | "pnm" => ppm::decode(data),
+            "tga" => tga::decode(data),
+            #[cfg(feature = "png")]
+            "png" => png::decode(data),
+            #[cfg(not(feature = "png"))]
+            "png" => Err(format_err!(
+                "PNG support isn't enabled; build with --features png"
+            )),
+            _ => Err(format_err!("unknown texture format")),
+        }
+    }
+
+    pub fn get_pixel(&self, x: usize, y: usize) -> Vec4 {
+        self.pixels[y * self.width + x]
+    }
+
+    /// Samples the texture at `uv`, where (0, 0) is the bottom left corner and (1, 1) the top right.
+    pub fn sample(&self, uv: &Vec2) -> Vec4 {
+        // Texel centers are at half coordinates.
+        let x = uv.x * self.width as f32 - 0.5;
+        let y = (1.0 - uv.y) * self.height as f32 - 0.5;
+
+        match self.filter {
+            Filter::Nearest => self.texel(x.round() as i64, y.round() as i64),
+            Filter::Bilinear => {
+                let (x0, y0) = (x.floor(), y.floor());
+                let (fx, fy) = (x - x0, y - y0);
+                let (x0, y0) = (x0 as i64, y0 as i64);
+
+                let top = lerp(&self.texel(x0, y0), &self.texel(x0 + 1, y0), fx);
+                let bottom = lerp(&self.texel(x0, y0 + 1), &self.texel(x0 + 1, y0 + 1), fx);
+                lerp(&top, &bottom, fy)
+            }
+        }
+    }
+
+    /// Looks up a texel by coordinates that may be outside the texture, according to the wrap mode.
+    fn texel(&self, x: i64, y: i64) -> Vec4 {
+        let x = wrap(self.wrap, x, self.width as i64);
+        let y = wrap(self.wrap, y, self.height as i64);
+        self.get_pixel(x, y)
+    }
+}
+
+impl std::fmt::Debug for Texture {
+    // Leave out the pixels; nobody wants to read those.
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("Texture")
+            .field("width", &self.width)
+            .field("height", &self.height)
+            .field("filter", &self.filter)
+            .field("wrap", &self.wrap)
+            .finish()
+    }
+}
+
+fn wrap(mode: Wrap, i: i64, size: i64) -> usize {
+    let wrapped = match mode {
+        Wrap::Repeat => i.rem_euclid(size),
+        Wrap::Clamp => i.max(0).min(size - 1),
+        Wrap::Mirror => {
+            let i = i.rem_euclid(2 * size);
+            if i < size {
+                i
+            } else {
+                2 * size - 1 - i
+            }
+        }
+    };
+    wrapped as usize
+}
+
+fn lerp(a: &Vec4, b: &Vec4, t: f32) -> Vec4 {
+    a.mul(1.0 - t).add(&b.mul(t))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn wraps_coordinates() {
+        assert_eq!(wrap(Wrap::Repeat, -1, 3), 2);
+        assert_eq!(wrap(Wrap::Repeat, 4, 3), 1);
+        assert_eq!(wrap(Wrap::Clamp, -5, 3), 0);
+        assert_eq!(wrap(Wrap::Clamp, 5, 3), 2);
+        assert_eq!(wrap(Wrap::Mirror, 3, 3), 2);
+        assert_eq!(wrap(Wrap::Mirror, -1, 3), 0);
+        assert_eq!(wrap(Wrap::Mirror, 6, 3), 0);
+    }
+
+    #[test]
+    fn samples_texel_centers() {
+        let mut texture = Texture::new(
+            2,
+            1,
+            vec![Vec4::new(0.0, 0.0, 0.0, 1.0), Vec4::new(1.0, 1.0, 1.0, 1.0)],
+        );
+        texture.wrap = Wrap::Clamp;
+
+        assert_eq!(texture.sample(&Vec2::new(0.25, 0.5)).x, 0.0);
+        assert_eq!(texture.sample(&Vec2::new(0.5, 0.5)).x, 0.5);
+        assert_eq!(texture.sample(&Vec2::new(0.75, 0.5)).x, 1.0);
+
+        texture.filter = Filter::Nearest;
+        assert_eq!(texture.sample(&Vec2::new(0.6, 0.5)).x, 1.0);
+    }
+
+    #[test]
+    #[should_panic(expected = "texture is empty")]
+    fn empty_textures_cant_be_made() {
+        Texture::new(0, 0, vec![]);
+    }
+}
@@ -0,0 +1,107 @@
+use super::*;
+
+pub fn decode(data: &[u8]) -> Result<Texture, Error> {
+    let mut decoder = ::png::Decoder::new(data);
+    // Get 8 bits per channel, with palettes and low bit depths expanded.
+    decoder.set_transformations(::png::Transformations::EXPAND | ::png::Transformations::STRIP_16);
+
+    let (info, mut reader) = decoder.read_info()?;
+    if info.width == 0 || info.height == 0 {
+        return Err(format_err!(
+            "image is {}x{} pixels",
+            info.width,
+            info.height
+        ));
+    }
+    let mut buffer = vec![0; info.buffer_size()];
+    reader.next_frame(&mut buffer)?;
+
+    let channels = match reader.output_color_type().0 {
+        ::png::ColorType::Grayscale => 1,
+        ::png::ColorType::GrayscaleAlpha => 2,
+        ::png::ColorType::RGB => 3,
+        ::png::ColorType::RGBA => 4,
+        ::png::ColorType::Indexed => return Err(format_err!("palette wasn't expanded")),
+    };
+
+    let value = |b: u8| b as f32 / 255.0;
+    let pixels = buffer
+        .chunks(channels)
+        .map(|pixel| match *pixel {
+            [gray] => Vec4::new(value(gray), value(gray), value(gray), 1.0),
+            [gray, a] => Vec4::new(value(gray), value(gray), value(gray), value(a)),
+            [r, g, b] => Vec4::new(value(r), value(g), value(b), 1.0),
+            [r, g, b, a] => Vec4::new(value(r), value(g), value(b), value(a)),
+            _ => unreachable!(),
+        })
+        .collect();
+
+    Ok(Texture::new(
+        info.width as usize,
+        info.height as usize,
+        pixels,
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn encode(
+        (width, height): (u32, u32),
+        color: ::png::ColorType,
+        palette: Option<Vec<u8>>,
+        data: &[u8],
+    ) -> Vec<u8> {
+        let mut png = vec![];
+        let mut encoder = ::png::Encoder::new(&mut png, width, height);
+        encoder.set_color(color);
+        encoder.set_depth(::png::BitDepth::Eight);
+        if let Some(palette) = palette {
+            encoder.set_palette(palette);
+        }
+        let mut writer = encoder.write_header().unwrap();
+        writer.write_image_data(data).unwrap();
+        drop(writer);
+        png
+    }
+
+    fn rgba(texture: &Texture, x: usize, y: usize) -> (f32, f32, f32, f32) {
+        let pixel = texture.get_pixel(x, y);
+        (pixel.x, pixel.y, pixel.z, pixel.w)
+    }
+
+    #[test]
+    fn decodes_every_color_type() {
+        let rgba_png = encode(
+            (2, 1),
+            ::png::ColorType::RGBA,
+            None,
+            &[255, 0, 0, 255, 0, 0, 255, 0],
+        );
+        let texture = decode(&rgba_png).unwrap();
+        assert_eq!(rgba(&texture, 0, 0), (1.0, 0.0, 0.0, 1.0));
+        assert_eq!(rgba(&texture, 1, 0), (0.0, 0.0, 1.0, 0.0));
+
+        let rgb = encode((1, 2), ::png::ColorType::RGB, None, &[0, 255, 0, 0, 0, 0]);
+        let texture = decode(&rgb).unwrap();
+        assert_eq!(rgba(&texture, 0, 0), (0.0, 1.0, 0.0, 1.0));
+        assert_eq!(rgba(&texture, 0, 1), (0.0, 0.0, 0.0, 1.0));
+
+        let gray = encode((1, 1), ::png::ColorType::GrayscaleAlpha, None, &[255, 0]);
+        assert_eq!(rgba(&decode(&gray).unwrap(), 0, 0), (1.0, 1.0, 1.0, 0.0));
+
+        let palette = vec![0, 0, 0, 255, 255, 0];
+        let indexed = encode((2, 1), ::png::ColorType::Indexed, Some(palette), &[1, 0]);
+        let texture = decode(&indexed).unwrap();
+        assert_eq!(rgba(&texture, 0, 0), (1.0, 1.0, 0.0, 1.0));
+        assert_eq!(rgba(&texture, 1, 0), (0.0, 0.0, 0.0, 1.0));
+    }
+
+    #[test]
+    fn rejects_what_isnt_a_png() {
+        assert!(decode(b"P3 1 1 255 0 0 0").is_err());
+        let rgb = encode((2, 2), ::png::ColorType::RGB, None, &[0; 12]);
+        assert!(decode(&rgb[..rgb.len() / 2]).is_err());
+    }
+}
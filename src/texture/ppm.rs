@@ -0,0 +1,157 @@
+use super::*;
+
+/// Decodes a Netpbm image: PPM (`P3` or `P6`) or PGM (`P2` or `P5`).
+pub fn decode(data: &[u8]) -> Result<Texture, Error> {
+    let mut header = Header { data, position: 0 };
+
+    let magic = header.token()?;
+    let (channels, binary) = match magic {
+        b"P2" => (1, false),
+        b"P3" => (3, false),
+        b"P5" => (1, true),
+        b"P6" => (3, true),
+        _ => return Err(format_err!("not a PPM or PGM file")),
+    };
+
+    let width = header.number()?;
+    let height = header.number()?;
+    let max_value = header.number()?;
+    if max_value == 0 || max_value > 65535 {
+        return Err(format_err!("invalid maximum value {}", max_value));
+    }
+
+    if width == 0 || height == 0 {
+        return Err(format_err!("image is {}x{} pixels", width, height));
+    }
+
+    let count = width
+        .checked_mul(height)
+        .and_then(|pixels| pixels.checked_mul(channels))
+        .ok_or(format_err!("image is too big"))?;
+    let samples: Vec<usize> = if binary {
+        // A single whitespace character separates the header from the data.
+        let start = header.position + 1;
+        let bytes_per_sample = if max_value < 256 { 1 } else { 2 };
+        let bytes = count
+            .checked_mul(bytes_per_sample)
+            .and_then(|size| data.get(start..start.checked_add(size)?))
+            .ok_or(format_err!("image data is cut short"))?;
+
+        if bytes_per_sample == 1 {
+            bytes.iter().map(|&b| b as usize).collect()
+        } else {
+            bytes
+                .chunks(2)
+                .map(|b| u16::from_be_bytes([b[0], b[1]]) as usize)
+                .collect()
+        }
+    } else {
+        (0..count)
+            .map(|_| header.number())
+            .collect::<Result<_, _>>()?
+    };
+
+    let value = |sample: usize| sample.min(max_value) as f32 / max_value as f32;
+    let pixels = samples
+        .chunks(channels)
+        .map(|pixel| match *pixel {
+            [gray] => Vec4::new(value(gray), value(gray), value(gray), 1.0),
+            [r, g, b] => Vec4::new(value(r), value(g), value(b), 1.0),
+            _ => unreachable!(),
+        })
+        .collect();
+
+    Ok(Texture::new(width, height, pixels))
+}
+
+struct Header<'a> {
+    data: &'a [u8],
+    position: usize,
+}
+
+impl<'a> Header<'a> {
+    /// Returns the next whitespace separated token, skipping comments.
+    fn token(&mut self) -> Result<&'a [u8], Error> {
+        loop {
+            match self.data.get(self.position) {
+                Some(b'#') => {
+                    while !matches!(self.data.get(self.position), Some(b'\n') | None) {
+                        self.position += 1;
+                    }
+                }
+                Some(c) if c.is_ascii_whitespace() => self.position += 1,
+                Some(_) => break,
+                None => return Err(format_err!("file is cut short")),
+            }
+        }
+
+        let start = self.position;
+        while matches!(self.data.get(self.position), Some(c) if !c.is_ascii_whitespace()) {
+            self.position += 1;
+        }
+        Ok(&self.data[start..self.position])
+    }
+
+    fn number(&mut self) -> Result<usize, Error> {
+        let token = self.token()?;
+        std::str::from_utf8(token)
+            .ok()
+            .and_then(|token| token.parse().ok())
+            .ok_or_else(|| format_err!("invalid number {:?}", String::from_utf8_lossy(token)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rgb(texture: &Texture, x: usize, y: usize) -> (f32, f32, f32) {
+        let pixel = texture.get_pixel(x, y);
+        (pixel.x, pixel.y, pixel.z)
+    }
+
+    #[test]
+    fn decodes_ascii_with_comments() {
+        let texture = decode(b"P3\n# a comment\n2 1\n255\n255 0 0  0 0 255\n").unwrap();
+        assert_eq!((texture.width, texture.height), (2, 1));
+        assert_eq!(rgb(&texture, 0, 0), (1.0, 0.0, 0.0));
+        assert_eq!(rgb(&texture, 1, 0), (0.0, 0.0, 1.0));
+    }
+
+    #[test]
+    fn decodes_binary_gray_and_16_bit() {
+        let texture = decode(b"P5 1 2 255\n\x00\xff").unwrap();
+        assert_eq!(rgb(&texture, 0, 0), (0.0, 0.0, 0.0));
+        assert_eq!(rgb(&texture, 0, 1), (1.0, 1.0, 1.0));
+
+        let texture = decode(b"P6 1 1 65535\n\xff\xff\x00\x00\x80\x00").unwrap();
+        let (r, g, b) = rgb(&texture, 0, 0);
+        assert_eq!((r, g), (1.0, 0.0));
+        assert!((b - 0.5).abs() < 0.001);
+    }
+
+    #[test]
+    fn rejects_bad_headers() {
+        assert!(decode(b"P7 1 1 255\n").is_err());
+        assert!(decode(b"P3 1 1 0\n0 0 0").is_err());
+        assert!(decode(b"P3 1 x 255\n").is_err());
+        assert!(decode(b"P3 1").is_err());
+    }
+
+    #[test]
+    fn rejects_empty_images() {
+        assert!(decode(b"P3 0 1 255\n").is_err());
+        assert!(decode(b"P6 1 0 255\n").is_err());
+    }
+
+    #[test]
+    fn rejects_sizes_that_dont_fit_the_data() {
+        assert!(decode(b"P6 2 1 255\n\x00\x00\x00").is_err());
+        assert!(decode(b"P3 1 1 255\n0 0").is_err());
+        // Overflows when multiplied out; must not panic or try to allocate it.
+        let huge = format!("P6 {} {} 255\n", usize::MAX / 2, 3);
+        assert!(decode(huge.as_bytes()).is_err());
+        let huge = format!("P6 {} {} 255\n", 1 << 20, 1 << 20);
+        assert!(decode(huge.as_bytes()).is_err());
+    }
+}
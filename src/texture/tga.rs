@@ -0,0 +1,189 @@
+use super::*;
+
+const HEADER_SIZE: usize = 18;
+
+/// Decodes a Truevision TGA image: true color or grayscale, raw or run-length encoded.
+pub fn decode(data: &[u8]) -> Result<Texture, Error> {
+    if data.len() < HEADER_SIZE {
+        return Err(format_err!("not a TGA file"));
+    }
+
+    let id_length = data[0] as usize;
+    let color_map_type = data[1];
+    let image_type = data[2];
+    let color_map_length = u16::from_le_bytes([data[5], data[6]]) as usize;
+    let color_map_depth = data[7] as usize;
+    let width = u16::from_le_bytes([data[12], data[13]]) as usize;
+    let height = u16::from_le_bytes([data[14], data[15]]) as usize;
+    let depth = data[16];
+    let descriptor = data[17];
+
+    let (rle, gray) = match image_type {
+        2 => (false, false),
+        3 => (false, true),
+        10 => (true, false),
+        11 => (true, true),
+        1 | 9 => return Err(format_err!("color mapped TGA images aren't supported")),
+        _ => return Err(format_err!("unsupported TGA image type {}", image_type)),
+    };
+
+    if width == 0 || height == 0 {
+        return Err(format_err!("image is {}x{} pixels", width, height));
+    }
+
+    let bytes_per_pixel = match (gray, depth) {
+        (true, 8) => 1,
+        (false, 24) => 3,
+        (false, 32) => 4,
+        _ => return Err(format_err!("unsupported TGA pixel depth {}", depth)),
+    };
+
+    // Skip the image ID, and the color map in case there's one we don't need.
+    let color_map_size = if color_map_type == 1 {
+        color_map_length * (color_map_depth + 7) / 8
+    } else {
+        0
+    };
+    let mut input = data
+        .get(HEADER_SIZE + id_length + color_map_size..)
+        .ok_or(format_err!("file is cut short"))?;
+
+    let count = width * height;
+    // Not allocated up front, since the header can claim any size it likes.
+    let mut raw = vec![];
+    if rle {
+        while raw.len() < count * bytes_per_pixel {
+            let (&packet, rest) = input
+                .split_first()
+                .ok_or(format_err!("image data is cut short"))?;
+            let length = (packet & 0x7f) as usize + 1;
+            // The high bit marks a run of a single repeated pixel.
+            let size = if packet & 0x80 != 0 {
+                bytes_per_pixel
+            } else {
+                length * bytes_per_pixel
+            };
+            let bytes = rest
+                .get(..size)
+                .ok_or(format_err!("image data is cut short"))?;
+
+            if packet & 0x80 != 0 {
+                for _ in 0..length {
+                    raw.extend_from_slice(bytes);
+                }
+            } else {
+                raw.extend_from_slice(bytes);
+            }
+            input = &rest[size..];
+        }
+        raw.truncate(count * bytes_per_pixel);
+    } else {
+        raw.extend_from_slice(
+            input
+                .get(..count * bytes_per_pixel)
+                .ok_or(format_err!("image data is cut short"))?,
+        );
+    }
+
+    // The low four bits of the descriptor count the alpha bits of a pixel. Plenty of
+    // writers store 32-bit pixels without alpha, leaving the fourth byte as padding.
+    let has_alpha = descriptor & 0x0f != 0;
+    let value = |b: u8| b as f32 / 255.0;
+    let decoded: Vec<Vec4> = raw
+        .chunks(bytes_per_pixel)
+        .map(|pixel| match *pixel {
+            [gray] => Vec4::new(value(gray), value(gray), value(gray), 1.0),
+            [b, g, r] => Vec4::new(value(r), value(g), value(b), 1.0),
+            [b, g, r, a] if has_alpha => Vec4::new(value(r), value(g), value(b), value(a)),
+            [b, g, r, _] => Vec4::new(value(r), value(g), value(b), 1.0),
+            _ => unreachable!(),
+        })
+        .collect();
+
+    // Rows are stored bottom to top unless bit 5 says otherwise, and pixels
+    // left to right unless bit 4 says otherwise.
+    let top_to_bottom = descriptor & 0x20 != 0;
+    let right_to_left = descriptor & 0x10 != 0;
+    let mut pixels = Vec::with_capacity(count);
+    for y in 0..height {
+        let row = if top_to_bottom { y } else { height - 1 - y };
+        for x in 0..width {
+            let column = if right_to_left { width - 1 - x } else { x };
+            pixels.push(decoded[row * width + column]);
+        }
+    }
+
+    Ok(Texture::new(width, height, pixels))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn header(image_type: u8, (width, height): (u16, u16), depth: u8, descriptor: u8) -> Vec<u8> {
+        let mut header = vec![0; HEADER_SIZE];
+        header[2] = image_type;
+        header[12..14].copy_from_slice(&width.to_le_bytes());
+        header[14..16].copy_from_slice(&height.to_le_bytes());
+        header[16] = depth;
+        header[17] = descriptor;
+        header
+    }
+
+    fn rgba(texture: &Texture, x: usize, y: usize) -> (f32, f32, f32, f32) {
+        let pixel = texture.get_pixel(x, y);
+        (pixel.x, pixel.y, pixel.z, pixel.w)
+    }
+
+    #[test]
+    fn decodes_raw_bottom_to_top() {
+        let mut data = header(2, (1, 2), 24, 0);
+        // Blue-green-red, bottom row first.
+        data.extend_from_slice(&[255, 0, 0, 0, 0, 255]);
+        let texture = decode(&data).unwrap();
+
+        assert_eq!(rgba(&texture, 0, 0), (1.0, 0.0, 0.0, 1.0));
+        assert_eq!(rgba(&texture, 0, 1), (0.0, 0.0, 1.0, 1.0));
+    }
+
+    #[test]
+    fn decodes_run_length_encoding_with_alpha() {
+        let mut data = header(10, (3, 1), 32, 0x28);
+        // A run of two pixels, then a single raw one.
+        data.extend_from_slice(&[0x81, 0, 255, 0, 0, 0x00, 0, 0, 255, 255]);
+        let texture = decode(&data).unwrap();
+
+        assert_eq!(rgba(&texture, 0, 0), (0.0, 1.0, 0.0, 0.0));
+        assert_eq!(rgba(&texture, 1, 0), (0.0, 1.0, 0.0, 0.0));
+        assert_eq!(rgba(&texture, 2, 0), (1.0, 0.0, 0.0, 1.0));
+    }
+
+    #[test]
+    fn pixels_without_alpha_bits_are_opaque() {
+        let mut data = header(2, (1, 1), 32, 0x20);
+        data.extend_from_slice(&[255, 0, 0, 0]);
+        let texture = decode(&data).unwrap();
+
+        assert_eq!(rgba(&texture, 0, 0), (0.0, 0.0, 1.0, 1.0));
+    }
+
+    #[test]
+    fn decodes_grayscale_right_to_left() {
+        let mut data = header(3, (2, 1), 8, 0x30);
+        data.extend_from_slice(&[0, 255]);
+        let texture = decode(&data).unwrap();
+
+        assert_eq!(rgba(&texture, 0, 0), (1.0, 1.0, 1.0, 1.0));
+        assert_eq!(rgba(&texture, 1, 0), (0.0, 0.0, 0.0, 1.0));
+    }
+
+    #[test]
+    fn rejects_empty_and_cut_short_images() {
+        assert!(decode(&header(2, (0, 1), 24, 0)).is_err());
+        assert!(decode(&header(2, (1, 0), 24, 0)).is_err());
+        assert!(decode(&header(2, (2, 2), 24, 0)).is_err());
+        assert!(decode(&header(10, (u16::MAX, u16::MAX), 32, 0)).is_err());
+        assert!(decode(&header(1, (1, 1), 8, 0)).is_err());
+        assert!(decode(&[0; 5]).is_err());
+    }
+}
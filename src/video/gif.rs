@@ -0,0 +1,523 @@
+use super::*;
+use std::collections::HashMap;
+use std::io::Write;
+
+const MAX_COLORS: usize = 256;
+const MAX_CODE: u16 = 4096;
+/// Sizes in GIF files are 16 bits.
+const MAX_SIZE: usize = u16::MAX as usize;
+
+/// Writes frames as a looping animated GIF. Every frame gets its own 256-color
+/// palette, picked with median cut.
+pub struct GifWriter<W: Write> {
+    out: W,
+    frame_rate: u32,
+    size: Option<(usize, usize)>,
+    frames: u64,
+}
+
+impl<W: Write> GifWriter<W> {
+    pub fn new(out: W, frame_rate: u32) -> std::io::Result<Self> {
+        if frame_rate == 0 {
+            return Err(invalid_input("a GIF needs a frame rate above 0"));
+        }
+        Ok(GifWriter {
+            out,
+            frame_rate,
+            size: None,
+            frames: 0,
+        })
+    }
+
+    pub fn into_inner(self) -> W {
+        self.out
+    }
+
+    fn write_header(&mut self, (width, height): (usize, usize)) -> std::io::Result<()> {
+        if width > MAX_SIZE || height > MAX_SIZE {
+            return Err(invalid_input(&format!(
+                "{}x{} is too big for a GIF, which can be at most {}x{}",
+                width, height, MAX_SIZE, MAX_SIZE
+            )));
+        }
+        self.out.write_all(b"GIF89a")?;
+        self.out.write_all(&(width as u16).to_le_bytes())?;
+        self.out.write_all(&(height as u16).to_le_bytes())?;
+        // No global color table, background color 0, square pixels.
+        self.out.write_all(&[0x00, 0, 0])?;
+
+        // NETSCAPE2.0 application extension; loop forever.
+        self.out
+            .write_all(b"\x21\xff\x0bNETSCAPE2.0\x03\x01\x00\x00\x00")
+    }
+
+    /// GIF delays are in hundredths of a second, so keep track of the total
+    /// time to avoid drifting at frame rates that don't divide 100.
+    fn next_delay(&mut self) -> u16 {
+        let hundredths =
+            |frames: u64| (frames * 100 + self.frame_rate as u64 / 2) / self.frame_rate as u64;
+        let delay = hundredths(self.frames + 1) - hundredths(self.frames);
+        self.frames += 1;
+        delay as u16
+    }
+}
+
+impl<W: Write> FrameSink for GifWriter<W> {
+    fn write_frame(&mut self, rt: &RenderTarget) -> std::io::Result<()> {
+        let size = match self.size {
+            Some(size) => size,
+            None => {
+                self.write_header(rt.dimensions())?;
+                self.size = Some(rt.dimensions());
+                rt.dimensions()
+            }
+        };
+        let (width, height) = size;
+
+        let mut pixels = Vec::with_capacity(width * height);
+        for y in 0..height {
+            for x in 0..width {
+                pixels.push(to_rgb8(&sample(rt, size, x, y)));
+            }
+        }
+
+        let palette = median_cut(&pixels);
+        let indices = map_to_palette(&pixels, &palette);
+
+        // Graphic control extension with the frame delay.
+        let delay = self.next_delay();
+        self.out.write_all(&[0x21, 0xf9, 0x04, 0x04])?;
+        self.out.write_all(&delay.to_le_bytes())?;
+        self.out.write_all(&[0, 0])?;
+
+        // Image descriptor covering the whole screen, with a local color table of 256 entries.
+        self.out.write_all(&[0x2c, 0, 0, 0, 0])?;
+        self.out.write_all(&(width as u16).to_le_bytes())?;
+        self.out.write_all(&(height as u16).to_le_bytes())?;
+        self.out.write_all(&[0x87])?;
+
+        for i in 0..MAX_COLORS {
+            self.out
+                .write_all(&palette.get(i).unwrap_or(&[0, 0, 0])[..])?;
+        }
+
+        self.out.write_all(&[8])?;
+        for block in lzw_encode(&indices).chunks(255) {
+            self.out.write_all(&[block.len() as u8])?;
+            self.out.write_all(block)?;
+        }
+        self.out.write_all(&[0])
+    }
+
+    fn finish(&mut self) -> std::io::Result<()> {
+        self.out.write_all(&[0x3b])?;
+        self.out.flush()
+    }
+}
+
+/// Picks up to 256 colors representing `pixels` by repeatedly splitting the box
+/// of colors with the widest channel range at its median.
+fn median_cut(pixels: &[[u8; 3]]) -> Vec<[u8; 3]> {
+    let mut boxes: Vec<Vec<[u8; 3]>> = vec![pixels.to_vec()];
+
+    while boxes.len() < MAX_COLORS {
+        let widest = boxes
+            .iter()
+            .enumerate()
+            .map(|(i, colors)| {
+                let (channel, range) = widest_channel(colors);
+                (i, channel, range)
+            })
+            .filter(|&(_, _, range)| range > 0)
+            .max_by_key(|&(_, _, range)| range);
+
+        let (i, channel, _) = match widest {
+            Some(widest) => widest,
+            // Every box holds a single color; no need to split further.
+            None => break,
+        };
+
+        let mut colors = boxes.swap_remove(i);
+        colors.sort_unstable_by_key(|color| color[channel]);
+        // Split at the median, moved to where the channel changes, so that the pixels of a
+        // color all end up on the same side. Otherwise splits are wasted on common colors.
+        let median = colors[colors.len() / 2][channel];
+        let split = match colors.partition_point(|color| color[channel] < median) {
+            0 => colors.partition_point(|color| color[channel] <= median),
+            split => split,
+        };
+        let upper = colors.split_off(split);
+        boxes.push(colors);
+        boxes.push(upper);
+    }
+
+    boxes
+        .iter()
+        .filter(|colors| !colors.is_empty())
+        .map(|colors| {
+            let mut sum = [0usize; 3];
+            for color in colors {
+                for channel in 0..3 {
+                    sum[channel] += color[channel] as usize;
+                }
+            }
+            let average = |channel: usize| (sum[channel] / colors.len()) as u8;
+            [average(0), average(1), average(2)]
+        })
+        .collect()
+}
+
+fn widest_channel(colors: &[[u8; 3]]) -> (usize, u8) {
+    (0..3)
+        .map(|channel| {
+            let min = colors.iter().map(|color| color[channel]).min().unwrap_or(0);
+            let max = colors.iter().map(|color| color[channel]).max().unwrap_or(0);
+            (channel, max - min)
+        })
+        .max_by_key(|&(_, range)| range)
+        .unwrap()
+}
+
+fn map_to_palette(pixels: &[[u8; 3]], palette: &[[u8; 3]]) -> Vec<u8> {
+    // Rendered frames have large areas of the same color, so don't search the palette for each of them.
+    let mut cache: HashMap<[u8; 3], u8> = HashMap::new();
+
+    pixels
+        .iter()
+        .map(|pixel| {
+            *cache.entry(*pixel).or_insert_with(|| {
+                let distance = |color: &[u8; 3]| -> i32 {
+                    (0..3)
+                        .map(|c| (color[c] as i32 - pixel[c] as i32).pow(2))
+                        .sum()
+                };
+
+                (0..palette.len())
+                    .min_by_key(|&i| distance(&palette[i]))
+                    .unwrap_or(0) as u8
+            })
+        })
+        .collect()
+}
+
+struct BitWriter {
+    bytes: Vec<u8>,
+    buffer: u32,
+    bits: u32,
+}
+
+impl BitWriter {
+    fn write(&mut self, code: u16, size: u32) {
+        self.buffer |= (code as u32) << self.bits;
+        self.bits += size;
+        while self.bits >= 8 {
+            self.bytes.push(self.buffer as u8);
+            self.buffer >>= 8;
+            self.bits -= 8;
+        }
+    }
+
+    fn finish(mut self) -> Vec<u8> {
+        if self.bits > 0 {
+            self.bytes.push(self.buffer as u8);
+        }
+        self.bytes
+    }
+}
+
+/// Variable code size LZW, as used for GIF image data, with 8-bit palette indices.
+fn lzw_encode(indices: &[u8]) -> Vec<u8> {
+    let clear: u16 = 256;
+    let end: u16 = 257;
+
+    let mut output = BitWriter {
+        bytes: vec![],
+        buffer: 0,
+        bits: 0,
+    };
+    let mut codes: HashMap<(u16, u8), u16> = HashMap::new();
+    let mut next_code = end + 1;
+    let mut size = 9;
+
+    output.write(clear, size);
+
+    let mut prefix = match indices.first() {
+        Some(&index) => index as u16,
+        None => {
+            output.write(end, size);
+            return output.finish();
+        }
+    };
+
+    for &index in &indices[1..] {
+        if let Some(&code) = codes.get(&(prefix, index)) {
+            prefix = code;
+            continue;
+        }
+
+        // The decoder only learns about a new code after reading the next one,
+        // so the code size grows one code later than the table does.
+        if next_code > (1 << size) && size < 12 {
+            size += 1;
+        }
+        output.write(prefix, size);
+
+        if next_code < MAX_CODE {
+            codes.insert((prefix, index), next_code);
+            next_code += 1;
+        } else {
+            output.write(clear, size);
+            codes.clear();
+            next_code = end + 1;
+            size = 9;
+        }
+
+        prefix = index as u16;
+    }
+
+    if next_code > (1 << size) && size < 12 {
+        size += 1;
+    }
+    output.write(prefix, size);
+    output.write(end, size);
+    output.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Bytes that repeat now and then, with `symbols` different values, made up the same way
+    /// every time.
+    fn noise(length: usize, symbols: u32) -> Vec<u8> {
+        let mut state = 12345u32;
+        (0..length)
+            .map(|_| {
+                state = state.wrapping_mul(1_103_515_245).wrapping_add(12345);
+                ((state >> 16) % symbols) as u8
+            })
+            .collect()
+    }
+
+    /// Decodes GIF image data the way GIF readers do, returning the indices along with the
+    /// widest code size used and how many times the table was cleared.
+    fn lzw_decode(data: &[u8]) -> (Vec<u8>, u32, usize) {
+        let mut position = 0;
+        let mut read = |size: u32| {
+            let mut code = 0usize;
+            for i in 0..size as usize {
+                let bit = position + i;
+                assert!(bit < data.len() * 8, "the data ends without an end code");
+                code |= ((data[bit / 8] >> (bit % 8)) as usize & 1) << i;
+            }
+            position += size as usize;
+            code
+        };
+        let fresh_table = || -> Vec<Vec<u8>> {
+            let mut table: Vec<Vec<u8>> = (0..=255u8).map(|index| vec![index]).collect();
+            table.extend(vec![vec![], vec![]]);
+            table
+        };
+
+        let mut table = fresh_table();
+        let (mut size, mut widest, mut clears) = (9, 9, 0);
+        let mut previous: Option<Vec<u8>> = None;
+        let mut indices = vec![];
+        loop {
+            let code = read(size);
+            if code == 256 {
+                table = fresh_table();
+                size = 9;
+                previous = None;
+                clears += 1;
+                continue;
+            }
+            if code == 257 {
+                return (indices, widest, clears);
+            }
+
+            let entry = if code < table.len() {
+                table[code].clone()
+            } else {
+                assert_eq!(code, table.len(), "code {} isn't in the table yet", code);
+                let mut entry = previous.clone().expect("a new code needs a previous one");
+                entry.push(entry[0]);
+                entry
+            };
+            indices.extend(&entry);
+            if let Some(mut previous) = previous.take() {
+                if table.len() < MAX_CODE as usize {
+                    previous.push(entry[0]);
+                    table.push(previous);
+                    if table.len() == 1 << size && size < 12 {
+                        size += 1;
+                        widest = widest.max(size);
+                    }
+                }
+            }
+            previous = Some(entry);
+        }
+    }
+
+    struct Frame {
+        delay: u16,
+        pixels: Vec<[u8; 3]>,
+        /// How many times the code table was started over.
+        clears: usize,
+    }
+
+    /// Reads back what `GifWriter` writes, checking the framing on the way.
+    fn decode(gif: &[u8]) -> ((usize, usize), Vec<Frame>) {
+        let u16_at = |at: usize| u16::from_le_bytes([gif[at], gif[at + 1]]);
+        assert_eq!(&gif[..6], b"GIF89a");
+        let size = (u16_at(6) as usize, u16_at(8) as usize);
+        assert_eq!(&gif[10..13], &[0, 0, 0]);
+        assert_eq!(&gif[13..32], b"\x21\xff\x0bNETSCAPE2.0\x03\x01\x00\x00\x00");
+
+        let mut frames = vec![];
+        let mut at = 32;
+        while gif[at] != 0x3b {
+            assert_eq!(&gif[at..at + 4], &[0x21, 0xf9, 0x04, 0x04]);
+            let delay = u16_at(at + 4);
+            assert_eq!(&gif[at + 6..at + 13], &[0, 0, 0x2c, 0, 0, 0, 0]);
+            assert_eq!((u16_at(at + 13) as usize, u16_at(at + 15) as usize), size);
+            assert_eq!(gif[at + 17], 0x87);
+            at += 18;
+
+            let palette: Vec<&[u8]> = gif[at..at + 3 * MAX_COLORS].chunks(3).collect();
+            at += 3 * MAX_COLORS;
+            assert_eq!(gif[at], 8);
+            at += 1;
+
+            let mut data = vec![];
+            loop {
+                let length = gif[at] as usize;
+                at += 1;
+                if length == 0 {
+                    break;
+                }
+                data.extend(&gif[at..at + length]);
+                at += length;
+            }
+            let (indices, _, clears) = lzw_decode(&data);
+            assert_eq!(indices.len(), size.0 * size.1);
+            let pixels = indices
+                .iter()
+                .map(|&index| {
+                    let color = palette[index as usize];
+                    [color[0], color[1], color[2]]
+                })
+                .collect();
+            frames.push(Frame {
+                delay,
+                pixels,
+                clears,
+            });
+        }
+        assert_eq!(at + 1, gif.len(), "there's more after the trailer");
+        (size, frames)
+    }
+
+    fn target(size: (usize, usize), pixels: &[[u8; 3]]) -> RenderTarget {
+        let mut rt = RenderTarget::new(size);
+        for (i, [r, g, b]) in pixels.iter().enumerate() {
+            let channel = |value: u8| value as f32 / 255.0;
+            let color = Vec4::new(channel(*r), channel(*g), channel(*b), 1.0);
+            rt.set_pixel(i % size.0, i / size.0, &color);
+        }
+        rt
+    }
+
+    #[test]
+    fn lzw_round_trips_short_data() {
+        for indices in [vec![], vec![7], vec![1; 6], noise(100, 3)] {
+            let (decoded, widest, clears) = lzw_decode(&lzw_encode(&indices));
+            assert_eq!(decoded, indices);
+            assert_eq!((widest, clears), (9, 1));
+        }
+    }
+
+    #[test]
+    fn lzw_widens_codes_and_starts_over_when_the_table_fills() {
+        // Just enough new strings to need 10-bit codes.
+        let indices = noise(300, 256);
+        let (decoded, widest, _) = lzw_decode(&lzw_encode(&indices));
+        assert_eq!(decoded, indices);
+        assert_eq!(widest, 10);
+
+        let indices = noise(20000, 256);
+        let (decoded, widest, clears) = lzw_decode(&lzw_encode(&indices));
+        assert_eq!(decoded, indices);
+        assert_eq!(widest, 12);
+        assert!(clears > 1, "the table was never cleared");
+    }
+
+    #[test]
+    fn frames_decode_to_what_was_drawn() {
+        // Noise over 200 colors fills the code table in a single frame, and there's few
+        // enough of them for the palette to hold them exactly.
+        let colors: Vec<[u8; 3]> = noise(600, 256)
+            .chunks(3)
+            .map(|c| [c[0], c[1], c[2]])
+            .collect();
+        let first: Vec<[u8; 3]> = noise(100 * 80, 200)
+            .iter()
+            .map(|&i| colors[i as usize])
+            .collect();
+        let second = vec![[255, 0, 0], [0, 0, 255]];
+
+        let mut writer = GifWriter::new(vec![], 30).unwrap();
+        writer.write_frame(&target((100, 80), &first)).unwrap();
+        // Frames of another size are scaled to the first one.
+        writer.write_frame(&target((2, 1), &second)).unwrap();
+        writer.write_frame(&target((100, 80), &first)).unwrap();
+        writer.finish().unwrap();
+
+        let (size, frames) = decode(&writer.into_inner());
+        assert_eq!(size, (100, 80));
+        // A 30th of a second is 3.33 hundredths, so the delays add up without drifting.
+        let delays: Vec<u16> = frames.iter().map(|frame| frame.delay).collect();
+        assert_eq!(delays, vec![3, 4, 3]);
+        assert!(frames[0].pixels == first);
+        assert!(frames[0].clears > 1, "the code table never filled up");
+        assert_eq!(frames[1].pixels[0], [255, 0, 0]);
+        assert_eq!(frames[1].pixels[99], [0, 0, 255]);
+        assert!(frames[2].pixels == first);
+    }
+
+    #[test]
+    fn median_cut_keeps_to_256_colors_close_to_the_originals() {
+        let pixels: Vec<[u8; 3]> = (0..16 * 16 * 4)
+            .map(|i| {
+                [
+                    (i % 16 * 17) as u8,
+                    (i / 16 % 16 * 17) as u8,
+                    (i / 256 * 85) as u8,
+                ]
+            })
+            .collect();
+        let palette = median_cut(&pixels);
+        assert_eq!(palette.len(), MAX_COLORS);
+
+        let indices = map_to_palette(&pixels, &palette);
+        for (pixel, &index) in pixels.iter().zip(&indices) {
+            let color = palette[index as usize];
+            for channel in 0..3 {
+                let error = (pixel[channel] as i32 - color[channel] as i32).abs();
+                assert!(error <= 45, "{:?} became {:?}", pixel, color);
+            }
+        }
+    }
+
+    #[test]
+    fn rejects_what_a_gif_cant_hold() {
+        assert!(GifWriter::new(vec![], 0).is_err());
+
+        let mut writer = GifWriter::new(vec![], 30).unwrap();
+        let error = writer
+            .write_frame(&RenderTarget::new((65536, 1)))
+            .unwrap_err();
+        assert_eq!(error.kind(), std::io::ErrorKind::InvalidInput);
+        assert!(writer.into_inner().is_empty());
+    }
+}
@@ -0,0 +1,35 @@
+use super::math::*;
+use super::render_target::RenderTarget;
+
+mod gif;
+mod y4m;
+
+pub use gif::GifWriter;
+pub use y4m::Y4mWriter;
+
+/// Something that turns a sequence of rendered frames into a video.
+pub trait FrameSink {
+    /// Appends a frame. The first frame decides the size of the video;
+    /// later frames of a different size are scaled to match.
+    fn write_frame(&mut self, rt: &RenderTarget) -> std::io::Result<()>;
+
+    /// Writes whatever the format needs after the last frame.
+    fn finish(&mut self) -> std::io::Result<()>;
+}
+
+/// Nearest-neighbor lookup of pixel (x, y) of a `width` by `height` image in `rt`.
+fn sample(rt: &RenderTarget, (width, height): (usize, usize), x: usize, y: usize) -> Vec4 {
+    rt.get_pixel(x * rt.width / width, y * rt.height / height)
+}
+
+fn invalid_input(message: &str) -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::InvalidInput, message)
+}
+
+fn to_rgb8(pixel: &Vec4) -> [u8; 3] {
+    [
+        (clamp((0.0, 1.0), pixel.x) * 255.0).round() as u8,
+        (clamp((0.0, 1.0), pixel.y) * 255.0).round() as u8,
+        (clamp((0.0, 1.0), pixel.z) * 255.0).round() as u8,
+    ]
+}
@@ -0,0 +1,104 @@
+use super::*;
+use std::io::Write;
+
+/// Writes frames as uncompressed YUV4MPEG2 (4:4:4, BT.601), which ffmpeg and most video tools read.
+pub struct Y4mWriter<W: Write> {
+    out: W,
+    frame_rate: u32,
+    size: Option<(usize, usize)>,
+    planes: Vec<u8>,
+}
+
+impl<W: Write> Y4mWriter<W> {
+    pub fn new(out: W, frame_rate: u32) -> std::io::Result<Self> {
+        if frame_rate == 0 {
+            return Err(invalid_input("a video needs a frame rate above 0"));
+        }
+        Ok(Y4mWriter {
+            out,
+            frame_rate,
+            size: None,
+            planes: vec![],
+        })
+    }
+
+    pub fn into_inner(self) -> W {
+        self.out
+    }
+}
+
+impl<W: Write> FrameSink for Y4mWriter<W> {
+    fn write_frame(&mut self, rt: &RenderTarget) -> std::io::Result<()> {
+        let (width, height) = match self.size {
+            Some(size) => size,
+            None => {
+                writeln!(
+                    self.out,
+                    "YUV4MPEG2 W{} H{} F{}:1 Ip A1:1 C444",
+                    rt.width, rt.height, self.frame_rate
+                )?;
+                self.size = Some(rt.dimensions());
+                rt.dimensions()
+            }
+        };
+
+        let count = width * height;
+        self.planes.clear();
+        self.planes.resize(count * 3, 0);
+
+        for y in 0..height {
+            for x in 0..width {
+                let [r, g, b] = to_rgb8(&sample(rt, (width, height), x, y));
+                let (r, g, b) = (r as f32 / 255.0, g as f32 / 255.0, b as f32 / 255.0);
+
+                // Studio-swing BT.601, which is what Y4M files are assumed to contain.
+                let i = y * width + x;
+                self.planes[i] = (16.0 + 65.481 * r + 128.553 * g + 24.966 * b).round() as u8;
+                self.planes[count + i] =
+                    (128.0 - 37.797 * r - 74.203 * g + 112.0 * b).round() as u8;
+                self.planes[count * 2 + i] =
+                    (128.0 + 112.0 * r - 93.786 * g - 18.214 * b).round() as u8;
+            }
+        }
+
+        self.out.write_all(b"FRAME\n")?;
+        self.out.write_all(&self.planes)
+    }
+
+    fn finish(&mut self) -> std::io::Result<()> {
+        self.out.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn writes_a_header_and_planes_per_frame() {
+        let mut writer = Y4mWriter::new(vec![], 25).unwrap();
+        let mut rt = RenderTarget::new((2, 1));
+        rt.set_pixel(0, 0, &Vec4::new(1.0, 0.0, 0.0, 1.0));
+        rt.set_pixel(1, 0, &Vec4::new(1.0, 1.0, 1.0, 1.0));
+        writer.write_frame(&rt).unwrap();
+
+        // Frames of another size are scaled to the first one.
+        let mut blue = RenderTarget::new((1, 1));
+        blue.set_pixel(0, 0, &Vec4::new(0.0, 0.0, 1.0, 1.0));
+        writer.write_frame(&blue).unwrap();
+        writer.finish().unwrap();
+
+        let mut expected = b"YUV4MPEG2 W2 H1 F25:1 Ip A1:1 C444\n".to_vec();
+        // Red and white: the Y plane, then U, then V.
+        expected.extend(b"FRAME\n");
+        expected.extend(&[81, 235, 90, 128, 240, 128]);
+        expected.extend(b"FRAME\n");
+        expected.extend(&[41, 41, 240, 240, 110, 110]);
+        assert_eq!(writer.into_inner(), expected);
+    }
+
+    #[test]
+    fn rejects_a_frame_rate_of_zero() {
+        assert!(Y4mWriter::new(vec![], 0).is_err());
+    }
+}
@@ -0,0 +1,108 @@
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+/// When a file was last modified and how big it was, or `None` if it can't be read.
+type Stamp = Option<(SystemTime, u64)>;
+
+fn stamp(path: &Path) -> Stamp {
+    let metadata = std::fs::metadata(path).ok()?;
+    Some((metadata.modified().ok()?, metadata.len()))
+}
+
+/// Notices when files change by checking their modification times, which is cheap enough
+/// to do every frame.
+pub struct FileWatcher {
+    files: Vec<(PathBuf, Stamp)>,
+    /// What the last poll saw, if it differed from what the files were.
+    pending: Option<Vec<Stamp>>,
+}
+
+impl FileWatcher {
+    pub fn new(mut paths: Vec<PathBuf>) -> Self {
+        paths.sort();
+        paths.dedup();
+        FileWatcher {
+            files: paths
+                .into_iter()
+                .map(|path| {
+                    let stamp = stamp(&path);
+                    (path, stamp)
+                })
+                .collect(),
+            pending: None,
+        }
+    }
+
+    pub fn files(&self) -> impl Iterator<Item = &Path> {
+        self.files.iter().map(|(path, _)| path.as_path())
+    }
+
+    /// Whether any of the files changed since the last time this returned true. A change
+    /// only counts once two polls in a row agree on it, so that files still being written,
+    /// or saved one after the other, don't get picked up halfway.
+    pub fn poll(&mut self) -> bool {
+        let stamps: Vec<Stamp> = self.files.iter().map(|(path, _)| stamp(path)).collect();
+        if self
+            .files
+            .iter()
+            .zip(&stamps)
+            .all(|((_, old), new)| old == new)
+        {
+            self.pending = None;
+            return false;
+        }
+        if self.pending.as_ref() != Some(&stamps) {
+            self.pending = Some(stamps);
+            return false;
+        }
+
+        for ((_, old), new) in self.files.iter_mut().zip(stamps) {
+            *old = new;
+        }
+        self.pending = None;
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::temp_dir;
+
+    #[test]
+    fn changes_count_once_two_polls_agree() {
+        let path = temp_dir("watch-changes").join("file");
+        std::fs::write(&path, "a").unwrap();
+        let mut watcher = FileWatcher::new(vec![path.clone(), path.clone()]);
+        assert_eq!(watcher.files().count(), 1);
+        assert!(!watcher.poll());
+
+        // Sizes differ, so the change shows even if the modification time doesn't.
+        std::fs::write(&path, "ab").unwrap();
+        assert!(!watcher.poll());
+        assert!(watcher.poll());
+        assert!(!watcher.poll());
+
+        // A file that's still being written only counts once it settles.
+        std::fs::write(&path, "abc").unwrap();
+        assert!(!watcher.poll());
+        std::fs::write(&path, "abcd").unwrap();
+        assert!(!watcher.poll());
+        assert!(watcher.poll());
+    }
+
+    #[test]
+    fn files_appearing_and_disappearing_are_changes() {
+        let path = temp_dir("watch-missing").join("file");
+        let mut watcher = FileWatcher::new(vec![path.clone()]);
+        assert!(!watcher.poll());
+
+        std::fs::write(&path, "a").unwrap();
+        assert!(!watcher.poll());
+        assert!(watcher.poll());
+
+        std::fs::remove_file(&path).unwrap();
+        assert!(!watcher.poll());
+        assert!(watcher.poll());
+    }
+}